@@ -108,6 +108,79 @@ impl Default for ItemData {
     }
 }
 
+/// Most items an inventory can hold.
+pub const MAX_INV_ITEMS: usize = 30;
+
+/// Most meseta a character can carry.
+pub const MAX_MESETA: u32 = 999999;
+
+/// Most items a bank can hold.
+pub const MAX_BANK_ITEMS: usize = 200;
+
+/// Most meseta a bank can hold.
+pub const MAX_BANK_MESETA: u32 = 999999;
+
+/// Set in an inventory item's flags while it is equipped.
+pub const ITEM_EQUIPPED: u32 = 0x08;
+
+/// Set in a weapon's special byte until a tekker identifies it.
+pub const WEAPON_UNIDENTIFIED: u8 = 0x80;
+
+impl ItemData {
+    /// A meseta item, as it lies on the floor.
+    pub fn meseta(amount: u32, item_id: u32) -> ItemData {
+        let mut i = ItemData::default();
+        i.data[0] = 4;
+        i.item_id = item_id;
+        i.data2 = vec![amount as u8, (amount >> 8) as u8, (amount >> 16) as u8, (amount >> 24) as u8];
+        i
+    }
+
+    pub fn is_meseta(&self) -> bool {
+        self.data[0] == 4
+    }
+
+    /// The amount of meseta in a meseta item.
+    pub fn meseta_amount(&self) -> u32 {
+        (self.data2[0] as u32) | (self.data2[1] as u32) << 8 | (self.data2[2] as u32) << 16 | (self.data2[3] as u32) << 24
+    }
+
+    /// The most of this item a single inventory slot holds. Only tools other
+    /// than technique disks stack.
+    pub fn max_stack(&self) -> u32 {
+        match (self.data[0], self.data[1]) {
+            (3, 0x02) => 1,
+            (3, 0x10) => 99,
+            (3, _) => 10,
+            _ => 1
+        }
+    }
+
+    pub fn is_stackable(&self) -> bool {
+        self.max_stack() > 1
+    }
+
+    /// The number of items in this stack.
+    pub fn stack_count(&self) -> u32 {
+        if self.is_stackable() && self.data[5] > 0 {
+            self.data[5] as u32
+        } else {
+            1
+        }
+    }
+
+    pub fn set_stack_count(&mut self, count: u32) {
+        if self.is_stackable() {
+            self.data[5] = count as u8;
+        }
+    }
+
+    /// Whether the two items are the same kind of stackable item.
+    pub fn stacks_with(&self, other: &ItemData) -> bool {
+        self.is_stackable() && self.data[0..3] == other.data[0..3]
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Inventory {
     pub hp_mats: u8,
@@ -153,15 +226,111 @@ impl Default for Inventory {
     }
 }
 
+impl Inventory {
+    /// Find the item with the given ID.
+    pub fn find(&self, item_id: u32) -> Option<&InvItem> {
+        self.items.iter().find(|i| i.data.item_id == item_id)
+    }
+
+    /// Take `amount` of the item with the given ID out of the inventory. An
+    /// amount of 0 takes the whole item. Part of a stack is split off with
+    /// `new_id` as its ID; a whole item keeps its own. Yields None if there
+    /// isn't that much of the item.
+    pub fn take(&mut self, item_id: u32, amount: u32, new_id: u32) -> Option<ItemData> {
+        let pos = match self.items.iter().position(|i| i.data.item_id == item_id) {
+            Some(p) => p,
+            None => return None
+        };
+        let count = self.items[pos].data.stack_count();
+        if amount == 0 || amount == count {
+            return Some(self.items.remove(pos).data)
+        }
+        if amount > count {
+            return None
+        }
+        let mut split = self.items[pos].data.clone();
+        split.item_id = new_id;
+        split.set_stack_count(amount);
+        self.items[pos].data.set_stack_count(count - amount);
+        Some(split)
+    }
+
+    /// Add an item, stacking it onto one of the same kind if there is one.
+    /// Yields false, leaving the inventory as it was, if the item doesn't fit.
+    pub fn add(&mut self, item: ItemData) -> bool {
+        if let Some(i) = self.items.iter_mut().find(|i| i.data.stacks_with(&item)) {
+            let count = i.data.stack_count() + item.stack_count();
+            if count > i.data.max_stack() {
+                return false
+            }
+            i.data.set_stack_count(count);
+            return true
+        }
+        if self.items.len() >= MAX_INV_ITEMS || item.stack_count() > item.max_stack() {
+            return false
+        }
+        self.items.push(InvItem {
+            exists: 1,
+            tech: 0,
+            flags: 0,
+            data: item
+        });
+        true
+    }
+
+    /// Mark the item with the given ID as equipped or not. Yields false if
+    /// there's no such item.
+    pub fn set_equipped(&mut self, item_id: u32, equipped: bool) -> bool {
+        match self.items.iter_mut().find(|i| i.data.item_id == item_id) {
+            Some(i) => {
+                if equipped {
+                    i.flags |= ITEM_EQUIPPED;
+                } else {
+                    i.flags &= !ITEM_EQUIPPED;
+                }
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Put the items in the order of the given IDs. IDs of 0xFFFFFFFF are
+    /// skipped. Yields false, leaving the order as it was, unless the IDs name
+    /// every item exactly once.
+    pub fn sort(&mut self, item_ids: &[u32]) -> bool {
+        let ids: Vec<u32> = item_ids.iter().cloned().filter(|&id| id != 0xFFFFFFFF).collect();
+        if ids.len() != self.items.len() {
+            return false
+        }
+        if !self.items.iter().all(|i| ids.contains(&i.data.item_id)) {
+            return false
+        }
+        let mut sorted = Vec::with_capacity(self.items.len());
+        for id in ids {
+            let p = self.items.iter().position(|i| i.data.item_id == id).unwrap();
+            sorted.push(self.items.remove(p));
+        }
+        self.items = sorted;
+        true
+    }
+
+    /// Whether the two inventories hold the same items in the same order.
+    /// Equipped flags and materials aren't compared.
+    pub fn same_items(&self, other: &Inventory) -> bool {
+        self.items.len() == other.items.len() && self.items.iter().zip(other.items.iter()).all(|(a, b)| {
+            a.data.item_id == b.data.item_id && a.data.data == b.data.data && a.data.data2 == b.data.data2
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ItemBank {
-    pub item_count: u32,
     pub meseta: u32,
     pub items: Vec<BankItem>
 }
 impl Serial for ItemBank {
     fn serialize(&self, dst: &mut Write) -> io::Result<()> {
-        try!(self.item_count.serialize(dst));
+        try!((self.items.len() as u32).serialize(dst));
         try!(self.meseta.serialize(dst));
         try!(write_array(&self.items, 200, dst));
         Ok(())
@@ -170,9 +339,10 @@ impl Serial for ItemBank {
     fn deserialize(src: &mut Read) -> io::Result<Self> {
         let item_count = try!(u32::deserialize(src));
         let meseta = try!(u32::deserialize(src));
-        let items = try!(read_array(200, src));
+        let mut items: Vec<BankItem> = try!(read_array(200, src));
+        // Only the first item_count slots hold anything.
+        items.truncate(item_count as usize);
         Ok(ItemBank {
-            item_count: item_count,
             meseta: meseta,
             items: items
         })
@@ -181,10 +351,67 @@ impl Serial for ItemBank {
 impl Default for ItemBank {
     fn default() -> Self {
         ItemBank {
-            item_count: 0,
             meseta: 0,
-            items: vec![Default::default(); 200]
+            items: Vec::new()
+        }
+    }
+}
+
+impl ItemBank {
+    pub fn find(&self, item_id: u32) -> Option<&BankItem> {
+        self.items.iter().find(|i| i.data.item_id == item_id)
+    }
+
+    /// Give the items IDs counting up from `base`.
+    pub fn assign_ids(&mut self, base: u32) {
+        for (i, item) in self.items.iter_mut().enumerate() {
+            item.data.item_id = base + i as u32;
+        }
+    }
+
+    /// Take `amount` of the item with the given ID out of the bank, as
+    /// `Inventory::take` does.
+    pub fn take(&mut self, item_id: u32, amount: u32, new_id: u32) -> Option<ItemData> {
+        let pos = match self.items.iter().position(|i| i.data.item_id == item_id) {
+            Some(p) => p,
+            None => return None
+        };
+        let count = self.items[pos].data.stack_count();
+        if amount == 0 || amount == count {
+            return Some(self.items.remove(pos).data)
+        }
+        if amount > count {
+            return None
+        }
+        let mut split = self.items[pos].data.clone();
+        split.item_id = new_id;
+        split.set_stack_count(amount);
+        self.items[pos].data.set_stack_count(count - amount);
+        self.items[pos].amount = (count - amount) as u16;
+        Some(split)
+    }
+
+    /// Deposit an item, stacking it onto one of the same kind if there is one.
+    /// Yields false, leaving the bank as it was, if the item doesn't fit.
+    pub fn add(&mut self, item: ItemData) -> bool {
+        if let Some(i) = self.items.iter_mut().find(|i| i.data.stacks_with(&item)) {
+            let count = i.data.stack_count() + item.stack_count();
+            if count > i.data.max_stack() {
+                return false
+            }
+            i.data.set_stack_count(count);
+            i.amount = count as u16;
+            return true
+        }
+        if self.items.len() >= MAX_BANK_ITEMS || item.stack_count() > item.max_stack() {
+            return false
         }
+        self.items.push(BankItem {
+            amount: item.stack_count() as u16,
+            flags: 1,
+            data: item
+        });
+        true
     }
 }
 
@@ -230,6 +457,23 @@ impl Serial for CharStats {
     }
 }
 
+/// Strip the language tag ("\tE" or "\tJ") that BB clients put at the start
+/// of character names.
+pub fn strip_name_tag(name: &str) -> &str {
+    let mut chars = name.chars();
+    if chars.next() == Some('\t') {
+        chars.next();
+        chars.as_str()
+    } else {
+        name
+    }
+}
+
+/// The form character names are compared in: no language tag, lowercase.
+pub fn name_key(name: &str) -> String {
+    strip_name_tag(name).to_lowercase()
+}
+
 #[derive(Clone, Debug)]
 pub struct BbChar {
     pub stats: CharStats,
@@ -865,6 +1109,82 @@ mod test {
         assert_eq!(cursor.position(), 4 + 30*28);
     }
 
+    fn monomates(count: u8, item_id: u32) -> ItemData {
+        let mut i = ItemData::default();
+        i.data[0] = 3;
+        i.data[5] = count;
+        i.item_id = item_id;
+        i
+    }
+
+    #[test]
+    fn test_inventory_take_splits_stacks() {
+        let mut inv = Inventory::default();
+        assert!(inv.add(monomates(5, 1)));
+        let split = inv.take(1, 2, 9).unwrap();
+        assert_eq!(split.item_id, 9);
+        assert_eq!(split.stack_count(), 2);
+        assert_eq!(inv.find(1).unwrap().data.stack_count(), 3);
+        assert!(inv.take(1, 4, 10).is_none());
+        assert_eq!(inv.take(1, 3, 10).unwrap().item_id, 1);
+        assert!(inv.items.is_empty());
+    }
+
+    #[test]
+    fn test_inventory_add_stacks() {
+        let mut inv = Inventory::default();
+        assert!(inv.add(monomates(9, 1)));
+        assert!(inv.add(monomates(1, 2)));
+        assert_eq!(inv.items.len(), 1);
+        assert_eq!(inv.find(1).unwrap().data.stack_count(), 10);
+        assert!(!inv.add(monomates(1, 3)));
+        let mut dimates = monomates(11, 4);
+        dimates.data[2] = 1;
+        assert!(!inv.add(dimates));
+        assert_eq!(inv.items.len(), 1);
+        for i in 0..MAX_INV_ITEMS as u32 - 1 {
+            let mut saber = ItemData::default();
+            saber.data[0] = 0;
+            saber.data[1] = 1;
+            saber.item_id = 100 + i;
+            assert!(inv.add(saber));
+        }
+        assert!(!inv.add(ItemData::default()));
+    }
+
+    #[test]
+    fn test_bank_keeps_only_held_items() {
+        let mut bank = ItemBank::default();
+        assert!(bank.add(monomates(4, 1)));
+        assert!(bank.add(monomates(3, 2)));
+        assert_eq!(bank.items.len(), 1);
+        assert_eq!(bank.items[0].amount, 7);
+        assert_eq!(bank.take(1, 2, 9).unwrap().stack_count(), 2);
+        assert_eq!(bank.items[0].amount, 5);
+
+        let mut cursor = Cursor::new(Vec::new());
+        bank.serialize(&mut cursor).unwrap();
+        cursor.set_position(0);
+        let read = ItemBank::deserialize(&mut cursor).unwrap();
+        assert_eq!(read.items.len(), 1);
+        assert_eq!(read.find(1).unwrap().data.stack_count(), 5);
+    }
+
+    #[test]
+    fn test_inventory_sort() {
+        let mut inv = Inventory::default();
+        let mut saber = ItemData::default();
+        saber.data[1] = 1;
+        saber.item_id = 2;
+        inv.add(monomates(1, 1));
+        inv.add(saber);
+        assert!(!inv.sort(&[2, 3, 0xFFFFFFFF]));
+        assert_eq!(inv.items[0].data.item_id, 1);
+        assert!(inv.sort(&[2, 0xFFFFFFFF, 1]));
+        assert_eq!(inv.items[0].data.item_id, 2);
+        assert_eq!(inv.items[1].data.item_id, 1);
+    }
+
     #[test]
     fn test_bank_size() {
         let mut cursor = Cursor::new(Vec::new());
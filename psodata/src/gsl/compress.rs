@@ -0,0 +1,116 @@
+//! Archive routine for GSL format buffers.
+
+use std::io::Write;
+use std::io;
+use std::cmp::max;
+
+use byteorder::{LittleEndian as LE, BigEndian as BE, ByteOrder, WriteBytesExt};
+
+use super::GslFile;
+
+/// Files start on boundaries of this many bytes, and offsets count them.
+const BLOCK_SIZE: usize = 2048;
+const HEADER_SIZE: usize = 48;
+const NAME_SIZE: usize = 32;
+/// Archives from the games leave room for this many headers, whatever
+/// they hold.
+const MIN_HEADERS: usize = 256;
+
+fn blocks(size: usize) -> usize {
+    (size + BLOCK_SIZE - 1) / BLOCK_SIZE
+}
+
+fn invalid<T>(msg: String) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+fn compress<B: ByteOrder, W: Write>(files: &[GslFile], mut dst: W) -> io::Result<()> {
+    if files.is_empty() {
+        return invalid("GSL archive has no files".to_string())
+    }
+
+    // The header table ends with an empty name, and the files follow it from
+    // the next block.
+    let mut offset = blocks(max(files.len() + 1, MIN_HEADERS) * HEADER_SIZE);
+    let mut headers = Vec::with_capacity(offset * BLOCK_SIZE);
+    for f in files.iter() {
+        if f.name.is_empty() || f.name.len() > NAME_SIZE || !f.name.is_ascii() || f.name.contains('\0') {
+            return invalid(format!("\"{}\" can't be a GSL file name", f.name))
+        }
+        if offset > u32::max_value() as usize || f.data.len() > u32::max_value() as usize {
+            return invalid("GSL archive is too large".to_string())
+        }
+        headers.extend_from_slice(f.name.as_bytes());
+        headers.extend_from_slice(&vec![0; NAME_SIZE - f.name.len()]);
+        try!(headers.write_u32::<B>(offset as u32));
+        try!(headers.write_u32::<B>(f.data.len() as u32));
+        headers.extend_from_slice(&[0; 8]);
+        offset += blocks(f.data.len());
+    }
+    let len = headers.len();
+    headers.extend_from_slice(&vec![0; blocks(max(len + HEADER_SIZE, MIN_HEADERS * HEADER_SIZE)) * BLOCK_SIZE - len]);
+    try!(dst.write_all(&headers));
+
+    for f in files.iter() {
+        try!(dst.write_all(&f.data));
+        try!(dst.write_all(&vec![0; blocks(f.data.len()) * BLOCK_SIZE - f.data.len()]));
+    }
+    Ok(())
+}
+
+/// Archives the files into a stream. Use for Little Endian archives (Blue
+/// Burst).
+pub fn compress_le<W: Write>(files: &[GslFile], dst: W) -> io::Result<()> {
+    compress::<LE, W>(files, dst)
+}
+
+/// Archives the files into a stream. Use for Big Endian archives (GameCube).
+pub fn compress_be<W: Write>(files: &[GslFile], dst: W) -> io::Result<()> {
+    compress::<BE, W>(files, dst)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use super::super::*;
+
+    fn files() -> Vec<GslFile> {
+        vec![
+            GslFile { name: "ItemPTc.rel".to_string(), data: (0..5000).map(|i| i as u8).collect() },
+            GslFile { name: "empty".to_string(), data: Vec::new() },
+            GslFile { name: "ItemPTu.rel".to_string(), data: vec![0xAA; BLOCK_SIZE] }
+        ]
+    }
+
+    #[test]
+    fn test_gsl_round_trip() {
+        let mut le = Vec::new();
+        compress_le(&files(), &mut le).unwrap();
+        // Headers take six blocks, then 3 + 0 + 1 blocks of files.
+        assert_eq!(le.len(), 10 * BLOCK_SIZE);
+        assert_eq!(decompress_le(Cursor::new(&le[..])).unwrap(), files());
+        assert_eq!(decompress_guess(Cursor::new(&le[..])).unwrap(), files());
+
+        let mut be = Vec::new();
+        compress_be(&files(), &mut be).unwrap();
+        assert_eq!(decompress_be(Cursor::new(&be[..])).unwrap(), files());
+        assert_eq!(decompress_guess(Cursor::new(&be[..])).unwrap(), files());
+    }
+
+    #[test]
+    fn test_gsl_edit() {
+        let mut f = files();
+        assert_eq!(replace_file(&mut f, "empty", vec![1, 2, 3]), Some(Vec::new()));
+        assert_eq!(replace_file(&mut f, "ItemRTc.rel", vec![4]), None);
+        assert_eq!(f.len(), 4);
+        assert_eq!(find_file(&f, "empty").unwrap().data, vec![1, 2, 3]);
+        assert_eq!(remove_file(&mut f, "ItemPTu.rel").map(|r| r.data.len()), Some(BLOCK_SIZE));
+        assert!(find_file(&f, "ItemPTu.rel").is_none());
+
+        f.push(GslFile { name: (0..33).map(|_| 'a').collect(), data: Vec::new() });
+        assert!(compress_le(&f, Vec::new()).is_err());
+        assert!(compress_le(&[], Vec::new()).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 //! GameCube archive and compression format.
 
-//pub mod compress;
+pub mod compress;
 pub mod decompress;
 
 #[derive(Clone, Debug)]
@@ -10,12 +10,41 @@ pub struct GslHeader {
     pub size: u32
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct GslFile {
     pub name: String,
     pub data: Vec<u8>
 }
 
+pub use self::compress::compress_le;
+pub use self::compress::compress_be;
 pub use self::decompress::decompress_le;
 pub use self::decompress::decompress_be;
 pub use self::decompress::decompress_guess;
+
+/// Finds a file in an archive by name.
+pub fn find_file<'a>(files: &'a [GslFile], name: &str) -> Option<&'a GslFile> {
+    files.iter().find(|f| f.name == name)
+}
+
+/// Replaces the data of the named file, returning what it was, or adds the
+/// file to the end of the archive if it isn't there.
+pub fn replace_file(files: &mut Vec<GslFile>, name: &str, data: Vec<u8>) -> Option<Vec<u8>> {
+    match files.iter_mut().find(|f| f.name == name) {
+        Some(f) => return Some(::std::mem::replace(&mut f.data, data)),
+        None => ()
+    }
+    files.push(GslFile {
+        name: name.to_string(),
+        data: data
+    });
+    None
+}
+
+/// Removes the named file from an archive.
+pub fn remove_file(files: &mut Vec<GslFile>, name: &str) -> Option<GslFile> {
+    match files.iter().position(|f| f.name == name) {
+        Some(i) => Some(files.remove(i)),
+        None => None
+    }
+}
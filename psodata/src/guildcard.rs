@@ -0,0 +1,227 @@
+//! The Blue Burst guild card file, sent to the client from the character
+//! server and stored per account.
+
+use psoserial::Serial;
+use psoserial::util::*;
+
+use std::io;
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian as LE, ReadBytesExt, WriteBytesExt};
+
+/// Size of the guild card file the client expects.
+pub const GUILDCARD_FILE_SIZE: usize = 54672;
+
+/// Maximum number of guild cards in the file.
+pub const MAX_GUILDCARDS: usize = 105;
+
+/// Maximum number of blocked senders in the file.
+pub const MAX_BLOCKED: usize = 28;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BbGuildCard {
+    // uint32_t guildcard;
+    // uint16_t name[24];
+    // uint16_t team_name[16];
+    // uint16_t desc[88];
+    // uint8_t one;
+    // uint8_t language;
+    // uint8_t section;
+    // uint8_t char_class;
+    pub guildcard: u32,
+    pub name: String,
+    pub team_name: String,
+    pub desc: String,
+    pub one: u8,
+    pub lang: u8,
+    pub section: u8,
+    pub class: u8
+}
+impl Serial for BbGuildCard {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(dst.write_u32::<LE>(self.guildcard));
+        try!(write_utf16_len(&self.name, 48, dst));
+        try!(write_utf16_len(&self.team_name, 32, dst));
+        try!(write_utf16_len(&self.desc, 176, dst));
+        try!(self.one.serialize(dst));
+        try!(self.lang.serialize(dst));
+        try!(self.section.serialize(dst));
+        try!(self.class.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let guildcard = try!(src.read_u32::<LE>());
+        let name = try!(read_utf16_len(48, src));
+        let team_name = try!(read_utf16_len(32, src));
+        let desc = try!(read_utf16_len(176, src));
+        let one = try!(src.read_u8());
+        let lang = try!(src.read_u8());
+        let section = try!(src.read_u8());
+        let class = try!(src.read_u8());
+        Ok(BbGuildCard {
+            guildcard: guildcard,
+            name: name,
+            team_name: team_name,
+            desc: desc,
+            one: one,
+            lang: lang,
+            section: section,
+            class: class
+        })
+    }
+}
+
+impl Default for BbGuildCard {
+    fn default() -> BbGuildCard {
+        BbGuildCard {
+            guildcard: 0,
+            name: String::new(),
+            team_name: String::new(),
+            desc: String::new(),
+            one: 0,
+            lang: 0,
+            section: 0,
+            class: 0
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BbGuildCardEntry {
+    // bb_guildcard_t data;
+    // uint16_t comment[88];
+    // uint8_t unk[4];
+    pub data: BbGuildCard,
+    pub comment: String
+}
+impl Serial for BbGuildCardEntry {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.data.serialize(dst));
+        try!(write_utf16_len(&self.comment, 176, dst));
+        try!(dst.write_all(&[0; 4]));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let data = try!(BbGuildCard::deserialize(src));
+        let comment = try!(read_utf16_len(176, src));
+        try!(read_array::<u8>(4, src));
+        Ok(BbGuildCardEntry {
+            data: data,
+            comment: comment
+        })
+    }
+}
+
+/// The guild card file. Empty slots (guild card number 0) are not kept in
+/// `blocked` or `entries`, and are filled back in on serialization.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BbGuildCardFile {
+    // uint8_t unk1[0x114];
+    // bb_guildcard_t blocked[28];
+    // uint8_t unk2[0x180];
+    // bb_gc_entry_t entries[105];
+    pub unk1: Vec<u8>,
+    pub blocked: Vec<BbGuildCard>,
+    pub unk2: Vec<u8>,
+    pub entries: Vec<BbGuildCardEntry>
+}
+impl Serial for BbGuildCardFile {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(write_array(&self.unk1, 0x114, dst));
+        try!(write_array(&self.blocked, MAX_BLOCKED as u32, dst));
+        try!(write_array(&self.unk2, 0x180, dst));
+        try!(write_array(&self.entries, MAX_GUILDCARDS as u32, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let unk1 = try!(read_array(0x114, src));
+        let blocked: Vec<BbGuildCard> = try!(read_array(MAX_BLOCKED as u32, src));
+        let unk2 = try!(read_array(0x180, src));
+        let entries: Vec<BbGuildCardEntry> = try!(read_array(MAX_GUILDCARDS as u32, src));
+        Ok(BbGuildCardFile {
+            unk1: unk1,
+            blocked: blocked.into_iter().filter(|c| c.guildcard != 0).collect(),
+            unk2: unk2,
+            entries: entries.into_iter().filter(|e| e.data.guildcard != 0).collect()
+        })
+    }
+}
+
+impl Default for BbGuildCardFile {
+    fn default() -> BbGuildCardFile {
+        BbGuildCardFile {
+            unk1: vec![0; 0x114],
+            blocked: Vec::new(),
+            unk2: vec![0; 0x180],
+            entries: Vec::new()
+        }
+    }
+}
+
+impl BbGuildCardFile {
+    /// Add a guild card, or update it if the file already has one with the
+    /// same number. Returns false if the file is full.
+    pub fn add(&mut self, card: BbGuildCard) -> bool {
+        if let Some(e) = self.entries.iter_mut().find(|e| e.data.guildcard == card.guildcard) {
+            e.data = card;
+            return true
+        }
+        if self.entries.len() >= MAX_GUILDCARDS {
+            return false
+        }
+        self.entries.push(BbGuildCardEntry {
+            data: card,
+            comment: String::new()
+        });
+        true
+    }
+
+    /// Remove a guild card by number. Returns false if it wasn't in the file.
+    pub fn remove(&mut self, guildcard: u32) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.data.guildcard != guildcard);
+        self.entries.len() != len
+    }
+
+    /// Serialize the file to the exact bytes sent to the client.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(GUILDCARD_FILE_SIZE);
+        self.serialize(&mut buf).unwrap();
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use psoserial::Serial;
+    use super::*;
+
+    #[test]
+    fn test_guildcard_file_size() {
+        let f = BbGuildCardFile::default();
+        assert_eq!(f.to_bytes().len(), GUILDCARD_FILE_SIZE);
+    }
+
+    #[test]
+    fn test_guildcard_file_round_trip() {
+        let mut f = BbGuildCardFile::default();
+        assert!(f.add(BbGuildCard {
+            guildcard: 42000001,
+            name: "\tEAlice".to_string(),
+            team_name: "".to_string(),
+            desc: "Hello".to_string(),
+            one: 1,
+            lang: 1,
+            section: 3,
+            class: 2
+        }));
+        let bytes = f.to_bytes();
+        assert_eq!(bytes.len(), GUILDCARD_FILE_SIZE);
+        let g = BbGuildCardFile::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(f, g);
+    }
+}
@@ -0,0 +1,72 @@
+//! Mag evolution data from ItemMagEdit.prs.
+//!
+//! Like ItemPMT, the decompressed file is a set of tables found through a
+//! table of offsets pointed at by the first word of its last 16 bytes. Most of
+//! the tables only matter to how the client draws mags; the server only needs
+//! to know how far each mag type has evolved.
+
+use std::io::{Read, Cursor};
+use std::io;
+
+use psoserial::Serial;
+
+/// Index of the evolution stage table in the table of offsets.
+const OFS_EVOLUTION: u32 = 5;
+
+/// Mag types; the second byte of a mag.
+pub const NUM_MAG_TYPES: usize = 0x53;
+
+#[derive(Clone, Debug, Default)]
+pub struct ItemMagEdit {
+    /// The evolution stage of each mag type: 0 for a new Mag, 1 after the
+    /// level 10 evolution, 2 after level 35, 3 after level 50 and 4 for mags
+    /// that don't evolve any further.
+    pub evolution: Vec<u8>
+}
+
+impl ItemMagEdit {
+    /// Parse a decompressed ItemMagEdit.
+    pub fn load_from_buffer(buf: &[u8]) -> io::Result<ItemMagEdit> {
+        if buf.len() < 16 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "ItemMagEdit is too short"))
+        }
+        let mut cur = Cursor::new(buf);
+        cur.set_position(buf.len() as u64 - 16);
+        let table: u32 = try!(Serial::deserialize(&mut cur));
+        cur.set_position(table as u64 + OFS_EVOLUTION as u64 * 4);
+        let ofs: u32 = try!(Serial::deserialize(&mut cur));
+
+        let mut evolution = vec![0; NUM_MAG_TYPES];
+        cur.set_position(ofs as u64);
+        try!(cur.read_exact(&mut evolution));
+        Ok(ItemMagEdit {
+            evolution: evolution
+        })
+    }
+
+    /// The evolution stage of a mag type. Unknown types count as fully
+    /// evolved so nothing tries to evolve them.
+    pub fn evolution(&self, mag: u8) -> u8 {
+        self.evolution.get(mag as usize).cloned().unwrap_or(4)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_itemmagedit_evolution() {
+        let mut buf = vec![0u8; 0x100];
+        buf[0xF0] = 0x80;
+        buf[0x94] = 0x20;
+        buf[0x20] = 0;
+        buf[0x21] = 1;
+        buf[0x22] = 2;
+        let me = ItemMagEdit::load_from_buffer(&buf).unwrap();
+        assert_eq!(me.evolution(0), 0);
+        assert_eq!(me.evolution(2), 2);
+        assert_eq!(me.evolution(0xFF), 4);
+        assert!(ItemMagEdit::load_from_buffer(&buf[..0x40]).is_err());
+    }
+}
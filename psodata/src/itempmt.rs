@@ -0,0 +1,604 @@
+//! Item parameters from ItemPMT.prs: the base stats, requirements and prices of
+//! every item the client knows about.
+//!
+//! The decompressed file is a set of tables found through a table of offsets,
+//! itself pointed at by the first word of the last 16 bytes of the file. Most
+//! item tables are lists of (count, offset) pairs, one list per item group,
+//! e.g. one per weapon type, indexed by the item's second data byte.
+//!
+//! Only the tables the server has a use for are read.
+
+use std::io::{Read, Write, Cursor};
+use std::io;
+
+use psoserial::Serial;
+
+/// The fields every item parameter entry starts with.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ItemBase {
+    /// Index into the star value table, among other things.
+    pub id: u32,
+    pub kind: u16,
+    pub skin: u16,
+    pub team_points: u32
+}
+impl Serial for ItemBase {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.id.serialize(dst));
+        try!(self.kind.serialize(dst));
+        try!(self.skin.serialize(dst));
+        try!(self.team_points.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(ItemBase {
+            id: try!(Serial::deserialize(src)),
+            kind: try!(Serial::deserialize(src)),
+            skin: try!(Serial::deserialize(src)),
+            team_points: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Weapon {
+    pub base: ItemBase,
+    pub class_flags: u16,
+    pub atp_min: u16,
+    pub atp_max: u16,
+    pub atp_required: u16,
+    pub mst_required: u16,
+    pub ata_required: u16,
+    pub mst: u16,
+    pub max_grind: u8,
+    pub photon: u8,
+    pub special: u8,
+    pub ata: u8,
+    pub stat_boost: u8,
+    pub projectile: u8,
+    pub trail: [u8; 4],
+    pub color: u8,
+    pub unknown: [u8; 5],
+    pub tech_boost: u8,
+    pub behavior_flags: u8
+}
+impl Serial for Weapon {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.base.serialize(dst));
+        try!(self.class_flags.serialize(dst));
+        try!(self.atp_min.serialize(dst));
+        try!(self.atp_max.serialize(dst));
+        try!(self.atp_required.serialize(dst));
+        try!(self.mst_required.serialize(dst));
+        try!(self.ata_required.serialize(dst));
+        try!(self.mst.serialize(dst));
+        try!(self.max_grind.serialize(dst));
+        try!(self.photon.serialize(dst));
+        try!(self.special.serialize(dst));
+        try!(self.ata.serialize(dst));
+        try!(self.stat_boost.serialize(dst));
+        try!(self.projectile.serialize(dst));
+        try!(self.trail.serialize(dst));
+        try!(self.color.serialize(dst));
+        try!(self.unknown.serialize(dst));
+        try!(self.tech_boost.serialize(dst));
+        try!(self.behavior_flags.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(Weapon {
+            base: try!(Serial::deserialize(src)),
+            class_flags: try!(Serial::deserialize(src)),
+            atp_min: try!(Serial::deserialize(src)),
+            atp_max: try!(Serial::deserialize(src)),
+            atp_required: try!(Serial::deserialize(src)),
+            mst_required: try!(Serial::deserialize(src)),
+            ata_required: try!(Serial::deserialize(src)),
+            mst: try!(Serial::deserialize(src)),
+            max_grind: try!(Serial::deserialize(src)),
+            photon: try!(Serial::deserialize(src)),
+            special: try!(Serial::deserialize(src)),
+            ata: try!(Serial::deserialize(src)),
+            stat_boost: try!(Serial::deserialize(src)),
+            projectile: try!(Serial::deserialize(src)),
+            trail: try!(Serial::deserialize(src)),
+            color: try!(Serial::deserialize(src)),
+            unknown: try!(Serial::deserialize(src)),
+            tech_boost: try!(Serial::deserialize(src)),
+            behavior_flags: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+/// A frame or a barrier.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Armor {
+    pub base: ItemBase,
+    pub dfp: u16,
+    pub evp: u16,
+    pub block_particle: u8,
+    pub block_effect: u8,
+    pub class_flags: u16,
+    pub required_level: u8,
+    /// EFR, ETH, EIC, EDK and ELT.
+    pub resists: [u8; 5],
+    /// How far above the base DFP and EVP a dropped one may roll.
+    pub dfp_range: u8,
+    pub evp_range: u8,
+    pub stat_boost: u8,
+    pub tech_boost: u8,
+    pub flags: u16
+}
+impl Serial for Armor {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.base.serialize(dst));
+        try!(self.dfp.serialize(dst));
+        try!(self.evp.serialize(dst));
+        try!(self.block_particle.serialize(dst));
+        try!(self.block_effect.serialize(dst));
+        try!(self.class_flags.serialize(dst));
+        try!(self.required_level.serialize(dst));
+        try!(self.resists.serialize(dst));
+        try!(self.dfp_range.serialize(dst));
+        try!(self.evp_range.serialize(dst));
+        try!(self.stat_boost.serialize(dst));
+        try!(self.tech_boost.serialize(dst));
+        try!(self.flags.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(Armor {
+            base: try!(Serial::deserialize(src)),
+            dfp: try!(Serial::deserialize(src)),
+            evp: try!(Serial::deserialize(src)),
+            block_particle: try!(Serial::deserialize(src)),
+            block_effect: try!(Serial::deserialize(src)),
+            class_flags: try!(Serial::deserialize(src)),
+            required_level: try!(Serial::deserialize(src)),
+            resists: try!(Serial::deserialize(src)),
+            dfp_range: try!(Serial::deserialize(src)),
+            evp_range: try!(Serial::deserialize(src)),
+            stat_boost: try!(Serial::deserialize(src)),
+            tech_boost: try!(Serial::deserialize(src)),
+            flags: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unit {
+    pub base: ItemBase,
+    pub stat: u16,
+    pub stat_amount: u16,
+    pub modifier: i16,
+    pub unused: u16
+}
+impl Serial for Unit {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.base.serialize(dst));
+        try!(self.stat.serialize(dst));
+        try!(self.stat_amount.serialize(dst));
+        try!(self.modifier.serialize(dst));
+        try!(self.unused.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(Unit {
+            base: try!(Serial::deserialize(src)),
+            stat: try!(Serial::deserialize(src)),
+            stat_amount: try!(Serial::deserialize(src)),
+            modifier: try!(Serial::deserialize(src)),
+            unused: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tool {
+    pub base: ItemBase,
+    pub amount: u16,
+    pub tech: u16,
+    /// Price in a shop. Technique disks cost this much per level.
+    pub cost: i32,
+    pub flags: u32
+}
+impl Serial for Tool {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.base.serialize(dst));
+        try!(self.amount.serialize(dst));
+        try!(self.tech.serialize(dst));
+        try!(self.cost.serialize(dst));
+        try!(self.flags.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(Tool {
+            base: try!(Serial::deserialize(src)),
+            amount: try!(Serial::deserialize(src)),
+            tech: try!(Serial::deserialize(src)),
+            cost: try!(Serial::deserialize(src)),
+            flags: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Mag {
+    pub base: ItemBase,
+    /// Which of the feed tables this mag uses.
+    pub feed_table: u16,
+    /// The photon blast the mag learns on becoming this type, or 0xFF.
+    pub photon_blast: u8,
+    pub activation: u8,
+    pub on_pb_full: u8,
+    pub on_low_hp: u8,
+    pub on_death: u8,
+    pub on_boss: u8,
+    pub flags: [u8; 4],
+    pub class_flags: u16,
+    pub unused: u16
+}
+impl Serial for Mag {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.base.serialize(dst));
+        try!(self.feed_table.serialize(dst));
+        try!(self.photon_blast.serialize(dst));
+        try!(self.activation.serialize(dst));
+        try!(self.on_pb_full.serialize(dst));
+        try!(self.on_low_hp.serialize(dst));
+        try!(self.on_death.serialize(dst));
+        try!(self.on_boss.serialize(dst));
+        try!(self.flags.serialize(dst));
+        try!(self.class_flags.serialize(dst));
+        try!(self.unused.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(Mag {
+            base: try!(Serial::deserialize(src)),
+            feed_table: try!(Serial::deserialize(src)),
+            photon_blast: try!(Serial::deserialize(src)),
+            activation: try!(Serial::deserialize(src)),
+            on_pb_full: try!(Serial::deserialize(src)),
+            on_low_hp: try!(Serial::deserialize(src)),
+            on_death: try!(Serial::deserialize(src)),
+            on_boss: try!(Serial::deserialize(src)),
+            flags: try!(Serial::deserialize(src)),
+            class_flags: try!(Serial::deserialize(src)),
+            unused: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+/// What feeding a mag one item does to it. Stats are in hundredths of a
+/// level.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MagFeedResult {
+    pub def: i8,
+    pub pow: i8,
+    pub dex: i8,
+    pub mind: i8,
+    pub iq: i8,
+    pub synchro: i8,
+    pub unused: [u8; 2]
+}
+impl Serial for MagFeedResult {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.def.serialize(dst));
+        try!(self.pow.serialize(dst));
+        try!(self.dex.serialize(dst));
+        try!(self.mind.serialize(dst));
+        try!(self.iq.serialize(dst));
+        try!(self.synchro.serialize(dst));
+        try!(self.unused.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(MagFeedResult {
+            def: try!(Serial::deserialize(src)),
+            pow: try!(Serial::deserialize(src)),
+            dex: try!(Serial::deserialize(src)),
+            mind: try!(Serial::deserialize(src)),
+            iq: try!(Serial::deserialize(src)),
+            synchro: try!(Serial::deserialize(src)),
+            unused: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+/// Divisors used in pricing everything but weapons, which have their own per
+/// weapon type.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SaleDivisors {
+    pub armor: f32,
+    pub shield: f32,
+    pub unit: f32,
+    pub mag: f32
+}
+
+// Indices into the table of offsets
+const OFS_WEAPONS: u64 = 0;
+const OFS_ARMORS: u64 = 1;
+const OFS_UNITS: u64 = 2;
+const OFS_TOOLS: u64 = 3;
+const OFS_MAGS: u64 = 4;
+const OFS_WEAPON_DIVISORS: u64 = 8;
+const OFS_SALE_DIVISORS: u64 = 9;
+const OFS_MAG_FEED: u64 = 10;
+const OFS_STARS: u64 = 11;
+
+/// Weapon types, including the unused type 0.
+const NUM_WEAPON_TYPES: u32 = 0xED;
+/// Tool types; the second byte of a tool.
+const NUM_TOOL_TYPES: u32 = 0x1B;
+/// Mag feed tables, and the items each has a result for.
+const NUM_MAG_FEED_TABLES: u32 = 8;
+pub const NUM_MAG_FEED_ITEMS: usize = 11;
+
+#[derive(Clone, Debug, Default)]
+pub struct ItemPMT {
+    /// By weapon type, then by the weapon's third byte.
+    pub weapons: Vec<Vec<Weapon>>,
+    pub frames: Vec<Armor>,
+    pub barriers: Vec<Armor>,
+    pub units: Vec<Unit>,
+    /// By tool type, then by the tool's third byte. Technique disks are by
+    /// technique instead.
+    pub tools: Vec<Vec<Tool>>,
+    /// By the mag's second byte.
+    pub mags: Vec<Mag>,
+    /// By feed table, then by item in the order of `mag_feed_item`.
+    pub mag_feed_tables: Vec<Vec<MagFeedResult>>,
+    pub weapon_divisors: Vec<f32>,
+    pub sale_divisors: SaleDivisors,
+    /// Stars of each item with an ID from the first weapon's on.
+    pub stars: Vec<u8>
+}
+
+fn seek(cur: &mut Cursor<&[u8]>, offset: u32) -> io::Result<()> {
+    if offset as usize >= cur.get_ref().len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("ItemPMT offset {:X} is out of range", offset)))
+    }
+    cur.set_position(offset as u64);
+    Ok(())
+}
+
+/// Read the offset at index `i` of the table of offsets.
+fn table_offset(cur: &mut Cursor<&[u8]>, table: u32, i: u64) -> io::Result<u32> {
+    try!(seek(cur, table + i as u32 * 4));
+    Serial::deserialize(cur)
+}
+
+/// Read `groups` (count, offset) pairs at `offset`, and the entries each points
+/// to.
+fn read_groups<T: Serial>(cur: &mut Cursor<&[u8]>, offset: u32, groups: u32) -> io::Result<Vec<Vec<T>>> {
+    let mut pairs = Vec::with_capacity(groups as usize);
+    try!(seek(cur, offset));
+    for _ in 0..groups {
+        let count: u32 = try!(Serial::deserialize(cur));
+        let at: u32 = try!(Serial::deserialize(cur));
+        pairs.push((count, at));
+    }
+    let mut ret = Vec::with_capacity(groups as usize);
+    for (count, at) in pairs {
+        let mut entries = Vec::with_capacity(count as usize);
+        try!(seek(cur, at));
+        for _ in 0..count {
+            entries.push(try!(T::deserialize(cur)));
+        }
+        ret.push(entries);
+    }
+    Ok(ret)
+}
+
+impl ItemPMT {
+    /// Parse a decompressed ItemPMT.
+    pub fn load_from_buffer(buf: &[u8]) -> io::Result<ItemPMT> {
+        if buf.len() < 16 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "ItemPMT is too short"))
+        }
+        let mut cur = Cursor::new(buf);
+        let len = buf.len() as u32;
+        try!(seek(&mut cur, len - 16));
+        let table: u32 = try!(Serial::deserialize(&mut cur));
+
+        let ofs = try!(table_offset(&mut cur, table, OFS_WEAPONS));
+        let weapons = try!(read_groups(&mut cur, ofs, NUM_WEAPON_TYPES));
+        let ofs = try!(table_offset(&mut cur, table, OFS_ARMORS));
+        let mut armors = try!(read_groups(&mut cur, ofs, 2));
+        let barriers = armors.pop().unwrap();
+        let frames = armors.pop().unwrap();
+        let ofs = try!(table_offset(&mut cur, table, OFS_UNITS));
+        let units = try!(read_groups(&mut cur, ofs, 1)).pop().unwrap();
+        let ofs = try!(table_offset(&mut cur, table, OFS_TOOLS));
+        let tools = try!(read_groups(&mut cur, ofs, NUM_TOOL_TYPES));
+        let ofs = try!(table_offset(&mut cur, table, OFS_MAGS));
+        let mags = try!(read_groups(&mut cur, ofs, 1)).pop().unwrap();
+
+        // The feed tables are a list of offsets to fixed size tables.
+        let ofs = try!(table_offset(&mut cur, table, OFS_MAG_FEED));
+        let mut mag_feed_tables = Vec::with_capacity(NUM_MAG_FEED_TABLES as usize);
+        for i in 0..NUM_MAG_FEED_TABLES {
+            let at = try!(table_offset(&mut cur, ofs, i as u64));
+            try!(seek(&mut cur, at));
+            let mut results = Vec::with_capacity(NUM_MAG_FEED_ITEMS);
+            for _ in 0..NUM_MAG_FEED_ITEMS {
+                results.push(try!(MagFeedResult::deserialize(&mut cur)));
+            }
+            mag_feed_tables.push(results);
+        }
+
+        let ofs = try!(table_offset(&mut cur, table, OFS_WEAPON_DIVISORS));
+        try!(seek(&mut cur, ofs));
+        let mut weapon_divisors = Vec::with_capacity(NUM_WEAPON_TYPES as usize);
+        for _ in 0..NUM_WEAPON_TYPES {
+            weapon_divisors.push(try!(Serial::deserialize(&mut cur)));
+        }
+        let ofs = try!(table_offset(&mut cur, table, OFS_SALE_DIVISORS));
+        try!(seek(&mut cur, ofs));
+        let sale_divisors = SaleDivisors {
+            armor: try!(Serial::deserialize(&mut cur)),
+            shield: try!(Serial::deserialize(&mut cur)),
+            unit: try!(Serial::deserialize(&mut cur)),
+            mag: try!(Serial::deserialize(&mut cur))
+        };
+
+        // The star table runs up to the table after it in the file.
+        let ofs = try!(table_offset(&mut cur, table, OFS_STARS));
+        let end = try!(table_offset(&mut cur, table, OFS_STARS + 1));
+        if end < ofs || end > len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "ItemPMT star table has no end"))
+        }
+        let stars = buf[ofs as usize..end as usize].to_vec();
+
+        Ok(ItemPMT {
+            weapons: weapons,
+            frames: frames,
+            barriers: barriers,
+            units: units,
+            tools: tools,
+            mags: mags,
+            mag_feed_tables: mag_feed_tables,
+            weapon_divisors: weapon_divisors,
+            sale_divisors: sale_divisors,
+            stars: stars
+        })
+    }
+
+    pub fn weapon(&self, kind: u8, index: u8) -> Option<&Weapon> {
+        self.weapons.get(kind as usize).and_then(|w| w.get(index as usize))
+    }
+
+    /// A frame (kind 1) or barrier (kind 2).
+    pub fn armor(&self, kind: u8, index: u8) -> Option<&Armor> {
+        match kind {
+            1 => self.frames.get(index as usize),
+            2 => self.barriers.get(index as usize),
+            _ => None
+        }
+    }
+
+    pub fn unit(&self, index: u8) -> Option<&Unit> {
+        self.units.get(index as usize)
+    }
+
+    pub fn tool(&self, kind: u8, index: u8) -> Option<&Tool> {
+        self.tools.get(kind as usize).and_then(|t| t.get(index as usize))
+    }
+
+    pub fn mag(&self, index: u8) -> Option<&Mag> {
+        self.mags.get(index as usize)
+    }
+
+    /// What feeding a tool (its second and third bytes) to a mag does, if
+    /// mags can eat it at all.
+    pub fn mag_feed_result(&self, mag: u8, kind: u8, index: u8) -> Option<&MagFeedResult> {
+        let item = match mag_feed_item(kind, index) {
+            Some(i) => i,
+            None => return None
+        };
+        self.mag(mag)
+            .and_then(|m| self.mag_feed_tables.get(m.feed_table as usize))
+            .and_then(|t| t.get(item))
+    }
+
+    /// The stars of the item with a parameter ID. Items before the first
+    /// weapon have none.
+    pub fn stars(&self, id: u32) -> u8 {
+        let first = self.weapons.iter().filter_map(|w| w.first()).map(|w| w.base.id).min().unwrap_or(0);
+        if id < first {
+            return 0
+        }
+        self.stars.get((id - first) as usize).cloned().unwrap_or(0)
+    }
+}
+
+/// The index into a feed table of a tool, given its second and third bytes.
+pub fn mag_feed_item(kind: u8, index: u8) -> Option<usize> {
+    let item = match (kind, index) {
+        (0x00, i) if i <= 2 => i, // Monomate, Dimate, Trimate
+        (0x01, i) if i <= 2 => 3 + i, // Monofluid, Difluid, Trifluid
+        (0x06, i) if i <= 1 => 6 + i, // Antidote, Antiparalysis
+        (0x03, 0x00) => 8, // Sol Atomizer
+        (0x04, 0x00) => 9, // Moon Atomizer
+        (0x05, 0x00) => 10, // Star Atomizer
+        _ => return None
+    };
+    Some(item as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use psoserial::Serial;
+    use super::*;
+
+    fn put(buf: &mut Vec<u8>, at: usize, v: usize) {
+        let mut w = Vec::new();
+        (v as u32).serialize(&mut w).unwrap();
+        buf[at..at + 4].copy_from_slice(&w);
+    }
+
+    #[test]
+    fn test_itempmt_tables() {
+        // One saber, one frame, one monomate; every other group is empty.
+        let mut buf = vec![0u8; 0x2000];
+        let table = 0x1F00;
+        put(&mut buf, 0x2000 - 16, table);
+
+        let weapons = 0x100;
+        put(&mut buf, table, weapons);
+        put(&mut buf, weapons + 8, 1);
+        put(&mut buf, weapons + 12, 0x1000);
+        let mut saber = Weapon::default();
+        saber.base.id = 0xB1;
+        saber.atp_max = 55;
+        let mut w = Vec::new();
+        saber.serialize(&mut w).unwrap();
+        buf[0x1000..0x1000 + w.len()].copy_from_slice(&w);
+
+        let armors = 0x900;
+        put(&mut buf, table + 4, armors);
+        put(&mut buf, armors, 1);
+        put(&mut buf, armors + 4, 0x1100);
+        let mut frame = Armor::default();
+        frame.base.id = 0xB3;
+        frame.dfp = 5;
+        let mut w = Vec::new();
+        frame.serialize(&mut w).unwrap();
+        buf[0x1100..0x1100 + w.len()].copy_from_slice(&w);
+
+        put(&mut buf, table + 8, 0x920);
+        let tools = 0x940;
+        put(&mut buf, table + 12, tools);
+        put(&mut buf, tools, 1);
+        put(&mut buf, tools + 4, 0x1200);
+        let mut monomate = Tool::default();
+        monomate.cost = 50;
+        let mut w = Vec::new();
+        monomate.serialize(&mut w).unwrap();
+        buf[0x1200..0x1200 + w.len()].copy_from_slice(&w);
+
+        put(&mut buf, table + 32, 0x1300);
+        put(&mut buf, table + 36, 0x1700);
+        put(&mut buf, table + 44, 0x1800);
+        put(&mut buf, table + 48, 0x1804);
+        buf[0x1800..0x1804].copy_from_slice(&[0, 1, 2, 3]);
+
+        let pmt = ItemPMT::load_from_buffer(&buf).unwrap();
+        assert_eq!(pmt.weapon(1, 0).unwrap().atp_max, 55);
+        assert!(pmt.weapon(1, 1).is_none());
+        assert_eq!(pmt.armor(1, 0).unwrap().dfp, 5);
+        assert!(pmt.barriers.is_empty());
+        assert_eq!(pmt.tool(0, 0).unwrap().cost, 50);
+        assert_eq!(pmt.weapon_divisors.len(), 0xED);
+        assert_eq!(pmt.stars(0xB3), 2);
+        assert_eq!(pmt.stars(0x10), 0);
+    }
+}
@@ -57,6 +57,18 @@ impl ItemPT {
             sections: sections
         })
     }
+
+    /// Make a table from its sections, in Section ID order.
+    pub fn from_sections(sections: Vec<ProbTable>) -> ItemPT {
+        ItemPT {
+            sections: sections
+        }
+    }
+
+    /// The probability table for a Section ID.
+    pub fn section(&self, section_id: u8) -> Option<&ProbTable> {
+        self.sections.get(section_id as usize)
+    }
 }
 
 // We have to circumvent some language limitations at the moment... there's no
@@ -39,7 +39,7 @@ impl RtEntry {
             v if v < 0 => 0,
             v => v
         };
-        let expanded = ((2 << tmp) * ((self.prob & 7) + 7)) as f64;
+        let expanded = ((2u64 << tmp) * ((self.prob & 7) as u64 + 7)) as f64;
         expanded / (0x100000000u64 as f64)
     }
 
@@ -55,25 +55,40 @@ impl RtEntry {
 #[derive(Clone, Debug)]
 pub struct RtSet {
     pub enemy_rares: Vec<RtEntry>,
+    /// The area each box rare can drop in.
+    pub box_areas: Vec<u8>,
     pub box_rares: Vec<RtEntry>
 }
 impl Serial for RtSet {
     fn serialize(&self, dst: &mut Write) -> io::Result<()> {
         try!(write_array(&self.enemy_rares, 101, dst));
+        try!(write_array(&self.box_areas, 30, dst));
         try!(write_array(&self.box_rares, 30, dst));
         Ok(())
     }
 
     fn deserialize(src: &mut Read) -> io::Result<Self> {
         let enemy_rares = try!(read_array(101, src));
+        let box_areas = try!(read_array(30, src));
         let box_rares = try!(read_array(30, src));
         Ok(RtSet {
             enemy_rares: enemy_rares,
+            box_areas: box_areas,
             box_rares: box_rares
         })
     }
 }
 
+impl RtSet {
+    /// The box rares that can drop in an area.
+    pub fn box_rares_in(&self, area: u8) -> Vec<&RtEntry> {
+        self.box_areas.iter().zip(self.box_rares.iter())
+            .filter(|&(&a, _)| a == area)
+            .map(|(_, e)| e)
+            .collect()
+    }
+}
+
 /// Full rare drop table for a full episode.
 #[derive(Clone, Debug)]
 pub struct ItemRT {
@@ -97,4 +112,43 @@ impl ItemRT {
             sections: sections
         })
     }
+
+    /// Make a table from its sections, in Section ID order.
+    pub fn from_sections(sections: Vec<RtSet>) -> ItemRT {
+        ItemRT {
+            sections: sections
+        }
+    }
+
+    /// The rare table for a Section ID.
+    pub fn section(&self, section_id: u8) -> Option<&RtSet> {
+        self.sections.get(section_id as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use psoserial::Serial;
+    use super::*;
+
+    #[test]
+    fn test_rtset_box_areas() {
+        let mut buf = vec![0u8; 101 * 4];
+        for i in 0..30 {
+            buf.push((i % 10) as u8);
+        }
+        for i in 0..30 {
+            buf.extend_from_slice(&[0xFF, 3, 0, i as u8]);
+        }
+        let set = RtSet::deserialize(&mut Cursor::new(&buf[..])).unwrap();
+        let rares = set.box_rares_in(4);
+        assert_eq!(rares.len(), 3);
+        assert_eq!(rares[0].item_data, [3, 0, 4]);
+        assert_eq!(rares[2].item_data, [3, 0, 24]);
+
+        let mut out = Vec::new();
+        set.serialize(&mut out).unwrap();
+        assert_eq!(out, buf);
+    }
 }
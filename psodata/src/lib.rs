@@ -3,6 +3,7 @@
 extern crate byteorder;
 extern crate psoserial;
 #[macro_use] extern crate log;
+#[cfg(test)] extern crate rand;
 
 pub mod battleparam;
 pub mod leveltable;
@@ -11,7 +12,11 @@ pub mod prs;
 pub mod gsl;
 pub mod itempt;
 pub mod itemrt;
+pub mod itempmt;
+pub mod itemmagedit;
+pub mod quest;
 pub mod chara;
+pub mod guildcard;
 pub mod bb_defaults;
 
 pub use battleparam::BattleParam;
@@ -1,13 +1,266 @@
 use std::io;
 use std::io::Read;
 
-//use byteorder::{LittleEndian as LE, ReadBytesExt, WriteBytesExt};
+/// Farthest back a copy can reach. The long form has 13 bits of offset, and
+/// an offset of zero would read as the end of the data.
+const MAX_OFFSET: usize = 0x1FFF;
+/// Farthest back the short form can reach.
+const MAX_SHORT_OFFSET: usize = 0x100;
+const MAX_SHORT_SIZE: usize = 5;
+/// Longest copy that fits in a long copy's own three bits; longer ones take
+/// an extra byte.
+const MAX_LONG_SIZE: usize = 9;
+const MAX_SIZE: usize = 0x100;
 
-// struct Ctx<'a> {
-//     src: &'a mut Read,
-//     dst: Cursor<Vec<u8>>,
-// }
+/// The effort `compress` uses.
+pub const DEFAULT_EFFORT: usize = 32;
+/// From this effort on, a match is passed over when the next byte starts a
+/// longer one.
+const LAZY_EFFORT: usize = 8;
 
-pub fn compress(_: &mut Read) -> io::Result<Vec<u8>> {
-    unimplemented!()
+struct Ctx {
+    dst: Vec<u8>,
+    flags_pos: usize,
+    bit_pos: u8
+}
+
+impl Ctx {
+    /// Control bits share the stream with the data: a new flag byte goes in
+    /// wherever the output is when the last one fills up, since that's when
+    /// the decompressor reads it.
+    fn write_bit(&mut self, bit: bool) {
+        if self.bit_pos == 8 {
+            self.flags_pos = self.dst.len();
+            self.dst.push(0);
+            self.bit_pos = 0;
+        }
+        if bit {
+            self.dst[self.flags_pos] |= 1 << self.bit_pos;
+        }
+        self.bit_pos += 1;
+    }
+
+    fn literal(&mut self, b: u8) {
+        self.write_bit(true);
+        self.dst.push(b);
+    }
+
+    fn copy(&mut self, size: usize, offset: usize) {
+        if size <= MAX_SHORT_SIZE && offset <= MAX_SHORT_OFFSET {
+            let s = size - 2;
+            self.write_bit(false);
+            self.write_bit(false);
+            self.write_bit(s & 2 != 0);
+            self.write_bit(s & 1 != 0);
+            self.dst.push((MAX_SHORT_OFFSET - offset) as u8);
+        } else {
+            let o = ((MAX_OFFSET + 1 - offset) << 3) as u16;
+            self.write_bit(false);
+            self.write_bit(true);
+            if size <= MAX_LONG_SIZE {
+                let v = o | (size - 2) as u16;
+                self.dst.push(v as u8);
+                self.dst.push((v >> 8) as u8);
+            } else {
+                self.dst.push(o as u8);
+                self.dst.push((o >> 8) as u8);
+                self.dst.push((size - 1) as u8);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.write_bit(false);
+        self.write_bit(true);
+        self.dst.push(0);
+        self.dst.push(0);
+        self.dst
+    }
+}
+
+/// Earlier positions of each pair of bytes, nearest first.
+struct Matcher<'a> {
+    src: &'a [u8],
+    head: Vec<isize>,
+    prev: Vec<isize>,
+    next_insert: usize,
+    effort: usize
+}
+
+impl<'a> Matcher<'a> {
+    fn new(src: &'a [u8], effort: usize) -> Matcher<'a> {
+        Matcher {
+            src: src,
+            head: vec![-1; 0x10000],
+            prev: vec![-1; src.len()],
+            next_insert: 0,
+            effort: effort
+        }
+    }
+
+    fn key(&self, pos: usize) -> usize {
+        self.src[pos] as usize | (self.src[pos + 1] as usize) << 8
+    }
+
+    /// Record every position before `end`.
+    fn insert_until(&mut self, end: usize) {
+        while self.next_insert < end {
+            let pos = self.next_insert;
+            if pos + 1 < self.src.len() {
+                let key = self.key(pos);
+                self.prev[pos] = self.head[key];
+                self.head[key] = pos as isize;
+            }
+            self.next_insert += 1;
+        }
+    }
+
+    /// The longest copy that can produce the bytes at `pos`, as its size and
+    /// offset. A size of 0 means there isn't one.
+    fn find(&self, pos: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if pos + 1 >= self.src.len() {
+            return best
+        }
+        let max_size = ::std::cmp::min(MAX_SIZE, self.src.len() - pos);
+        let mut cand = self.head[self.key(pos)];
+        let mut tries = self.effort;
+        while cand >= 0 && tries > 0 {
+            let offset = pos - cand as usize;
+            if offset > MAX_OFFSET {
+                break
+            }
+            // Copies may overlap what they're producing, so compare against
+            // the source rather than what's been written.
+            let size = (0..max_size)
+                .take_while(|&i| self.src[cand as usize + i] == self.src[pos + i])
+                .count();
+            // Two bytes are only worth a copy in the short form.
+            if size > best.0 && (size > 2 || offset <= MAX_SHORT_OFFSET) {
+                best = (size, offset);
+                if size == max_size {
+                    break
+                }
+            }
+            cand = self.prev[cand as usize];
+            tries -= 1;
+        }
+        best
+    }
+}
+
+/// Compress with `DEFAULT_EFFORT`.
+pub fn compress(src: &mut Read) -> io::Result<Vec<u8>> {
+    compress_with_effort(src, DEFAULT_EFFORT)
+}
+
+/// Compress everything in `src`. `effort` is how many earlier occurrences of
+/// each pair of bytes are tried when looking for a copy: 1 is fastest, and
+/// higher values compress better at the cost of time.
+pub fn compress_with_effort(src: &mut Read, effort: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    try!(src.read_to_end(&mut buf));
+
+    let mut ctx = Ctx {
+        dst: Vec::with_capacity(buf.len() / 2 + 16),
+        flags_pos: 0,
+        bit_pos: 8
+    };
+    let mut m = Matcher::new(&buf, ::std::cmp::max(effort, 1));
+    let mut pos = 0;
+    while pos < buf.len() {
+        m.insert_until(pos);
+        let (size, offset) = m.find(pos);
+        if size == 0 {
+            ctx.literal(buf[pos]);
+            pos += 1;
+            continue
+        }
+        if effort >= LAZY_EFFORT && size < MAX_SIZE {
+            m.insert_until(pos + 1);
+            if m.find(pos + 1).0 > size {
+                ctx.literal(buf[pos]);
+                pos += 1;
+                continue
+            }
+        }
+        ctx.copy(size, offset);
+        pos += size;
+    }
+    Ok(ctx.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    use super::*;
+    use super::super::decompress::decompress;
+
+    fn round_trip(data: &[u8], effort: usize) -> Vec<u8> {
+        let c = compress_with_effort(&mut Cursor::new(data), effort).unwrap();
+        let d = decompress(&mut Cursor::new(&c[..])).unwrap();
+        assert_eq!(&d[..], data);
+        c
+    }
+
+    #[test]
+    fn test_prs_round_trip_edges() {
+        round_trip(&[], DEFAULT_EFFORT);
+        round_trip(&[1], DEFAULT_EFFORT);
+        round_trip(&[1, 1], DEFAULT_EFFORT);
+        let zeros = vec![0u8; 0x10000];
+        let c = round_trip(&zeros, DEFAULT_EFFORT);
+        assert!(c.len() < 0x1000);
+        // Copies right at the edges of each form's reach
+        let mut far = vec![0u8; MAX_OFFSET + 0x200];
+        for (i, b) in far.iter_mut().enumerate() {
+            *b = (i * 7 / 3) as u8 ^ (i >> 8) as u8;
+        }
+        far[MAX_OFFSET..MAX_OFFSET + 0x20].copy_from_slice(&[9; 0x20]);
+        far[0..0x20].copy_from_slice(&[9; 0x20]);
+        round_trip(&far, DEFAULT_EFFORT);
+    }
+
+    /// Random inputs, from noise to long runs of a few symbols, at every
+    /// kind of effort.
+    #[test]
+    fn test_prs_round_trip_random() {
+        let mut rng = XorShiftRng::from_seed([0x1234, 0x5678, 0x9ABC, 0xDEF0]);
+        for case in 0..200 {
+            let len = rng.gen_range(0, 0x3000);
+            let symbols = rng.gen_range(1, 257);
+            let run = rng.gen_range(1, 40);
+            let mut data = Vec::with_capacity(len);
+            while data.len() < len {
+                let b = rng.gen_range(0, symbols) as u8;
+                let n = rng.gen_range(1, run + 1);
+                for _ in 0..n {
+                    data.push(b);
+                }
+            }
+            data.truncate(len);
+            let effort = [1, 4, DEFAULT_EFFORT, 256][case % 4];
+            round_trip(&data, effort);
+        }
+    }
+
+    #[test]
+    fn test_prs_effort() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let words: Vec<Vec<u8>> = (0..64).map(|_| {
+            let len = rng.gen_range(3, 12);
+            (0..len).map(|_| rng.gen()).collect()
+        }).collect();
+        let mut data = Vec::new();
+        for _ in 0..2000 {
+            data.extend_from_slice(rng.choose(&words).unwrap());
+        }
+        let fast = round_trip(&data, 1);
+        let best = round_trip(&data, 256);
+        assert!(best.len() <= fast.len());
+        assert!(fast.len() < data.len());
+    }
 }
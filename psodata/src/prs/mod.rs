@@ -4,4 +4,5 @@ pub mod compress;
 pub mod decompress;
 
 pub use self::compress::compress as compress_prs;
+pub use self::compress::compress_with_effort as compress_prs_with_effort;
 pub use self::decompress::decompress as decompress_prs;
@@ -0,0 +1,477 @@
+//! Blue Burst quest files.
+//!
+//! A quest is a pair of PRS-compressed files. The `.bin` holds the quest's
+//! script behind a header naming and describing the quest; the `.dat` holds
+//! the objects, enemies and wave events the quest places in each area, as a
+//! list of sections. Quests are often distributed as a `.qst` instead, which
+//! packs both compressed files as the messages a server would send to
+//! download them.
+
+use std::io::{Read, Write, Cursor};
+use std::io;
+
+use byteorder::{LittleEndian as LE, ReadBytesExt, WriteBytesExt};
+
+use psoserial::Serial;
+use psoserial::util::*;
+
+use map::{MapEnemy, MapObject};
+use prs::decompress_prs;
+
+/// Size of the header at the start of a decompressed `.bin`.
+pub const QUEST_HEADER_SIZE: usize = 0x388;
+
+// Section types in a .dat
+const DAT_END: u32 = 0;
+const DAT_OBJECTS: u32 = 1;
+const DAT_ENEMIES: u32 = 2;
+const DAT_EVENTS: u32 = 3;
+
+const DAT_SECTION_HEADER_SIZE: u32 = 16;
+const MAP_OBJECT_SIZE: u32 = 68;
+const MAP_ENEMY_SIZE: u32 = 72;
+const QUEST_EVENT_SIZE: u32 = 20;
+
+// Messages a .qst is made of
+const QST_OPEN_FILE: u16 = 0x44;
+const QST_CHUNK: u16 = 0x13;
+const QST_CHUNK_SIZE: usize = 0x400;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuestHeader {
+    pub code_offset: u32,
+    pub function_table_offset: u32,
+    /// Size of the decompressed `.bin`.
+    pub size: u32,
+    pub number: u16,
+    pub language: u8,
+    pub unknown1: u8,
+    /// 0 for Episode 1, 1 for Episode 2 and 2 for Episode 4.
+    pub episode: u8,
+    pub max_players: u8,
+    pub joinable: u8,
+    pub unknown2: u8,
+    pub name: String,
+    pub short_description: String,
+    pub long_description: String
+}
+
+impl Serial for QuestHeader {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(dst.write_u32::<LE>(self.code_offset));
+        try!(dst.write_u32::<LE>(self.function_table_offset));
+        try!(dst.write_u32::<LE>(self.size));
+        try!(dst.write_u32::<LE>(0xFFFFFFFF));
+        try!(dst.write_u16::<LE>(self.number));
+        try!(dst.write_u8(self.language));
+        try!(dst.write_u8(self.unknown1));
+        try!(dst.write_u8(self.episode));
+        try!(dst.write_u8(self.max_players));
+        try!(dst.write_u8(self.joinable));
+        try!(dst.write_u8(self.unknown2));
+        try!(write_utf16_len(&self.name, 0x30, dst));
+        try!(write_utf16_len(&self.short_description, 0x100, dst));
+        try!(write_utf16_len(&self.long_description, 0x240, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let code_offset = try!(src.read_u32::<LE>());
+        let function_table_offset = try!(src.read_u32::<LE>());
+        let size = try!(src.read_u32::<LE>());
+        try!(src.read_u32::<LE>());
+        let number = try!(src.read_u16::<LE>());
+        let language = try!(src.read_u8());
+        let unknown1 = try!(src.read_u8());
+        let episode = try!(src.read_u8());
+        let max_players = try!(src.read_u8());
+        let joinable = try!(src.read_u8());
+        let unknown2 = try!(src.read_u8());
+        let name = try!(read_utf16_len(0x30, src));
+        let short_description = try!(read_utf16_len(0x100, src));
+        let long_description = try!(read_utf16_len(0x240, src));
+        Ok(QuestHeader {
+            code_offset: code_offset,
+            function_table_offset: function_table_offset,
+            size: size,
+            number: number,
+            language: language,
+            unknown1: unknown1,
+            episode: episode,
+            max_players: max_players,
+            joinable: joinable,
+            unknown2: unknown2,
+            name: name,
+            short_description: short_description,
+            long_description: long_description
+        })
+    }
+}
+
+impl Default for QuestHeader {
+    fn default() -> QuestHeader {
+        QuestHeader {
+            code_offset: QUEST_HEADER_SIZE as u32,
+            function_table_offset: QUEST_HEADER_SIZE as u32,
+            size: QUEST_HEADER_SIZE as u32,
+            number: 0,
+            language: 0,
+            unknown1: 0,
+            episode: 0,
+            max_players: 4,
+            joinable: 0,
+            unknown2: 0,
+            name: String::new(),
+            short_description: String::new(),
+            long_description: String::new()
+        }
+    }
+}
+
+impl QuestHeader {
+    /// The episode as 1, 2 or 4.
+    pub fn episode_number(&self) -> u8 {
+        match self.episode {
+            1 => 2,
+            2 => 4,
+            _ => 1
+        }
+    }
+}
+
+/// An entry in a wave event section. The actions it runs are found at
+/// `action_offset` in the section's action stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuestEvent {
+    pub id: u32,
+    pub flags: u16,
+    pub event_type: u16,
+    pub section: u16,
+    pub wave: u16,
+    pub delay: u32,
+    pub action_offset: u32
+}
+
+impl Serial for QuestEvent {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(dst.write_u32::<LE>(self.id));
+        try!(dst.write_u16::<LE>(self.flags));
+        try!(dst.write_u16::<LE>(self.event_type));
+        try!(dst.write_u16::<LE>(self.section));
+        try!(dst.write_u16::<LE>(self.wave));
+        try!(dst.write_u32::<LE>(self.delay));
+        try!(dst.write_u32::<LE>(self.action_offset));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(QuestEvent {
+            id: try!(src.read_u32::<LE>()),
+            flags: try!(src.read_u16::<LE>()),
+            event_type: try!(src.read_u16::<LE>()),
+            section: try!(src.read_u16::<LE>()),
+            wave: try!(src.read_u16::<LE>()),
+            delay: try!(src.read_u32::<LE>()),
+            action_offset: try!(src.read_u32::<LE>())
+        })
+    }
+}
+
+/// The wave events of one area.
+#[derive(Clone, Debug, Default)]
+pub struct QuestEvents {
+    pub area: u32,
+    pub events: Vec<QuestEvent>,
+    /// The actions the events run, left as the client reads them.
+    pub actions: Vec<u8>
+}
+
+/// The contents of a decompressed `.dat`. Enemies and objects are kept in
+/// the order the client numbers them: by area, then as listed.
+#[derive(Clone, Debug, Default)]
+pub struct QuestDat {
+    pub objects: Vec<(u32, MapObject)>,
+    pub enemies: Vec<(u32, MapEnemy)>,
+    pub events: Vec<QuestEvents>
+}
+
+fn invalid<T>(msg: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+impl QuestDat {
+    /// Parse a decompressed `.dat`. Sections of other types are skipped.
+    pub fn load_from_buffer(buf: &[u8]) -> io::Result<QuestDat> {
+        let mut ret = QuestDat::default();
+        let mut cur = Cursor::new(buf);
+        while cur.position() + DAT_SECTION_HEADER_SIZE as u64 <= buf.len() as u64 {
+            let start = cur.position();
+            let kind = try!(cur.read_u32::<LE>());
+            let size = try!(cur.read_u32::<LE>());
+            let area = try!(cur.read_u32::<LE>());
+            let data_size = try!(cur.read_u32::<LE>());
+            if kind == DAT_END {
+                break
+            }
+            if data_size > size.saturating_sub(DAT_SECTION_HEADER_SIZE) || start + size as u64 > buf.len() as u64 {
+                return invalid("Quest .dat section runs past the end of the file")
+            }
+            let data = &buf[start as usize + DAT_SECTION_HEADER_SIZE as usize..][..data_size as usize];
+            let mut data_cur = Cursor::new(data);
+            match kind {
+                DAT_OBJECTS => for _ in 0..data_size / MAP_OBJECT_SIZE {
+                    ret.objects.push((area, try!(MapObject::deserialize(&mut data_cur))));
+                },
+                DAT_ENEMIES => for _ in 0..data_size / MAP_ENEMY_SIZE {
+                    ret.enemies.push((area, try!(MapEnemy::deserialize(&mut data_cur))));
+                },
+                DAT_EVENTS => ret.events.push(try!(QuestDat::read_events(area, data))),
+                _ => ()
+            }
+            cur.set_position(start + size as u64);
+        }
+        // sections for an area may be split up or out of order
+        ret.objects.sort_by_key(|&(area, _)| area);
+        ret.enemies.sort_by_key(|&(area, _)| area);
+        Ok(ret)
+    }
+
+    fn read_events(area: u32, data: &[u8]) -> io::Result<QuestEvents> {
+        let mut cur = Cursor::new(data);
+        let actions_offset = try!(cur.read_u32::<LE>()) as usize;
+        let events_offset = try!(cur.read_u32::<LE>()) as u64;
+        let num_events = try!(cur.read_u32::<LE>());
+        if actions_offset > data.len() || events_offset + num_events as u64 * QUEST_EVENT_SIZE as u64 > data.len() as u64 {
+            return invalid("Quest .dat event section is truncated")
+        }
+        cur.set_position(events_offset);
+        let mut events = Vec::with_capacity(num_events as usize);
+        for _ in 0..num_events {
+            events.push(try!(QuestEvent::deserialize(&mut cur)));
+        }
+        Ok(QuestEvents {
+            area: area,
+            events: events,
+            actions: data[actions_offset..].to_vec()
+        })
+    }
+}
+
+/// A quest, read from its compressed files.
+#[derive(Clone, Debug, Default)]
+pub struct Quest {
+    pub header: QuestHeader,
+    pub dat: QuestDat
+}
+
+impl Quest {
+    pub fn load(bin: &mut Read, dat: &mut Read) -> io::Result<Quest> {
+        let bin = try!(decompress_prs(bin));
+        let dat = try!(decompress_prs(dat));
+        if bin.len() < QUEST_HEADER_SIZE {
+            return invalid("Quest .bin is too short")
+        }
+        Ok(Quest {
+            header: try!(QuestHeader::deserialize(&mut Cursor::new(&bin[..]))),
+            dat: try!(QuestDat::load_from_buffer(&dat))
+        })
+    }
+}
+
+/// A file packed in a `.qst`, still compressed.
+#[derive(Clone, Debug, Default)]
+pub struct QstFile {
+    /// The name shown while the file downloads.
+    pub name: String,
+    pub filename: String,
+    pub data: Vec<u8>
+}
+
+/// Unpack the files in a Blue Burst `.qst`. Each message is padded to eight
+/// bytes, the same as on the wire.
+pub fn read_qst(src: &mut Read) -> io::Result<Vec<QstFile>> {
+    let mut buf = Vec::new();
+    try!(src.read_to_end(&mut buf));
+
+    let mut files: Vec<QstFile> = Vec::new();
+    let mut cur = Cursor::new(&buf[..]);
+    while cur.position() + 8 <= buf.len() as u64 {
+        let start = cur.position();
+        let size = try!(cur.read_u16::<LE>()) as u64;
+        let command = try!(cur.read_u16::<LE>());
+        try!(cur.read_u32::<LE>());
+        if size < 8 || start + size > buf.len() as u64 {
+            return invalid("Message in .qst runs past the end of the file")
+        }
+        match command {
+            QST_OPEN_FILE => {
+                let mut unused = [0; 0x24];
+                try!(cur.read_exact(&mut unused));
+                let filename = try!(read_ascii_len(0x10, &mut cur));
+                let length = try!(cur.read_u32::<LE>());
+                let name = try!(read_ascii_len(0x18, &mut cur));
+                files.push(QstFile {
+                    name: name,
+                    filename: filename,
+                    data: Vec::with_capacity(length as usize)
+                });
+            },
+            QST_CHUNK => {
+                let filename = try!(read_ascii_len(0x10, &mut cur));
+                let mut data = vec![0; QST_CHUNK_SIZE];
+                try!(cur.read_exact(&mut data));
+                let len = try!(cur.read_u32::<LE>()) as usize;
+                if len > QST_CHUNK_SIZE {
+                    return invalid("Chunk in .qst is too long")
+                }
+                match files.iter_mut().find(|f| f.filename == filename) {
+                    Some(f) => f.data.extend_from_slice(&data[..len]),
+                    None => return invalid("Chunk in .qst is for a file it never opened")
+                }
+            },
+            _ => return invalid("Unexpected message in .qst")
+        }
+        cur.set_position(start + (size + 7) / 8 * 8);
+    }
+    Ok(files)
+}
+
+/// The compressed `.bin` and `.dat` packed in a `.qst`.
+pub fn unpack_qst(src: &mut Read) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let files = try!(read_qst(src));
+    let bin = files.iter().find(|f| f.filename.ends_with(".bin"));
+    let dat = files.iter().find(|f| f.filename.ends_with(".dat"));
+    match (bin, dat) {
+        (Some(b), Some(d)) => Ok((b.data.clone(), d.data.clone())),
+        _ => invalid(".qst doesn't have both a .bin and a .dat")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use byteorder::{ByteOrder, LittleEndian as LE, WriteBytesExt};
+
+    use psoserial::Serial;
+    use psoserial::util::*;
+
+    use map::{MapEnemy, MapObject};
+    use super::*;
+
+    #[test]
+    fn test_quest_header_round_trip() {
+        let header = QuestHeader {
+            code_offset: 0x394,
+            function_table_offset: 0x1200,
+            size: 0x1800,
+            number: 58,
+            language: 1,
+            unknown1: 0,
+            episode: 1,
+            max_players: 4,
+            joinable: 1,
+            unknown2: 0,
+            name: "Magnitude of Metal".to_string(),
+            short_description: "Recover the metal\nfrom the mines.".to_string(),
+            long_description: "Client: Hopkins\nQuest:\nFind the metal.".to_string()
+        };
+        let mut buf = Vec::new();
+        header.serialize(&mut buf).unwrap();
+        assert_eq!(buf.len(), QUEST_HEADER_SIZE);
+        let read = QuestHeader::deserialize(&mut Cursor::new(&buf[..])).unwrap();
+        assert_eq!(read, header);
+        assert_eq!(read.episode_number(), 2);
+    }
+
+    fn section(buf: &mut Vec<u8>, kind: u32, area: u32, data: &[u8]) {
+        buf.write_u32::<LE>(kind).unwrap();
+        buf.write_u32::<LE>(data.len() as u32 + 16).unwrap();
+        buf.write_u32::<LE>(area).unwrap();
+        buf.write_u32::<LE>(data.len() as u32).unwrap();
+        buf.extend_from_slice(data);
+    }
+
+    #[test]
+    fn test_quest_dat_sections() {
+        let mut enemies2 = Vec::new();
+        let mut e = MapEnemy::default();
+        e.base = 0x44;
+        e.serialize(&mut enemies2).unwrap();
+        let mut enemies1 = Vec::new();
+        e.base = 0x40;
+        e.serialize(&mut enemies1).unwrap();
+        e.serialize(&mut enemies1).unwrap();
+        let mut objects = Vec::new();
+        MapObject::default().serialize(&mut objects).unwrap();
+        let mut events = Vec::new();
+        events.write_u32::<LE>(12 + 20).unwrap();
+        events.write_u32::<LE>(12).unwrap();
+        events.write_u32::<LE>(1).unwrap();
+        QuestEvent { id: 100, wave: 1, ..Default::default() }.serialize(&mut events).unwrap();
+        events.extend_from_slice(&[0x08, 0x01]);
+
+        let mut dat = Vec::new();
+        section(&mut dat, 2, 2, &enemies2);
+        section(&mut dat, 1, 1, &objects);
+        section(&mut dat, 2, 1, &enemies1);
+        section(&mut dat, 3, 1, &events);
+        section(&mut dat, 0, 0, &[]);
+
+        let d = QuestDat::load_from_buffer(&dat).unwrap();
+        assert_eq!(d.objects.len(), 1);
+        let bases: Vec<_> = d.enemies.iter().map(|&(a, ref e)| (a, e.base)).collect();
+        assert_eq!(bases, vec![(1, 0x40), (1, 0x40), (2, 0x44)]);
+        assert_eq!(d.events.len(), 1);
+        assert_eq!(d.events[0].events[0].id, 100);
+        assert_eq!(d.events[0].actions, vec![0x08, 0x01]);
+
+        assert!(QuestDat::load_from_buffer(&dat[..40]).is_err());
+
+        // Event counts past the end of the section, including ones whose
+        // size doesn't fit in 32 bits.
+        for &n in [2, 0x10000000, 0xFFFFFFFF].iter() {
+            let mut bad = events.clone();
+            LE::write_u32(&mut bad[8..12], n);
+            let mut dat = Vec::new();
+            section(&mut dat, 3, 1, &bad);
+            section(&mut dat, 0, 0, &[]);
+            assert!(QuestDat::load_from_buffer(&dat).is_err());
+        }
+    }
+
+    #[test]
+    fn test_read_qst() {
+        let bin: Vec<u8> = (0..0x500).map(|i| i as u8).collect();
+        let dat = vec![7u8; 0x20];
+        let mut qst = Vec::new();
+        for &(filename, data) in [("quest58.bin", &bin), ("quest58.dat", &dat)].iter() {
+            qst.write_u16::<LE>(0x58).unwrap();
+            qst.write_u16::<LE>(0x44).unwrap();
+            qst.write_u32::<LE>(0).unwrap();
+            qst.extend_from_slice(&[0; 0x24]);
+            write_ascii_len(filename, 0x10, &mut qst).unwrap();
+            qst.write_u32::<LE>(data.len() as u32).unwrap();
+            write_ascii_len("PSO/Magnitude of Metal", 0x18, &mut qst).unwrap();
+        }
+        for &(filename, data) in [("quest58.bin", &bin), ("quest58.dat", &dat)].iter() {
+            for (i, chunk) in data.chunks(0x400).enumerate() {
+                qst.write_u16::<LE>(0x41C).unwrap();
+                qst.write_u16::<LE>(0x13).unwrap();
+                qst.write_u32::<LE>(i as u32).unwrap();
+                write_ascii_len(filename, 0x10, &mut qst).unwrap();
+                qst.extend_from_slice(chunk);
+                qst.extend_from_slice(&vec![0; 0x400 - chunk.len()]);
+                qst.write_u32::<LE>(chunk.len() as u32).unwrap();
+                qst.extend_from_slice(&[0; 4]);
+            }
+        }
+
+        let files = read_qst(&mut Cursor::new(&qst[..])).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "PSO/Magnitude of Metal");
+        let (b, d) = unpack_qst(&mut Cursor::new(&qst[..])).unwrap();
+        assert_eq!(b, bin);
+        assert_eq!(d, dat);
+    }
+}
@@ -59,6 +59,7 @@ impl Account {
 pub struct BbAccountInfo {
     pub account_id: u32,
     pub guildcard_num: u32,
+    /// The team the account is in, or 0 if it isn't in one.
     pub team_id: u32,
     pub options: u32,
     pub key_config: Vec<u8>,
@@ -73,7 +74,7 @@ impl BbAccountInfo {
         BbAccountInfo {
             account_id: 0,
             guildcard_num: bbgc,
-            team_id: 1,
+            team_id: 0,
             options: 0,
             key_config: DEFAULT_KEYS.to_vec(),
             joy_config: DEFAULT_JOY.to_vec(),
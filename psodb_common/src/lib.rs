@@ -9,13 +9,16 @@ pub mod error;
 pub mod pool;
 
 pub mod account;
+pub mod team;
 
 pub use self::error::Error;
 pub use self::account::Account;
 pub use self::account::BbAccountInfo;
+pub use self::team::{BbTeam, BbTeamMember};
 pub use self::pool::Pool;
 
-use psodata::chara::BbFullCharData;
+use psodata::chara::{BbFullCharData, ItemBank};
+use psodata::guildcard::BbGuildCardFile;
 
 use std::result;
 
@@ -57,7 +60,66 @@ pub trait Backend {
     /// whether or not to save the account-global data from the character info.
     fn put_bb_character(&self, account_id: u32, slot: u8, chara: BbFullCharData, save_acct_data: bool) -> Result<()>;
 
+    /// Fetch the bank shared by every character on the account. If the
+    /// account has no common bank yet, this should yield an empty one.
+    fn fetch_bb_common_bank(&self, account_id: u32) -> Result<ItemBank>;
+
+    /// Place a BB character and the account's common bank in the DB as one
+    /// change: if either can't be saved, neither is. Items moved between the
+    /// two can't be lost or duplicated that way.
+    fn put_bb_character_and_common_bank(&self, account_id: u32, slot: u8, chara: BbFullCharData, bank: &ItemBank) -> Result<()>;
+
+    /// Delete the BB character in the slot on the account. If `recoverable` is
+    /// set, the character is set aside so it can be brought back with
+    /// `restore_bb_character`; otherwise it is gone for good.
+    fn delete_bb_character(&self, account_id: u32, slot: u8, recoverable: bool) -> Result<()>;
+
+    /// Move the most recently deleted character for the slot back into it, if
+    /// it was deleted less than `max_age` seconds ago and the slot is empty.
+    /// Yields whether a character was restored.
+    fn restore_bb_character(&self, account_id: u32, slot: u8, max_age: u64) -> Result<bool>;
+
+    /// Permanently remove deleted characters older than `max_age` seconds.
+    /// Yields the number of characters removed.
+    fn purge_deleted_bb_characters(&self, max_age: u64) -> Result<u32>;
+
+    /// Reserve a name for the BB character in the slot on the account, in
+    /// place of the name it had. Yields false, reserving nothing, if another
+    /// character has the name. Names are compared without their language tag
+    /// and ignoring case.
+    fn reserve_bb_character_name(&self, name: &str, account_id: u32, slot: u8) -> Result<bool>;
+
     fn set_bb_login_flags(&self, account_id: u32, flags: u32) -> Result<()>;
 
     fn get_bb_login_flags(&self, account_id: u32) -> Result<u32>;
+
+    /// Fetch the guild card file for the account. If the account has no guild
+    /// cards stored yet, this should yield an empty file.
+    fn fetch_bb_guildcard_file(&self, account_id: u32) -> Result<BbGuildCardFile>;
+
+    /// Replace the stored guild card file for the account.
+    fn put_bb_guildcard_file(&self, account_id: u32, file: &BbGuildCardFile) -> Result<()>;
+
+    /// Create a team with the given name. Yields the new team's ID, or None if
+    /// a team already has that name.
+    fn create_bb_team(&self, name: &str) -> Result<Option<u32>>;
+
+    /// Retrieve a team by its ID.
+    fn fetch_bb_team(&self, team_id: u32) -> Result<Option<BbTeam>>;
+
+    /// Delete a team, removing all of its members from it.
+    fn delete_bb_team(&self, team_id: u32) -> Result<()>;
+
+    /// Get the team membership of an account, if it is in a team.
+    fn fetch_bb_team_member(&self, account_id: u32) -> Result<Option<BbTeamMember>>;
+
+    /// Get every member of a team, in the order they joined.
+    fn fetch_bb_team_members(&self, team_id: u32) -> Result<Vec<BbTeamMember>>;
+
+    /// Insert or update an account's team membership. The guild card number
+    /// of the member is ignored.
+    fn put_bb_team_member(&self, member: &BbTeamMember) -> Result<()>;
+
+    /// Remove an account from whatever team it is in.
+    fn remove_bb_team_member(&self, account_id: u32) -> Result<()>;
 }
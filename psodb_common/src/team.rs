@@ -0,0 +1,27 @@
+//! Structs related to Blue Burst teams.
+
+/// Rank of an ordinary team member.
+pub const PRIV_MEMBER: u32 = 0x00;
+/// Rank of a team leader, who may add and remove ordinary members.
+pub const PRIV_LEADER: u32 = 0x30;
+/// Rank of the team master. Each team has exactly one.
+pub const PRIV_MASTER: u32 = 0x40;
+
+/// A Blue Burst team.
+#[derive(Clone, Debug, Default)]
+pub struct BbTeam {
+    pub id: u32,
+    pub name: String
+}
+
+/// An account's membership in a team.
+#[derive(Clone, Debug, Default)]
+pub struct BbTeamMember {
+    pub account_id: u32,
+    pub team_id: u32,
+    /// The account's guild card number. Filled in by the backend on fetch.
+    pub guildcard: u32,
+    pub privilege: u32,
+    /// The name of the character the account joined with, for the member list.
+    pub name: String
+}
@@ -16,8 +16,10 @@ use psodb_common::error::Error;
 
 use psodb_common::account::Account;
 use psodb_common::account::BbAccountInfo;
+use psodb_common::team::{BbTeam, BbTeamMember};
 
-use psodata::chara::{BbFullCharData, BbTeamAndKeyData, BbChar};
+use psodata::chara::{BbFullCharData, BbTeamAndKeyData, BbChar, ItemBank, name_key};
+use psodata::guildcard::BbGuildCardFile;
 
 mod schema;
 use self::schema::SCHEMA;
@@ -57,10 +59,12 @@ impl Sqlite {
             try_db!(Sqlite::migrate(&conn, 0));
         }
 
-        Ok(Sqlite {
+        let s = Sqlite {
             path: p,
             conn: conn
-        })
+        };
+        try!(s.backfill_bb_character_names());
+        Ok(s)
     }
 
     /// Initialize and update tables
@@ -82,6 +86,163 @@ impl Sqlite {
 }
 
 
+/// The per-character columns shared by bb_character and bb_deleted_character.
+const CHARACTER_COLUMNS: &'static str = "inventory, char_data, quest_data1, bank, guildcard_desc, autoreply, infoboard, challenge_data, tech_menu, quest_data2";
+
+/// The current unix time, in SQL.
+const NOW: &'static str = "CAST(strftime('%s', 'now') AS INTEGER)";
+
+impl Sqlite {
+    /// Record a character's name for uniqueness checks. Yields false if
+    /// another character has it. The UNIQUE constraint on names means a
+    /// racing writer gets an error rather than a second copy of the name.
+    fn put_bb_character_name(&self, account_id: u32, slot: u8, name: &str) -> Result<bool> {
+        let aid = account_id as i64;
+        let slot = slot as i64;
+        let key = name_key(name);
+        let mut stmt = try_db!(self.conn.prepare("SELECT account_id, slot FROM bb_character_name WHERE name=? LIMIT 1"));
+        let mut results = try_db!(stmt.query_map(&[&key], |row| (row.get::<_, i64>(0), row.get::<_, i64>(1))));
+        match results.next() {
+            Some(Ok(owner)) => return Ok(owner == (aid, slot)),
+            Some(Err(e)) => return Err(Error::BackendError(Some(Box::new(e)))),
+            None => ()
+        }
+        let mut stmt = try_db!(self.conn.prepare("UPDATE bb_character_name SET name=? WHERE account_id=? AND slot=?"));
+        if try_db!(stmt.execute(&[&key, &aid, &slot])) == 0 {
+            let mut stmt = try_db!(self.conn.prepare("INSERT INTO bb_character_name (account_id,slot,name) VALUES (?,?,?)"));
+            try_db!(stmt.execute(&[&aid, &slot, &key]));
+        }
+        Ok(true)
+    }
+
+    /// Reserve the names of characters that were made before names were
+    /// recorded.
+    fn backfill_bb_character_names(&self) -> Result<()> {
+        let mut stmt = try_db!(self.conn.prepare("SELECT account_id, slot, char_data FROM bb_character c
+            WHERE NOT EXISTS (SELECT 1 FROM bb_character_name n WHERE n.account_id=c.account_id AND n.slot=c.slot)"));
+        let rows: Vec<(i64, i64, Vec<u8>)> = try_db!(try_db!(stmt.query_map(&[], |row| {
+            (row.get(0), row.get(1), row.get(2))
+        })).collect());
+        for (account_id, slot, data) in rows {
+            let chara: BbChar = try_db!(Serial::deserialize(&mut Cursor::new(data)));
+            if !try!(self.put_bb_character_name(account_id as u32, slot as u8, &chara.name)) {
+                warn!("Character at {} for account {} has the same name as another, {:?}; it isn't reserved", slot, account_id, chara.name);
+            }
+        }
+        Ok(())
+    }
+
+    fn put_bb_common_bank(&self, account_id: u32, bank: &ItemBank) -> Result<()> {
+        let mut stmt = try_db!(self.conn.prepare("INSERT OR REPLACE INTO bb_common_bank (account_id,bank) VALUES (?,?)"));
+        let aid = account_id as i64;
+        let bank = serial_to_vec(bank);
+        try_db!(stmt.execute(&[&aid, &bank]));
+        Ok(())
+    }
+
+    /// Run `f` in a transaction, committing if it succeeds and rolling back
+    /// if it fails. The database is locked for writing from the start, so
+    /// what `f` reads can't change under it.
+    fn in_transaction<T, F: FnOnce() -> Result<T>>(&self, f: F) -> Result<T> {
+        try_db!(self.conn.execute_batch("BEGIN IMMEDIATE"));
+        let r = f().and_then(|v| match self.conn.execute_batch("COMMIT") {
+            Ok(_) => Ok(v),
+            Err(e) => Err(Error::BackendError(Some(Box::new(e))))
+        });
+        if r.is_err() {
+            if let Err(e) = self.conn.execute_batch("ROLLBACK") {
+                error!("Couldn't roll back transaction: {}", e);
+            }
+        }
+        r
+    }
+
+    fn delete_bb_character_now(&self, account_id: u32, slot: u8, recoverable: bool) -> Result<()> {
+        let aid = account_id as i64;
+        let slot = slot as i64;
+        if recoverable {
+            let mut stmt = try_db!(self.conn.prepare(&format!("INSERT INTO bb_deleted_character (
+                account_id, slot, deleted_at, {cols}
+            ) SELECT account_id, slot, {now}, {cols} FROM bb_character WHERE account_id=? AND slot=?", cols=CHARACTER_COLUMNS, now=NOW)));
+            try_db!(stmt.execute(&[&aid, &slot]));
+        }
+        // Release the character's name, unless the slot has been given a
+        // new one for a character about to be made over it.
+        let mut stmt = try_db!(self.conn.prepare("SELECT char_data FROM bb_character WHERE account_id=? AND slot=?"));
+        let names: Vec<Vec<u8>> = try_db!(try_db!(stmt.query_map(&[&aid, &slot], |row| row.get(0))).collect());
+        for data in names {
+            let chara: BbChar = try_db!(Serial::deserialize(&mut Cursor::new(data)));
+            let mut stmt = try_db!(self.conn.prepare("DELETE FROM bb_character_name WHERE account_id=? AND slot=? AND name=?"));
+            try_db!(stmt.execute(&[&aid, &slot, &name_key(&chara.name)]));
+        }
+        let mut stmt = try_db!(self.conn.prepare("DELETE FROM bb_character WHERE account_id=? AND slot=?"));
+        let n = try_db!(stmt.execute(&[&aid, &slot]));
+        if n > 0 {
+            info!("Deleted character at {} for account {} (recoverable: {})", slot, account_id, recoverable);
+        }
+        Ok(())
+    }
+
+    fn restore_bb_character_now(&self, account_id: u32, slot: u8, max_age: u64) -> Result<bool> {
+        let aid = account_id as i64;
+        let slot = slot as i64;
+        let max_age = max_age as i64;
+
+        // Never overwrite a character that was made since.
+        let mut stmt = try_db!(self.conn.prepare("SELECT id FROM bb_character WHERE account_id=? AND slot=? LIMIT 1"));
+        let mut rows = try_db!(stmt.query(&[&aid, &slot]));
+        match rows.next() {
+            Some(Err(e)) => return Err(Error::BackendError(Some(Box::new(e)))),
+            Some(Ok(_)) => return Ok(false),
+            None => ()
+        }
+
+        let mut stmt = try_db!(self.conn.prepare(&format!("SELECT id FROM bb_deleted_character
+            WHERE account_id=? AND slot=? AND deleted_at >= {now} - ?
+            ORDER BY deleted_at DESC, id DESC LIMIT 1", now=NOW)));
+        let mut results = try_db!(stmt.query_map(&[&aid, &slot, &max_age], |row| {
+            row.get::<_, i64>(0)
+        }));
+        let id = match results.next() {
+            Some(Ok(id)) => id,
+            Some(Err(e)) => return Err(Error::BackendError(Some(Box::new(e)))),
+            None => return Ok(false)
+        };
+
+        // Its name may have been taken while it was gone.
+        let mut stmt = try_db!(self.conn.prepare("SELECT char_data FROM bb_deleted_character WHERE id=?"));
+        let data: Vec<u8> = try_db!(stmt.query_row(&[&id], |row| row.get(0)));
+        let chara: BbChar = try_db!(Serial::deserialize(&mut Cursor::new(data)));
+        if !try!(self.put_bb_character_name(account_id, slot as u8, &chara.name)) {
+            info!("Can't restore character at {} for account {}: another character is named {:?}", slot, account_id, chara.name);
+            return Ok(false)
+        }
+
+        let mut stmt = try_db!(self.conn.prepare(&format!("INSERT INTO bb_character (
+                account_id, slot, {cols}
+            ) SELECT account_id, slot, {cols} FROM bb_deleted_character WHERE id=?", cols=CHARACTER_COLUMNS)));
+        try_db!(stmt.execute(&[&id]));
+        let mut stmt = try_db!(self.conn.prepare("DELETE FROM bb_deleted_character WHERE id=?"));
+        try_db!(stmt.execute(&[&id]));
+        info!("Restored character at {} for account {}", slot, account_id);
+        Ok(true)
+    }
+}
+
+/// Selects team members along with their guild card numbers.
+const TEAM_MEMBER_SELECT: &'static str = "SELECT m.account_id, m.team_id, COALESCE(g.id, 0), m.privilege, m.name
+    FROM bb_team_member m LEFT JOIN bb_guildcard g ON g.account_id = m.account_id";
+
+fn team_member_from_row(row: &rusqlite::Row) -> BbTeamMember {
+    BbTeamMember {
+        account_id: row.get::<_, i64>(0) as u32,
+        team_id: row.get::<_, i64>(1) as u32,
+        guildcard: row.get::<_, i64>(2) as u32,
+        privilege: row.get::<_, i64>(3) as u32,
+        name: row.get(4)
+    }
+}
+
 // TEMPORARY -- REMOVE THESE ON RUSQLITE UPDATE
 #[inline(always)] fn b2i(a: bool) -> i64 { match a { true => 1, false => 0 }}
 #[inline(always)] fn i2b(a: i64) -> bool { match a { 0 => false, _ => true }}
@@ -162,7 +323,9 @@ impl Backend for Sqlite {
 
     fn fetch_bb_account_info(&self, account_id: u32) -> Result<Option<BbAccountInfo>> {
         let mut stmt = try_db!(self.conn.prepare(
-            "SELECT id,team_id,options,key_config,joy_config,shortcuts,symbol_chats FROM bb_guildcard WHERE account_id=? LIMIT 1"
+            "SELECT id,
+                COALESCE((SELECT team_id FROM bb_team_member WHERE bb_team_member.account_id=bb_guildcard.account_id), 0),
+                options,key_config,joy_config,shortcuts,symbol_chats FROM bb_guildcard WHERE account_id=? LIMIT 1"
         ));
 
         let mut results = try_db!(stmt.query_map(&[&(account_id as i64)], |row| {
@@ -214,6 +377,15 @@ impl Backend for Sqlite {
         };
 
         debug!("Account info for {} retrieved", account_id);
+
+        // Then their team, if they have one.
+        let (team_priv, team_name) = match try!(self.fetch_bb_team_member(account_id)) {
+            Some(m) => match try!(self.fetch_bb_team(m.team_id)) {
+                Some(t) => (m.privilege as u16, t.name),
+                None => (0, "".to_string())
+            },
+            None => (0, "".to_string())
+        };
         let mut query = try_db!(self.conn.prepare("SELECT
             inventory,
             char_data,
@@ -231,11 +403,11 @@ impl Backend for Sqlite {
                 unk: vec![0; 276],
                 key_config: acc_info.key_config.clone(),
                 joy_config: acc_info.joy_config.clone(),
-                guildcard: 0, // TODO no teams yet
-                team_id: 0,
+                guildcard: acc_info.guildcard_num,
+                team_id: acc_info.team_id,
                 team_info: (0, 0),
-                team_priv: 0,
-                team_name: "".to_string(),
+                team_priv: team_priv,
+                team_name: team_name.clone(),
                 team_flag: vec![0; 2048],
                 team_rewards: 0
             };
@@ -248,7 +420,7 @@ impl Backend for Sqlite {
                 bank: try_db!(Serial::deserialize(&mut Cursor::new(row.get::<_, Vec<u8>>(3)))),
                 guildcard: acc_info.guildcard_num,
                 name: chara.name.clone(),
-                team_name: "".to_string(), // TODO no teams yet
+                team_name: team_name.clone(),
                 guildcard_desc: row.get::<_, String>(4),
                 reserved1: 1,
                 reserved2: 1,
@@ -382,9 +554,49 @@ impl Backend for Sqlite {
             try_db!(stmt.execute_named(params));
         }
 
+        if !try!(self.put_bb_character_name(account_id as u32, slot as u8, &chara.chara.name)) {
+            warn!("Character at {} for account {} has the same name as another, {:?}; it isn't reserved", slot, account_id, chara.chara.name);
+        }
+
         Ok(())
     }
 
+    fn fetch_bb_common_bank(&self, account_id: u32) -> Result<ItemBank> {
+        let mut stmt = try_db!(self.conn.prepare("SELECT bank FROM bb_common_bank WHERE account_id=? LIMIT 1"));
+        let mut results = try_db!(stmt.query_map(&[&(account_id as i64)], |row| row.get::<_, Vec<u8>>(0)));
+        match results.next() {
+            Some(Ok(data)) => Ok(try_db!(Serial::deserialize(&mut Cursor::new(data)))),
+            Some(Err(e)) => Err(Error::BackendError(Some(Box::new(e)))),
+            None => Ok(ItemBank::default())
+        }
+    }
+
+    fn put_bb_character_and_common_bank(&self, account_id: u32, slot: u8, chara: BbFullCharData, bank: &ItemBank) -> Result<()> {
+        self.in_transaction(|| {
+            self.put_bb_character(account_id, slot, chara, false)
+                .and_then(|_| self.put_bb_common_bank(account_id, bank))
+        })
+    }
+
+    fn delete_bb_character(&self, account_id: u32, slot: u8, recoverable: bool) -> Result<()> {
+        self.in_transaction(|| self.delete_bb_character_now(account_id, slot, recoverable))
+    }
+
+    fn restore_bb_character(&self, account_id: u32, slot: u8, max_age: u64) -> Result<bool> {
+        self.in_transaction(|| self.restore_bb_character_now(account_id, slot, max_age))
+    }
+
+    fn purge_deleted_bb_characters(&self, max_age: u64) -> Result<u32> {
+        let mut stmt = try_db!(self.conn.prepare(&format!("DELETE FROM bb_deleted_character WHERE deleted_at < {now} - ?", now=NOW)));
+        let max_age = max_age as i64;
+        let n = try_db!(stmt.execute(&[&max_age]));
+        Ok(n as u32)
+    }
+
+    fn reserve_bb_character_name(&self, name: &str, account_id: u32, slot: u8) -> Result<bool> {
+        self.in_transaction(|| self.put_bb_character_name(account_id, slot, name))
+    }
+
     fn set_bb_login_flags(&self, account_id: u32, flags: u32) -> Result<()> {
         let mut stmt = try_db!(self.conn.prepare("INSERT OR UPDATE INTO bb_flags (account_id,login_flags) VALUES (?,?)"));
         let aid = account_id as i64;
@@ -405,6 +617,106 @@ impl Backend for Sqlite {
             None => Ok(0)
         }
     }
+
+    fn fetch_bb_guildcard_file(&self, account_id: u32) -> Result<BbGuildCardFile> {
+        let mut stmt = try_db!(self.conn.prepare("SELECT data FROM bb_guildcard_file WHERE account_id=?"));
+        let aid = account_id as i64;
+        let mut results = try_db!(stmt.query_map(&[&aid], |row| {
+            row.get::<_, Vec<u8>>(0)
+        }));
+        match results.next() {
+            Some(Ok(data)) => Ok(try_db!(Serial::deserialize(&mut Cursor::new(data)))),
+            Some(Err(e)) => Err(Error::BackendError(Some(Box::new(e)))),
+            None => Ok(BbGuildCardFile::default())
+        }
+    }
+
+    fn put_bb_guildcard_file(&self, account_id: u32, file: &BbGuildCardFile) -> Result<()> {
+        let mut stmt = try_db!(self.conn.prepare("INSERT OR REPLACE INTO bb_guildcard_file (account_id,data) VALUES (?,?)"));
+        let aid = account_id as i64;
+        let data = serial_to_vec(file);
+        try_db!(stmt.execute(&[&aid, &data]));
+        Ok(())
+    }
+
+    fn create_bb_team(&self, name: &str) -> Result<Option<u32>> {
+        let mut stmt = try_db!(self.conn.prepare("INSERT OR IGNORE INTO bb_team (name) VALUES (?)"));
+        let n = try_db!(stmt.execute(&[&name]));
+        if n == 0 {
+            return Ok(None)
+        }
+        Ok(Some(self.conn.last_insert_rowid() as u32))
+    }
+
+    fn fetch_bb_team(&self, team_id: u32) -> Result<Option<BbTeam>> {
+        let mut stmt = try_db!(self.conn.prepare("SELECT name FROM bb_team WHERE id=? LIMIT 1"));
+        let tid = team_id as i64;
+        let mut results = try_db!(stmt.query_map(&[&tid], |row| {
+            BbTeam {
+                id: team_id,
+                name: row.get(0)
+            }
+        }));
+        match results.next() {
+            Some(Ok(t)) => Ok(Some(t)),
+            Some(Err(e)) => Err(Error::BackendError(Some(Box::new(e)))),
+            None => Ok(None)
+        }
+    }
+
+    fn delete_bb_team(&self, team_id: u32) -> Result<()> {
+        self.in_transaction(|| {
+            let tid = team_id as i64;
+            let mut stmt = try_db!(self.conn.prepare("DELETE FROM bb_team_member WHERE team_id=?"));
+            try_db!(stmt.execute(&[&tid]));
+            let mut stmt = try_db!(self.conn.prepare("DELETE FROM bb_team WHERE id=?"));
+            try_db!(stmt.execute(&[&tid]));
+            Ok(())
+        })
+    }
+
+    fn fetch_bb_team_member(&self, account_id: u32) -> Result<Option<BbTeamMember>> {
+        let mut stmt = try_db!(self.conn.prepare(&format!("{} WHERE m.account_id=? LIMIT 1", TEAM_MEMBER_SELECT)));
+        let aid = account_id as i64;
+        let mut results = try_db!(stmt.query_map(&[&aid], team_member_from_row));
+        match results.next() {
+            Some(Ok(m)) => Ok(Some(m)),
+            Some(Err(e)) => Err(Error::BackendError(Some(Box::new(e)))),
+            None => Ok(None)
+        }
+    }
+
+    fn fetch_bb_team_members(&self, team_id: u32) -> Result<Vec<BbTeamMember>> {
+        let mut stmt = try_db!(self.conn.prepare(&format!("{} WHERE m.team_id=? ORDER BY m.id", TEAM_MEMBER_SELECT)));
+        let tid = team_id as i64;
+        let results = try_db!(stmt.query_map(&[&tid], team_member_from_row));
+        let mut members = Vec::new();
+        for r in results {
+            members.push(try_db!(r));
+        }
+        Ok(members)
+    }
+
+    fn put_bb_team_member(&self, member: &BbTeamMember) -> Result<()> {
+        let aid = member.account_id as i64;
+        let tid = member.team_id as i64;
+        let privilege = member.privilege as i64;
+        // Update in place so members keep their place in the list.
+        let mut stmt = try_db!(self.conn.prepare("UPDATE bb_team_member SET team_id=?,privilege=?,name=? WHERE account_id=?"));
+        let n = try_db!(stmt.execute(&[&tid, &privilege, &member.name, &aid]));
+        if n == 0 {
+            let mut stmt = try_db!(self.conn.prepare("INSERT INTO bb_team_member (account_id,team_id,privilege,name) VALUES (?,?,?,?)"));
+            try_db!(stmt.execute(&[&aid, &tid, &privilege, &member.name]));
+        }
+        Ok(())
+    }
+
+    fn remove_bb_team_member(&self, account_id: u32) -> Result<()> {
+        let mut stmt = try_db!(self.conn.prepare("DELETE FROM bb_team_member WHERE account_id=?"));
+        let aid = account_id as i64;
+        try_db!(stmt.execute(&[&aid]));
+        Ok(())
+    }
 }
 
 fn serial_to_vec<S: Serial>(i: &S) -> Vec<u8> {
@@ -25,10 +25,25 @@ CREATE TABLE IF NOT EXISTS bb_guildcard (
     symbol_chats BLOB NOT NULL
 );
 
+CREATE TABLE IF NOT EXISTS bb_guildcard_file (
+    account_id INTEGER PRIMARY KEY NOT NULL,
+    data BLOB NOT NULL
+);
+
 CREATE TABLE IF NOT EXISTS bb_team (
     id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
     name TEXT NOT NULL
 );
+CREATE UNIQUE INDEX IF NOT EXISTS bb_team_name_idx ON bb_team (name);
+
+CREATE TABLE IF NOT EXISTS bb_team_member (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    account_id INTEGER UNIQUE NOT NULL,
+    team_id INTEGER NOT NULL,
+    privilege INTEGER NOT NULL DEFAULT 0,
+    name TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS bb_team_member_team_idx ON bb_team_member (team_id);
 
 CREATE TABLE IF NOT EXISTS bb_character (
     id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
@@ -46,6 +61,35 @@ CREATE TABLE IF NOT EXISTS bb_character (
     quest_data2 BLOB
 );
 
+CREATE TABLE IF NOT EXISTS bb_character_name (
+    account_id INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    PRIMARY KEY (account_id, slot)
+);
+
+CREATE TABLE IF NOT EXISTS bb_deleted_character (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    account_id INTEGER NOT NULL DEFAULT 0,
+    slot INTEGER NOT NULL DEFAULT 0,
+    deleted_at INTEGER NOT NULL DEFAULT 0,
+    inventory BLOB,
+    char_data BLOB,
+    quest_data1 BLOB,
+    bank BLOB,
+    guildcard_desc TEXT,
+    autoreply TEXT,
+    infoboard TEXT,
+    challenge_data BLOB,
+    tech_menu BLOB,
+    quest_data2 BLOB
+);
+
+CREATE TABLE IF NOT EXISTS bb_common_bank (
+    account_id INTEGER PRIMARY KEY NOT NULL,
+    bank BLOB NOT NULL
+);
+
 CREATE TABLE IF NOT EXISTS bb_account_flags (
     account_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
     login_flags INTEGER NOT NULL DEFAULT 0
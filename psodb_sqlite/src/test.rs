@@ -1,10 +1,13 @@
 use super::Sqlite;
-use super::super::Backend;
-use psodb_common::account::Account;
+use psodb_common::Backend;
+use psodb_common::account::{Account, BbAccountInfo};
+use psodb_common::team::{BbTeamMember, PRIV_MEMBER, PRIV_LEADER, PRIV_MASTER};
+use psodata::chara::{BbFullCharData, ItemBank};
+use psodata::guildcard::{BbGuildCard, BbGuildCardFile};
 
 #[test]
 fn create_account() {
-    let s = Sqlite::new(":memory:", new).unwrap();
+    let s = Sqlite::new(":memory:", true).unwrap();
 
     let mut a = Account::new("testuser", "testpassword", "pourthesalt");
 
@@ -13,7 +16,7 @@ fn create_account() {
 
 #[test]
 fn fetch_account_by_id() {
-    let s = Sqlite::new(":memory:", new).unwrap();
+    let s = Sqlite::new(":memory:", true).unwrap();
 
     let mut a = Account::new("testuser", "testpassword", "pourthesalt");
 
@@ -30,7 +33,7 @@ fn fetch_account_by_id() {
 
 #[test]
 fn fetch_account_by_username() {
-    let s = Sqlite::new(":memory:", new).unwrap();
+    let s = Sqlite::new(":memory:", true).unwrap();
 
     let mut a = Account::new("testuser", "testpassword", "pourthesalt");
 
@@ -42,3 +45,272 @@ fn fetch_account_by_username() {
 
     assert_eq!(a.id, Some(id));
 }
+
+/// Make an account with Blue Burst account info, yielding its ID.
+fn bb_account(s: &Sqlite, username: &str) -> u32 {
+    let mut a = Account::new(username, "testpassword", "pourthesalt");
+    s.put_account(&mut a).unwrap();
+    let mut info = BbAccountInfo::new();
+    info.account_id = a.id.unwrap();
+    s.put_bb_account_info(&info).unwrap();
+    a.id.unwrap()
+}
+
+fn bank_with_meseta(meseta: u32) -> ItemBank {
+    let mut bank = ItemBank::default();
+    bank.meseta = meseta;
+    bank
+}
+
+#[test]
+fn fetch_bb_common_bank() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let id = bb_account(&s, "testuser");
+
+    // Accounts start with an empty common bank
+    let bank = s.fetch_bb_common_bank(id).unwrap();
+    assert_eq!(bank.meseta, 0);
+    assert!(bank.items.is_empty());
+
+    let mut chara = BbFullCharData::default();
+    chara.chara.meseta = 100;
+    s.put_bb_character_and_common_bank(id, 0, chara, &bank_with_meseta(500)).unwrap();
+
+    assert_eq!(s.fetch_bb_common_bank(id).unwrap().meseta, 500);
+    assert_eq!(s.fetch_bb_character(id, 0).unwrap().unwrap().chara.meseta, 100);
+
+    // Other accounts don't share it
+    let other = bb_account(&s, "otheruser");
+    assert_eq!(s.fetch_bb_common_bank(other).unwrap().meseta, 0);
+}
+
+#[test]
+fn put_bb_character_and_common_bank_is_atomic() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let id = bb_account(&s, "testuser");
+
+    let mut chara = BbFullCharData::default();
+    chara.chara.meseta = 100;
+    s.put_bb_character_and_common_bank(id, 0, chara.clone(), &bank_with_meseta(500)).unwrap();
+
+    // Make saving the bank fail after the character has been written.
+    s.conn.execute_batch("DROP TABLE bb_common_bank").unwrap();
+    chara.chara.meseta = 600;
+    assert!(s.put_bb_character_and_common_bank(id, 0, chara, &bank_with_meseta(0)).is_err());
+
+    // The character save was rolled back along with it.
+    assert_eq!(s.fetch_bb_character(id, 0).unwrap().unwrap().chara.meseta, 100);
+}
+
+#[test]
+fn delete_and_restore_bb_character() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let id = bb_account(&s, "testuser");
+
+    let mut chara = BbFullCharData::default();
+    chara.chara.meseta = 100;
+    s.put_bb_character(id, 1, chara.clone(), false).unwrap();
+    s.delete_bb_character(id, 1, true).unwrap();
+    assert!(s.fetch_bb_character(id, 1).unwrap().is_none());
+
+    assert!(s.restore_bb_character(id, 1, 86400).unwrap());
+    assert_eq!(s.fetch_bb_character(id, 1).unwrap().unwrap().chara.meseta, 100);
+    // It's only kept once
+    assert!(!s.restore_bb_character(id, 1, 86400).unwrap());
+
+    // A character made in the slot since isn't overwritten.
+    s.delete_bb_character(id, 1, true).unwrap();
+    chara.chara.meseta = 200;
+    s.put_bb_character(id, 1, chara, false).unwrap();
+    assert!(!s.restore_bb_character(id, 1, 86400).unwrap());
+    assert_eq!(s.fetch_bb_character(id, 1).unwrap().unwrap().chara.meseta, 200);
+
+    // Without recovery, nothing is kept, and the last character that was
+    // kept comes back instead.
+    s.delete_bb_character(id, 1, false).unwrap();
+    assert!(s.restore_bb_character(id, 1, 86400).unwrap());
+    assert_eq!(s.fetch_bb_character(id, 1).unwrap().unwrap().chara.meseta, 100);
+}
+
+fn named(name: &str) -> BbFullCharData {
+    let mut chara = BbFullCharData::default();
+    chara.chara.name = name.to_string();
+    chara
+}
+
+#[test]
+fn reserve_bb_character_name() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let a = bb_account(&s, "testuser");
+    let b = bb_account(&s, "otheruser");
+
+    assert!(s.reserve_bb_character_name("\tEAlice", a, 0).unwrap());
+    // Names are compared without the tag and ignoring case.
+    assert!(!s.reserve_bb_character_name("\tJALICE", b, 0).unwrap());
+    assert!(!s.reserve_bb_character_name("alice", a, 1).unwrap());
+    // The slot that has it can have it again.
+    assert!(s.reserve_bb_character_name("Alice", a, 0).unwrap());
+
+    // A new reservation for a slot replaces its old one.
+    assert!(s.reserve_bb_character_name("Alicia", a, 0).unwrap());
+    assert!(s.reserve_bb_character_name("Alice", b, 0).unwrap());
+}
+
+#[test]
+fn bb_character_names_follow_characters() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let a = bb_account(&s, "testuser");
+    let b = bb_account(&s, "otheruser");
+
+    s.put_bb_character(a, 0, named("\tEAlice"), false).unwrap();
+    assert!(!s.reserve_bb_character_name("Alice", b, 0).unwrap());
+
+    // Reserving a name for a character made over Alice keeps the reservation
+    // through her deletion.
+    assert!(s.reserve_bb_character_name("Bob", a, 0).unwrap());
+    s.delete_bb_character(a, 0, true).unwrap();
+    assert!(!s.reserve_bb_character_name("Bob", b, 0).unwrap());
+    s.put_bb_character(a, 0, named("\tEBob"), false).unwrap();
+
+    // Deleting a character releases its name, and it can't come back once
+    // the name has been taken.
+    s.put_bb_character(a, 1, named("\tECarol"), false).unwrap();
+    s.delete_bb_character(a, 1, true).unwrap();
+    assert!(s.reserve_bb_character_name("Carol", b, 0).unwrap());
+    assert!(!s.restore_bb_character(a, 1, 86400).unwrap());
+    assert!(s.fetch_bb_character(a, 1).unwrap().is_none());
+}
+
+#[test]
+fn backfill_bb_character_names() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let a = bb_account(&s, "testuser");
+    let b = bb_account(&s, "otheruser");
+
+    // Characters saved before names were recorded
+    s.put_bb_character(a, 2, named("\tECarol"), false).unwrap();
+    s.conn.execute_batch("DELETE FROM bb_character_name").unwrap();
+    assert!(s.reserve_bb_character_name("Carol", b, 0).unwrap());
+    assert!(s.reserve_bb_character_name("Dave", b, 0).unwrap());
+
+    s.backfill_bb_character_names().unwrap();
+    assert!(!s.reserve_bb_character_name("Carol", b, 1).unwrap());
+}
+
+fn guildcard(num: u32, name: &str) -> BbGuildCard {
+    let mut card = BbGuildCard::default();
+    card.guildcard = num;
+    card.name = name.to_string();
+    card
+}
+
+#[test]
+fn fetch_bb_guildcard_file() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let id = bb_account(&s, "testuser");
+
+    // Accounts start with an empty file
+    assert_eq!(s.fetch_bb_guildcard_file(id).unwrap(), BbGuildCardFile::default());
+
+    let mut file = BbGuildCardFile::default();
+    assert!(file.add(guildcard(42000001, "\tEAlice")));
+    assert!(file.add(guildcard(42000002, "\tEBob")));
+    file.entries[1].comment = "met in forest".to_string();
+    file.blocked.push(guildcard(42000003, "\tECarol"));
+    s.put_bb_guildcard_file(id, &file).unwrap();
+    assert_eq!(s.fetch_bb_guildcard_file(id).unwrap(), file);
+
+    // Saving again replaces the file
+    assert!(file.remove(42000001));
+    s.put_bb_guildcard_file(id, &file).unwrap();
+    let fetched = s.fetch_bb_guildcard_file(id).unwrap();
+    assert_eq!(fetched.entries.len(), 1);
+    assert_eq!(fetched, file);
+
+    // Other accounts don't share it
+    let other = bb_account(&s, "otheruser");
+    assert_eq!(s.fetch_bb_guildcard_file(other).unwrap(), BbGuildCardFile::default());
+}
+
+fn team_member(account_id: u32, team_id: u32, privilege: u32, name: &str) -> BbTeamMember {
+    BbTeamMember {
+        account_id: account_id,
+        team_id: team_id,
+        guildcard: 0,
+        privilege: privilege,
+        name: name.to_string()
+    }
+}
+
+#[test]
+fn create_and_delete_bb_team() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+
+    let team = s.create_bb_team("Hunters").unwrap().unwrap();
+    assert_eq!(s.fetch_bb_team(team).unwrap().unwrap().name, "Hunters");
+    // Team names are unique
+    assert!(s.create_bb_team("Hunters").unwrap().is_none());
+    let other = s.create_bb_team("Rangers").unwrap().unwrap();
+    assert!(other != team);
+
+    let a = bb_account(&s, "testuser");
+    let b = bb_account(&s, "otheruser");
+    s.put_bb_team_member(&team_member(a, team, PRIV_MASTER, "Alice")).unwrap();
+    s.put_bb_team_member(&team_member(b, other, PRIV_MASTER, "Bob")).unwrap();
+
+    // Deleting a team takes its members with it, and frees the name.
+    s.delete_bb_team(team).unwrap();
+    assert!(s.fetch_bb_team(team).unwrap().is_none());
+    assert!(s.fetch_bb_team_member(a).unwrap().is_none());
+    assert!(s.fetch_bb_team_member(b).unwrap().is_some());
+    assert!(s.create_bb_team("Hunters").unwrap().is_some());
+}
+
+#[test]
+fn bb_team_members() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let a = bb_account(&s, "testuser");
+    let b = bb_account(&s, "otheruser");
+    let c = bb_account(&s, "thirduser");
+    let team = s.create_bb_team("Hunters").unwrap().unwrap();
+
+    assert!(s.fetch_bb_team_member(a).unwrap().is_none());
+    assert!(s.fetch_bb_team_members(team).unwrap().is_empty());
+
+    s.put_bb_team_member(&team_member(a, team, PRIV_MASTER, "Alice")).unwrap();
+    s.put_bb_team_member(&team_member(b, team, PRIV_MEMBER, "Bob")).unwrap();
+    s.put_bb_team_member(&team_member(c, team, PRIV_MEMBER, "Carol")).unwrap();
+
+    // Members are fetched with their guild card numbers.
+    let m = s.fetch_bb_team_member(b).unwrap().unwrap();
+    assert_eq!(m.team_id, team);
+    assert_eq!(m.privilege, PRIV_MEMBER);
+    assert_eq!(m.name, "Bob");
+    assert_eq!(m.guildcard, s.fetch_bb_account_info(b).unwrap().unwrap().guildcard_num);
+
+    // Changing a member's rank keeps their place in the list.
+    s.put_bb_team_member(&team_member(b, team, PRIV_LEADER, "Bob")).unwrap();
+    let members = s.fetch_bb_team_members(team).unwrap();
+    let ids: Vec<u32> = members.iter().map(|m| m.account_id).collect();
+    assert_eq!(ids, vec![a, b, c]);
+    assert_eq!(members[1].privilege, PRIV_LEADER);
+
+    s.remove_bb_team_member(b).unwrap();
+    assert!(s.fetch_bb_team_member(b).unwrap().is_none());
+    assert_eq!(s.fetch_bb_team_members(team).unwrap().len(), 2);
+}
+
+#[test]
+fn delete_bb_team_is_atomic() {
+    let s = Sqlite::new(":memory:", true).unwrap();
+    let a = bb_account(&s, "testuser");
+    let team = s.create_bb_team("Hunters").unwrap().unwrap();
+    s.put_bb_team_member(&team_member(a, team, PRIV_MASTER, "Alice")).unwrap();
+
+    // Make deleting the team fail after its members have been removed.
+    s.conn.execute_batch("CREATE TRIGGER keep_teams BEFORE DELETE ON bb_team BEGIN SELECT RAISE(FAIL, 'kept'); END").unwrap();
+    assert!(s.delete_bb_team(team).is_err());
+
+    // The members were put back along with it.
+    assert_eq!(s.fetch_bb_team_member(a).unwrap().unwrap().team_id, team);
+}
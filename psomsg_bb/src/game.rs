@@ -187,6 +187,30 @@ impl Serial for BbPlayerLeaveGame {
     }
 }
 
+/// Indices, into the party's enemy list, of the enemies that spawn as their
+/// rare form. The client has room for 16; unused entries are 0xFFFF.
+#[derive(Clone, Debug, Default)]
+pub struct BbRareMonsterList(pub Vec<u16>);
+impl Serial for BbRareMonsterList {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        for i in 0..16 {
+            try!(self.0.get(i).cloned().unwrap_or(0xFFFF).serialize(dst));
+        }
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let mut enemies = Vec::new();
+        for _ in 0..16 {
+            let e: u16 = try!(Serial::deserialize(src));
+            if e != 0xFFFF {
+                enemies.push(e);
+            }
+        }
+        Ok(BbRareMonsterList(enemies))
+    }
+}
+
 derive_serial! {
     BbGameLeave {
         pub client_id: u8,
@@ -28,6 +28,8 @@ pub mod lobby;
 pub mod player;
 pub mod subcmd;
 pub mod game;
+pub mod team;
+pub mod quest;
 
 pub use self::msgs::*;
 pub use psomsg_common::*;
@@ -37,6 +39,8 @@ pub use self::lobby::*;
 pub use self::player::*;
 pub use self::subcmd::*;
 pub use self::game::*;
+pub use self::team::*;
+pub use self::quest::*;
 
 macro_rules! gen_message_enum {
     ($($id:expr => $name:ident),*) => {
@@ -161,8 +165,10 @@ gen_message_enum! {
     0x0007 => BlockList,
     0x0008 => BbGameList,
     0x0010 => MenuSelect,
+    0x0013 => BbQuestChunk,
     0x0011 => BbInfoReply,
     0x0019 => Redirect,
+    0x0044 => BbQuestFile,
     0x0060 => BbSubCmd60,
     0x0061 => BbCharDat,
     0x0062 => BbSubCmd62,
@@ -185,8 +191,13 @@ gen_message_enum! {
     0x001A => LargeMsg,
     0x001D => Ping,
     0x00A0 => ShipList,
+    // BB only plays quests online, from the guild counter. It has no memory
+    // card to keep quests on, so it has no download quest menu (0xA4).
+    0x00A2 => BbQuestList,
+    0x00AC => BbQuestLoadDone,
     0x00B1 => Timestamp,
     0x00C1 => BbCreateGame,
+    0x00DE => BbRareMonsterList,
     0x01DC => BbGuildCardHdr,
     0x02DC => BbGuildCardChunk,
     0x03DC => BbGuildCardChunkReq,
@@ -201,6 +212,16 @@ gen_message_enum! {
     0x02E8 => BbChecksumAck,
     0x03E8 => BbGuildRequest,
     0x04E8 => BbAddGuildCard,
+    0x01EA => BbTeamCreate,
+    0x02EA => BbTeamCreateAck,
+    0x03EA => BbTeamAddMember,
+    0x04EA => BbTeamAddMemberAck,
+    0x05EA => BbTeamRemoveMember,
+    0x06EA => BbTeamRemoveMemberAck,
+    0x08EA => BbTeamMemberListReq,
+    0x09EA => BbTeamMemberList,
+    0x10EA => BbTeamDisband,
+    0x11EA => BbTeamChangePriv,
     0x15EA => BbTeamInfo,
     0x01EB => BbParamHdr,
     0x02EB => BbParamChunk,
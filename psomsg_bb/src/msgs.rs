@@ -565,6 +565,28 @@ impl Default for BbSecurityData {
     }
 }
 
+impl BbSecurityData {
+    /// The login session token, kept in the first four reserved bytes so the
+    /// client carries it across redirects.
+    pub fn session_token(&self) -> u32 {
+        let r = &self.reserved;
+        if r.len() < 4 {
+            return 0
+        }
+        (r[0] as u32) | (r[1] as u32) << 8 | (r[2] as u32) << 16 | (r[3] as u32) << 24
+    }
+
+    pub fn set_session_token(&mut self, token: u32) {
+        if self.reserved.len() < 4 {
+            self.reserved.resize(34, 0);
+        }
+        self.reserved[0] = token as u8;
+        self.reserved[1] = (token >> 8) as u8;
+        self.reserved[2] = (token >> 16) as u8;
+        self.reserved[3] = (token >> 24) as u8;
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BbChat(pub u32, pub String);
 impl Serial for BbChat {
@@ -0,0 +1,122 @@
+//! Quest counter menus (0xA2) and quest file downloads (0x44, 0x13). Clients
+//! acknowledge each file and chunk by sending the message back with only the
+//! file name, and send 0xAC once the quest is loaded. The server answers with
+//! its own 0xAC when everyone in the party has.
+
+use std::io;
+use std::io::{Read, Write};
+
+use psoserial::Serial;
+use psomsg_common::util::*;
+
+/// Bytes of a file sent in each chunk.
+pub const QUEST_CHUNK_SIZE: usize = 0x400;
+
+/// A menu of quest categories, or of the quests in one. The client asks for
+/// the menu with an empty one.
+#[derive(Clone, Debug, Default)]
+pub struct BbQuestList {
+    pub entries: Vec<BbQuestListEntry>
+}
+impl Serial for BbQuestList {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        for e in self.entries.iter() {
+            try!(e.serialize(dst));
+        }
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        loop {
+            match BbQuestListEntry::deserialize(src) {
+                Ok(e) => entries.push(e),
+                Err(_) => break
+            }
+        }
+        Ok(BbQuestList { entries: entries })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BbQuestListEntry {
+    pub menu_id: u32,
+    pub item_id: u32,
+    pub name: String,
+    pub description: String
+}
+impl Serial for BbQuestListEntry {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.menu_id.serialize(dst));
+        try!(self.item_id.serialize(dst));
+        try!(write_utf16_len(&self.name, 0x40, dst));
+        try!(write_utf16_len(&self.description, 0xF4, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let menu_id = try!(Serial::deserialize(src));
+        let item_id = try!(Serial::deserialize(src));
+        let name = try!(read_utf16_len(0x40, src));
+        let description = try!(read_utf16_len(0xF4, src));
+        Ok(BbQuestListEntry {
+            menu_id: menu_id,
+            item_id: item_id,
+            name: name,
+            description: description
+        })
+    }
+}
+
+/// Opens a file of a quest on the client.
+#[derive(Clone, Debug, Default)]
+pub struct BbQuestFile {
+    pub name: String,
+    pub filename: String,
+    pub length: u32
+}
+impl Serial for BbQuestFile {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(dst.write_all(&[0; 0x22]));
+        try!(2u16.serialize(dst)); // flags
+        try!(write_ascii_len(&self.filename, 0x10, dst));
+        try!(self.length.serialize(dst));
+        try!(write_ascii_len(&self.name, 0x18, dst));
+        Ok(())
+    }
+
+    /// Only the file name of an acknowledgement.
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbQuestFile {
+            filename: try!(read_ascii_len(0x10, src)),
+            .. Default::default()
+        })
+    }
+}
+
+/// A chunk of a quest file. The message flags are the chunk's number.
+#[derive(Clone, Debug, Default)]
+pub struct BbQuestChunk {
+    pub filename: String,
+    pub data: Vec<u8>
+}
+impl Serial for BbQuestChunk {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(write_ascii_len(&self.filename, 0x10, dst));
+        let len = ::std::cmp::min(self.data.len(), QUEST_CHUNK_SIZE);
+        try!(dst.write_all(&self.data[..len]));
+        try!(dst.write_all(&vec![0; QUEST_CHUNK_SIZE - len]));
+        try!((len as u32).serialize(dst));
+        Ok(())
+    }
+
+    /// Only the file name of an acknowledgement.
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbQuestChunk {
+            filename: try!(read_ascii_len(0x10, src)),
+            data: Vec::new()
+        })
+    }
+}
+
+derive_serial!(BbQuestLoadDone);
@@ -171,6 +171,40 @@ derive_serial_default! {
     }
 }
 
+derive_serial_default! {
+    Bb60EquipItem {
+        pub item_id: u32,
+        pub unk: u32
+    }
+}
+
+derive_serial_default! {
+    Bb60UnequipItem {
+        pub item_id: u32,
+        pub unk: u32
+    }
+}
+
+derive_serial_default! {
+    Bb60UseItem {
+        pub item_id: u32
+    }
+}
+
+derive_serial_default! {
+    Bb60FeedMag {
+        pub mag_id: u32,
+        pub item_id: u32
+    }
+}
+
+// Unused entries are 0xFFFFFFFF.
+derive_serial_default! {
+    Bb60SortItems {
+        pub item_ids: [u32; 30]
+    }
+}
+
 derive_serial_default! {
     Bb60DestroyItem {
         pub item_id: u32,
@@ -198,3 +232,38 @@ derive_serial_default! {
         pub item2: [u8; 4]
     }
 }
+
+// Puts an item the server generated on the floor. Source is 1 for enemies and
+// 2 for boxes; req is the enemy or object ID from the request.
+derive_serial_default! {
+    Bb60ItemDrop {
+        pub area: u8,
+        pub source: u8,
+        pub req: u16,
+        pub x: f32,
+        pub z: f32,
+        pub unk: u32,
+        pub item: [u8; 12],
+        pub item_id: u32,
+        pub item2: [u8; 4]
+    }
+}
+
+// Tells clients an item was picked up off the floor.
+derive_serial_default! {
+    Bb60PickedUp {
+        pub client_id: u16,
+        pub area: u16,
+        pub item_id: u32
+    }
+}
+
+// Puts an item in a client's inventory.
+derive_serial_default! {
+    Bb60CreateItem {
+        pub item: [u8; 12],
+        pub item_id: u32,
+        pub item2: [u8; 4],
+        pub unused: u32
+    }
+}
@@ -76,12 +76,62 @@ impl Serial for Bb62ItemReq {
     }
 }
 
+// Sent to the leader when a box is broken. The trailing fields echo the box's
+// map object parameters, which the server looks up itself.
+derive_serial_default! {
+    Bb62BoxItemReq {
+        pub area: u8,
+        pub pt_index: u8,
+        pub req: u16,
+        pub x: f32,
+        pub z: f32,
+        pub unk1: u32,
+        pub unk2: u16,
+        pub unk3: u16,
+        pub params: [u32; 3]
+    }
+}
+
 derive_serial_default! {
     Bb62OpenBank {
         pub unk: u32
     }
 }
 
+derive_serial_default! {
+    Bb62BankAction {
+        // 0xFFFFFFFF for meseta
+        pub item_id: u32,
+        pub meseta_amount: u32,
+        // 0 deposit, 1 withdraw, 3 close
+        pub action: u8,
+        pub item_amount: u8,
+        pub unused: u16
+    }
+}
+
+derive_serial_default! {
+    Bb62IdentifyItem {
+        pub item_id: u32
+    }
+}
+
+// What the weapon turned out to be. It isn't in the inventory until the
+// client accepts it.
+derive_serial_default! {
+    Bb62IdentifyResult {
+        pub item: [u8; 12],
+        pub item_id: u32,
+        pub item2: [u8; 4]
+    }
+}
+
+derive_serial_default! {
+    Bb62AcceptIdentify {
+        pub item_id: u32
+    }
+}
+
 derive_serial_default! {
     Bb62ShopReq {
         pub shop_type: u8,
@@ -89,6 +139,17 @@ derive_serial_default! {
     }
 }
 
+derive_serial_default! {
+    Bb62ShopBuy {
+        // The ID the client gave the bought item
+        pub item_id: u32,
+        pub shop_type: u8,
+        pub shop_index: u8,
+        pub amount: u8,
+        pub unk: u8
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Bb62ShopInv {
     pub shop_type: u8,
@@ -198,14 +198,22 @@ macro_rules! impl_subcmd_6d_enum {
 
 impl_subcmd_enum! { BbSubCmd60 =
     0x30 => Bb60LevelUp,
+    0x25 => Bb60EquipItem,
+    0x26 => Bb60UnequipItem,
+    0x27 => Bb60UseItem,
+    0x28 => Bb60FeedMag,
     0x29 => Bb60DeleteItem,
     0x2A => Bb60DropItem,
+    0x59 => Bb60PickedUp,
     0x5D => Bb60DropStack,
+    0x5F => Bb60ItemDrop,
     0x63 => Bb60DestroyItem,
     0x72 => Bb60DoneBurst,
     0x6F => QuestData1,
+    0xBE => Bb60CreateItem,
     0xBF => Bb60GiveExp,
     0xC3 => Bb60DropPos,
+    0xC4 => Bb60SortItems,
     0xC8 => Bb60ReqExp
 }
 
@@ -222,9 +230,15 @@ impl_subcmd_enum! { BbSubCmd6C =
 impl_subcmd_enum! { BbSubCmd62 =
     0x5A => Bb62PickUp,
     0x60 => Bb62ItemReq,
+    0xA2 => Bb62BoxItemReq,
     0xB5 => Bb62ShopReq,
     0xB6 => Bb62ShopInv,
-    0xBB => Bb62OpenBank
+    0xB7 => Bb62ShopBuy,
+    0xB8 => Bb62IdentifyItem,
+    0xB9 => Bb62IdentifyResult,
+    0xBA => Bb62AcceptIdentify,
+    0xBB => Bb62OpenBank,
+    0xBD => Bb62BankAction
 }
 
 impl_subcmd_6d_enum! { BbSubCmd6D =
@@ -0,0 +1,134 @@
+//! Team (0xEA) messages. Results of team actions are sent back in the flags of
+//! the matching acknowledgement, 0 meaning success. Disbanding and rank
+//! changes have no acknowledgement; the server sends a fresh BbTeamInfo.
+
+use std::io;
+use std::io::{Read, Write};
+
+use psoserial::Serial;
+use psomsg_common::util::*;
+
+/// Create a team with the given name.
+#[derive(Clone, Debug, Default)]
+pub struct BbTeamCreate(pub String);
+impl Serial for BbTeamCreate {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(write_utf16_len(&self.0, 32, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbTeamCreate(try!(read_utf16_len(32, src))))
+    }
+}
+
+derive_serial!(BbTeamCreateAck);
+
+/// Invite the player with the given guild card into the sender's team.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BbTeamAddMember(pub u32);
+impl Serial for BbTeamAddMember {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        self.0.serialize(dst)
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbTeamAddMember(try!(Serial::deserialize(src))))
+    }
+}
+
+derive_serial!(BbTeamAddMemberAck);
+
+/// Remove the player with the given guild card from the sender's team. A
+/// player sending their own guild card leaves the team.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BbTeamRemoveMember(pub u32);
+impl Serial for BbTeamRemoveMember {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        self.0.serialize(dst)
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbTeamRemoveMember(try!(Serial::deserialize(src))))
+    }
+}
+
+derive_serial!(BbTeamRemoveMemberAck);
+
+derive_serial!(BbTeamMemberListReq);
+
+#[derive(Clone, Debug, Default)]
+pub struct BbTeamMemberEntry {
+    /// 1-based position in the list.
+    pub index: u32,
+    pub priv_level: u32,
+    pub guildcard: u32,
+    pub name: String
+}
+impl Serial for BbTeamMemberEntry {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.index.serialize(dst));
+        try!(self.priv_level.serialize(dst));
+        try!(self.guildcard.serialize(dst));
+        try!(write_utf16_len(&self.name, 24, dst));
+        try!(0u32.serialize(dst));
+        try!(0u32.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let index = try!(Serial::deserialize(src));
+        let priv_level = try!(Serial::deserialize(src));
+        let guildcard = try!(Serial::deserialize(src));
+        let name = try!(read_utf16_len(24, src));
+        let _: u32 = try!(Serial::deserialize(src));
+        let _: u32 = try!(Serial::deserialize(src));
+        Ok(BbTeamMemberEntry {
+            index: index,
+            priv_level: priv_level,
+            guildcard: guildcard,
+            name: name
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BbTeamMemberList(pub Vec<BbTeamMemberEntry>);
+impl Serial for BbTeamMemberList {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(0u32.serialize(dst));
+        try!((self.0.len() as u32).serialize(dst));
+        try!((self.0.len() as u32).serialize(dst));
+        for e in self.0.iter() {
+            try!(e.serialize(dst));
+        }
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let _: u32 = try!(Serial::deserialize(src));
+        let count: u32 = try!(Serial::deserialize(src));
+        let _: u32 = try!(Serial::deserialize(src));
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            entries.push(try!(Serial::deserialize(src)));
+        }
+        Ok(BbTeamMemberList(entries))
+    }
+}
+
+derive_serial!(BbTeamDisband);
+
+/// Change the rank of the member with the given guild card. The new rank is
+/// in the message flags.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BbTeamChangePriv(pub u32);
+impl Serial for BbTeamChangePriv {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        self.0.serialize(dst)
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbTeamChangePriv(try!(Serial::deserialize(src))))
+    }
+}
@@ -15,7 +15,7 @@ use psomsg_common::util::*;
 
 use std::io::{Read, Write};
 use std::io;
-use std::net::{SocketAddrV4, Ipv4Addr};
+use std::net::{SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr};
 
 use byteorder::{LittleEndian as LE, BigEndian as BE, ReadBytesExt, WriteBytesExt};
 
@@ -193,11 +193,38 @@ derive_serial!(FileSend {
     pub filename: StaticVec<u8, U48>
 });
 
-derive_serial!(DataSend {
+/// A chunk of file data. `checksum` is the CRC32 of `data`, and `chunk_size`
+/// should equal its length.
+#[derive(Clone, Debug, Default)]
+pub struct DataSend {
     pub chunk_num: u32,
     pub checksum: u32,
-    pub chunk_size: u32
-});
+    pub chunk_size: u32,
+    pub data: Vec<u8>
+}
+impl Serial for DataSend {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(dst.write_u32::<LE>(self.chunk_num));
+        try!(dst.write_u32::<LE>(self.checksum));
+        try!(dst.write_u32::<LE>(self.chunk_size));
+        try!(dst.write_all(&self.data[..]));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let chunk_num = try!(src.read_u32::<LE>());
+        let checksum = try!(src.read_u32::<LE>());
+        let chunk_size = try!(src.read_u32::<LE>());
+        let mut data = vec![0u8; chunk_size as usize];
+        try!(read_exact(src, &mut data[..]));
+        Ok(DataSend {
+            chunk_num: chunk_num,
+            checksum: checksum,
+            chunk_size: chunk_size,
+            data: data
+        })
+    }
+}
 
 derive_serial!(FileDone { pub padding: u32 });
 derive_serial!(SetDirectory { pub dirname: StaticVec<u8, U64> });
@@ -247,4 +274,21 @@ impl Serial for Redirect {
     }
 }
 
-derive_serial!(Redirect6 { pub ip_addr: StaticVec<u8, U16>, pub port: u16, pub padding: u16 });
+#[derive(Clone, Debug)]
+pub struct Redirect6(pub SocketAddrV6);
+impl Serial for Redirect6 {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(dst.write_all(&self.0.ip().octets()));
+        try!(dst.write_u16::<BE>(self.0.port()));
+        try!(dst.write_u16::<LE>(0));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let mut octets = [0u8; 16];
+        try!(src.read_exact(&mut octets));
+        let port: u16 = try!(src.read_u16::<BE>());
+        try!(src.read_u16::<LE>());
+        Ok(Redirect6(SocketAddrV6::new(Ipv6Addr::from(octets), port, 0, 0)))
+    }
+}
@@ -3,6 +3,8 @@ IDOLA PSO Server.
 
 Usage:
     idola [options]
+    idola [options] delete-char <username> <slot>
+    idola [options] restore-char <username> <slot>
     idola (-h | --help)
     idola --version
 
@@ -14,6 +16,13 @@ Options:
 The config path defaults to 'idola.toml'. If no file exists, the program
 will immediately exit.
 
+delete-char deletes the character in a slot (0-3) of an account. If the
+shipgate's recover_days is set, it can be restored for that many days.
+
+restore-char brings back the character most recently deleted from a slot
+(0-3) on an account, using the shipgate database in the config. Deleted
+characters are only kept if the shipgate's recover_days is set.
+
 The configuration file describes what services to run in this instance of the
 server. There are several kinds of services. The config in
 data/default/conf_local.toml is configured to spin up all the required services
@@ -23,5 +32,9 @@ for PSOBB to connect and play. See the docs for more details.
 #[derive(Debug, Clone, RustcDecodable)]
 pub struct Args {
     pub flag_config: String,
-    pub flag_version: bool
+    pub flag_version: bool,
+    pub cmd_delete_char: bool,
+    pub cmd_restore_char: bool,
+    pub arg_username: String,
+    pub arg_slot: u8
 }
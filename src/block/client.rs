@@ -1,12 +1,22 @@
 use psomsg::bb::BbSecurityData;
 use psomsg::bb::BbFullCharData;
+use psomsg::bb::ItemBank;
 
 #[derive(Clone, Default)]
 pub struct ClientState {
     pub sec_data: BbSecurityData,
     pub account_id: u32,
     pub team_id: u32,
+    pub team_priv: u32,
+    pub team_name: String,
     pub bb_guildcard: u32,
     pub full_char: Option<BbFullCharData>,
-    pub connection_id: usize
+    pub connection_id: usize,
+    /// The (account id, token) of the session registered with the shipgate.
+    pub session: Option<(u32, u32)>,
+    /// The bank the character isn't using, which is the common bank unless
+    /// `common_bank_active` is set. The one in use is always `full_char`'s.
+    /// None until the common bank is first loaded.
+    pub other_bank: Option<ItemBank>,
+    pub common_bank_active: bool
 }
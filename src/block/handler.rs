@@ -2,6 +2,7 @@ use std::rc::Rc;
 use std::sync::Arc;
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::mem;
 //use std::fs::File;
 
 use mio::Sender;
@@ -10,22 +11,35 @@ use psomsg::bb::*;
 
 use psodata::battleparam::BattleParamTables;
 use psodata::leveltable::LevelTable;
+use psodata::itempmt::ItemPMT;
+use psodata::itemmagedit::ItemMagEdit;
 
 //use ::game::CharClass;
 use ::shipgate::client::callbacks::SgCbMgr;
+use ::shipgate::client::session::{SessionHandler, session_connect};
 use ::loop_handler::LoopMsg;
 use ::shipgate::msg::Message as Sgm;
 use ::shipgate::msg::BbLoginChallenge;
 use ::shipgate::msg::BbGetAccountInfo;
 use ::shipgate::msg::BbGetCharacter;
 use ::shipgate::msg::BbGetCharacterAck;
+use ::shipgate::msg::BbPutCharacter;
+use ::shipgate::msg::{BbGetCommonBank, BbPutCharacterAndCommonBank};
+use ::shipgate::msg::{BbTeamAck, BbTeamChanged, TEAM_NAME_TAKEN, TEAM_NOT_ALLOWED, TEAM_ALREADY_IN_TEAM, TEAM_NOT_IN_TEAM};
 use ::maps::Areas;
+use ::droptables::DropTable;
+use ::rates::SharedRates;
+use ::quests::Quests;
 
 use super::client::ClientState;
 use super::lobbyhandler::Lobby;
 use super::partyhandler::Party;
+use super::partyhandler::enemygen::RareRates;
+use super::partyhandler::mag;
 
 const MENU_GAME_LIST: u32 = 0x00080000;
+const MENU_QUEST_CATEGORY: u32 = 0x00090000;
+const MENU_QUEST: u32 = 0x000A0000;
 
 pub struct BlockHandler {
     sender: Sender<LoopMsg>,
@@ -38,6 +52,12 @@ pub struct BlockHandler {
     online_maps: Arc<Areas>,
     offline_maps: Arc<Areas>,
     pub level_table: Arc<LevelTable>,
+    pub drop_table: Arc<DropTable>,
+    pub item_pmt: Arc<ItemPMT>,
+    pub mag_edit: Arc<ItemMagEdit>,
+    pub quests: Arc<Quests>,
+    pub rare_rates: Arc<RareRates>,
+    pub rates: SharedRates,
     party_counter: Rc<Cell<u32>>
 }
 
@@ -52,6 +72,12 @@ impl BlockHandler {
                online_maps: Arc<Areas>,
                offline_maps: Arc<Areas>,
                level_table: Arc<LevelTable>,
+               drop_table: Arc<DropTable>,
+               item_pmt: Arc<ItemPMT>,
+               mag_edit: Arc<ItemMagEdit>,
+               quests: Arc<Quests>,
+               rare_rates: Arc<RareRates>,
+               rates: SharedRates,
                party_counter: Rc<Cell<u32>>) -> BlockHandler {
         BlockHandler {
             sender: sender,
@@ -64,6 +90,12 @@ impl BlockHandler {
             online_maps: online_maps,
             offline_maps: offline_maps,
             level_table: level_table,
+            drop_table: drop_table,
+            item_pmt: item_pmt,
+            mag_edit: mag_edit,
+            quests: quests,
+            rare_rates: rare_rates,
+            rates: rates,
             party_counter: party_counter
         }
     }
@@ -125,36 +157,41 @@ impl BlockHandler {
                 }
 
                 let sec_data = sec_data.clone();
+                let account_id = a.account_id;
+                let token = sec_data.session_token();
+                session_connect(&mut h, account_id, token, false, move|mut h| {
+                    let sec_data = sec_data.clone();
 
-                let sgm: Sgm = BbGetAccountInfo { account_id: a.account_id }.into();
-                h.sg_sender.request(h.client_id, sgm, move|mut h, m| {
-                    if let Sgm::BbGetAccountInfoAck(_, a) = m {
-                        let r = Message::BbSecurity(0, BbSecurity {
-                            err_code: 0,
-                            tag: 0x00010000,
-                            guildcard: a.guildcard_num,
-                            team_id: 0xFFFFFFFF,
-                            security_data: sec_data.clone(),
-                            caps: 0x00000101
-                        });
-                        h.sender.send((h.client_id, r).into()).unwrap();
-
-                        let cr = h.get_client_state(h.client_id).unwrap();
-                        let ref mut c = cr.borrow_mut();
-                        c.sec_data = sec_data.clone();
-                        c.team_id = a.team_id;
-                        c.bb_guildcard = a.guildcard_num;
-                        c.account_id = a.account_id;
-
-                        // We need to get their character now.
-                        let sgm: Sgm = BbGetCharacter { account_id: a.account_id, slot: sec_data.slot }.into();
-                        h.sg_sender.request(h.client_id, sgm, move |mut h, m| {
-                            if let Sgm::BbGetCharacterAck(_, body) = m {
-                                h.sg_get_character_ack(body)
-                            }
-                        }).unwrap();
-                    }
-                }).unwrap();
+                    let sgm: Sgm = BbGetAccountInfo { account_id: account_id }.into();
+                    h.sg_sender.request(h.client_id, sgm, move|mut h, m| {
+                        if let Sgm::BbGetAccountInfoAck(_, a) = m {
+                            let r = Message::BbSecurity(0, BbSecurity {
+                                err_code: 0,
+                                tag: 0x00010000,
+                                guildcard: a.guildcard_num,
+                                team_id: 0xFFFFFFFF,
+                                security_data: sec_data.clone(),
+                                caps: 0x00000101
+                            });
+                            h.sender.send((h.client_id, r).into()).unwrap();
+
+                            let cr = h.get_client_state(h.client_id).unwrap();
+                            let ref mut c = cr.borrow_mut();
+                            c.sec_data = sec_data.clone();
+                            c.team_id = a.team_id;
+                            c.bb_guildcard = a.guildcard_num;
+                            c.account_id = a.account_id;
+
+                            // We need to get their character now.
+                            let sgm: Sgm = BbGetCharacter { account_id: a.account_id, slot: sec_data.slot }.into();
+                            h.sg_sender.request(h.client_id, sgm, move |mut h, m| {
+                                if let Sgm::BbGetCharacterAck(_, body) = m {
+                                    h.sg_get_character_ack(body)
+                                }
+                            }).unwrap();
+                        }
+                    }).unwrap();
+                });
             } else {
                 warn!("Unexpected response from shipgate: {:?}", m);
                 h.sender.send(LoopMsg::DropClient(h.client_id)).unwrap();
@@ -205,6 +242,9 @@ impl BlockHandler {
 
             let r = Message::BbFullChar(0, BbFullChar(full_char.clone()));
             self.sender.send((self.client_id, r).into()).unwrap();
+            client_state.team_id = full_char.key_config.team_id;
+            client_state.team_priv = full_char.key_config.team_priv as u32;
+            client_state.team_name = full_char.key_config.team_name.clone();
             client_state.full_char = Some(full_char);
             let r = Message::CharDataRequest(0, CharDataRequest);
             self.sender.send((self.client_id, r).into()).unwrap();
@@ -328,9 +368,9 @@ impl BlockHandler {
         let pass: Option<&str> = if m.password.len() == 0 { None } else { Some(&m.password) };
         let mut p;
         if m.single_player > 0 {
-            p = Party::new(&m.name, pass, m.episode, m.difficulty, m.battle != 0, m.challenge != 0, true, event, self.offline_maps.clone(), unique_id);
+            p = Party::new(&m.name, pass, m.episode, m.difficulty, m.battle != 0, m.challenge != 0, true, event, self.offline_maps.clone(), &self.rare_rates, unique_id);
         } else {
-            p = Party::new(&m.name, pass, m.episode, m.difficulty, m.battle != 0, m.challenge != 0, false, event, self.online_maps.clone(), unique_id);
+            p = Party::new(&m.name, pass, m.episode, m.difficulty, m.battle != 0, m.challenge != 0, false, event, self.online_maps.clone(), &self.rare_rates, unique_id);
         }
 
         let cid = self.client_id;
@@ -529,6 +569,49 @@ impl BlockHandler {
         self.send_to_client(self.client_id, m);
     }
 
+    /// The categories of quests for the episode of the player's party. The
+    /// counter only offers quests once the party is back in free play, so
+    /// any quest they were on is over.
+    pub fn bb_quest_list(&mut self) {
+        let episode = {
+            let pr = self.parties.clone();
+            let mut parties = pr.borrow_mut();
+            match parties.iter_mut().find(|p| p.has_player(self.client_id)) {
+                Some(p) => {
+                    p.end_quest(self);
+                    if p.episode == 3 { 4 } else { p.episode }
+                },
+                None => {
+                    warn!("Client {} requested quests when they weren't in a party.", self.client_id);
+                    self.send_fatal_error(self.client_id, "\tEIllegal message.");
+                    return
+                }
+            }
+        };
+        let entries: Vec<BbQuestListEntry> = self.quests.categories.iter().enumerate()
+            .filter(|&(_, c)| c.episode == episode)
+            .map(|(i, c)| BbQuestListEntry {
+                menu_id: MENU_QUEST_CATEGORY,
+                item_id: i as u32,
+                name: c.name.clone(),
+                description: c.description.clone()
+            }).collect();
+        let m = Message::BbQuestList(entries.len() as u32, BbQuestList { entries: entries });
+        self.send_to_client(self.client_id, m);
+    }
+
+    pub fn bb_quest_load_done(&mut self) {
+        let pr = self.parties.clone();
+        let mut parties = pr.borrow_mut();
+        for p in parties.iter_mut() {
+            if p.has_player(self.client_id) {
+                p.handle_bb_quest_load_done(self).unwrap();
+                return
+            }
+        }
+        warn!("Client {} finished loading a quest when they weren't in a party.", self.client_id);
+    }
+
     pub fn bb_player_leave_game(&mut self, _m: BbPlayerLeaveGame) {
         let pr = self.parties.clone();
         let ref mut parties = pr.borrow_mut();
@@ -562,6 +645,97 @@ impl BlockHandler {
         }
     }
 
+    /// Persist a client's character to the shipgate, if they have one loaded.
+    /// Once the common bank has been loaded it's saved along with them.
+    pub fn save_character(&mut self, client: usize) {
+        let cs = self.get_client_state(client).unwrap();
+        let ref client_state = cs.borrow();
+        let full_char = match client_state.full_char {
+            Some(ref fc) => fc,
+            None => return
+        };
+        let m = match client_state.other_bank {
+            Some(ref other) => {
+                let mut full_char = full_char.clone();
+                let common_bank = if client_state.common_bank_active {
+                    mem::replace(&mut full_char.bank, other.clone())
+                } else {
+                    other.clone()
+                };
+                Sgm::BbPutCharacterAndCommonBank(0, BbPutCharacterAndCommonBank {
+                    account_id: client_state.account_id,
+                    slot: client_state.sec_data.slot,
+                    full_char: full_char,
+                    common_bank: common_bank
+                })
+            },
+            None => Sgm::BbPutCharacter(0, BbPutCharacter {
+                account_id: client_state.account_id,
+                slot: client_state.sec_data.slot,
+                save_acct_data: 0,
+                full_char: full_char.clone()
+            })
+        };
+        self.sg_sender.send(m).unwrap();
+    }
+
+    /// Switch the client between their character's bank and the common bank
+    /// of their account, loading the common bank the first time.
+    pub fn switch_bank(&mut self, client: usize) {
+        let (account_id, loaded) = {
+            let cs = self.get_client_state(client).unwrap();
+            let ref client_state = cs.borrow();
+            (client_state.account_id, client_state.other_bank.is_some())
+        };
+        if loaded {
+            self.swap_banks(client);
+            return
+        }
+        self.sg_sender.request(client, BbGetCommonBank { account_id: account_id }, move|mut h, m| {
+            if let Sgm::BbGetCommonBankAck(_, a) = m {
+                if a.status != 0 {
+                    error!("Shipgate error retrieving common bank for account {}, status code {}", account_id, a.status);
+                    h.send_error(client, "\tEThe common bank\nisn't available.");
+                    return
+                }
+                {
+                    let cs = match h.get_client_state(client) {
+                        Some(cs) => cs,
+                        None => return
+                    };
+                    let mut client_state = cs.borrow_mut();
+                    if client_state.other_bank.is_some() {
+                        // Switched twice before the first answer came
+                        return
+                    }
+                    client_state.other_bank = Some(a.bank);
+                }
+                h.swap_banks(client);
+            }
+        }).unwrap();
+    }
+
+    fn swap_banks(&mut self, client: usize) {
+        let common = {
+            let cs = self.get_client_state(client).unwrap();
+            let mut c = cs.borrow_mut();
+            let ClientState { ref mut full_char, ref mut other_bank, ref mut common_bank_active, .. } = *c;
+            let (fc, other) = match (full_char.as_mut(), other_bank.as_mut()) {
+                (Some(fc), Some(other)) => (fc, other),
+                _ => return
+            };
+            mem::swap(&mut fc.bank, other);
+            *common_bank_active = !*common_bank_active;
+            *common_bank_active
+        };
+        info!("Client {} switched to their {} bank", client, if common { "common" } else { "character" });
+        if common {
+            self.send_error(client, "\tEYou're now using\nyour common bank.");
+        } else {
+            self.send_error(client, "\tEYou're now using\nyour character's bank.");
+        }
+    }
+
     pub fn bb_update_options(&mut self, m: BbUpdateOptions) {
         use ::shipgate::msg::BbUpdateOptions as SgBbUO;
         info!("{} updated general options", self.client_id);
@@ -598,6 +772,27 @@ impl BlockHandler {
         })).unwrap();
     }
 
+    pub fn bb_add_guildcard(&mut self, m: BbAddGuildCard) {
+        use ::shipgate::msg::BbAddGuildCard as SgBbAGC;
+        use psodata::guildcard::BbGuildCard;
+        info!("{} added guild card {}", self.client_id, m.guildcard);
+        let cr = self.get_client_state(self.client_id).unwrap();
+        let ref client_state = cr.borrow();
+        self.sg_sender.send(Sgm::BbAddGuildCard(0, SgBbAGC {
+            account_id: client_state.account_id,
+            card: BbGuildCard {
+                guildcard: m.guildcard,
+                name: m.name,
+                team_name: m.team_name,
+                desc: m.text,
+                one: m.one,
+                lang: m.lang,
+                section: m.section,
+                class: m.char_class
+            }
+        })).unwrap();
+    }
+
     pub fn menu_select(&mut self, m: MenuSelect) {
         let MenuSelect(menu_id, item_id) = m;
         match menu_id {
@@ -620,6 +815,10 @@ impl BlockHandler {
                             self.send_error(self.client_id, "\tEParty is full.");
                             return
                         }
+                        if p.quest.is_some() {
+                            self.send_error(self.client_id, "\tEA quest is in progress.");
+                            return
+                        }
 
                         // Then, remove them from their lobby
                         let lr = self.lobbies.clone();
@@ -645,6 +844,49 @@ impl BlockHandler {
                 }
                 self.send_error(self.client_id, "\tEParty no longer\texists.");
             },
+            MENU_QUEST_CATEGORY => {
+                let entries: Vec<BbQuestListEntry> = match self.quests.categories.get(item_id as usize) {
+                    Some(c) => c.quests.iter().map(|&i| BbQuestListEntry {
+                        menu_id: MENU_QUEST,
+                        item_id: i as u32,
+                        name: self.quests.quests[i].name.clone(),
+                        description: self.quests.quests[i].description.clone()
+                    }).collect(),
+                    None => {
+                        self.send_error(self.client_id, "\tEInvalid menu");
+                        return
+                    }
+                };
+                let m = Message::BbQuestList(entries.len() as u32, BbQuestList { entries: entries });
+                self.send_to_client(self.client_id, m);
+            },
+            MENU_QUEST => {
+                let quests = self.quests.clone();
+                let quest = match quests.quests.get(item_id as usize) {
+                    Some(q) => q,
+                    None => {
+                        self.send_error(self.client_id, "\tEInvalid menu");
+                        return
+                    }
+                };
+                let pr = self.parties.clone();
+                let mut parties = pr.borrow_mut();
+                for p in parties.iter_mut() {
+                    if p.has_player(self.client_id) {
+                        let episode = if p.episode == 3 { 4 } else { p.episode };
+                        if quest.episode != episode {
+                            self.send_error(self.client_id, "\tEThat quest is for\nanother episode.");
+                            return
+                        }
+                        if let Err(e) = p.load_quest(self, item_id as usize, quest) {
+                            self.send_error(self.client_id, &format!("\tE{:?}", e));
+                        }
+                        return
+                    }
+                }
+                warn!("Client {} picked a quest when they weren't in a party.", self.client_id);
+                self.send_fatal_error(self.client_id, "\tEIllegal message.");
+            },
             _ => {
                 self.send_error(self.client_id, "\tEInvalid menu");
                 return
@@ -664,23 +906,306 @@ impl BlockHandler {
     }
 
     pub fn bb_full_char(&mut self, m: BbFullChar) {
-        // TODO verify... or just track based on their other messages sent
-        // this is prone to being cheated. we'll just save some parts until
-        // the implementation is more thorough.
+        // The bank is ours, but the inventory and meseta still change in ways
+        // we don't follow (selling, quest rewards and so on), so we take the
+        // client's word for them and only log when it disagrees with ours.
         let BbFullChar(full_char) = m;
 
-        let BbFullCharData { inv, chara, bank, .. } = full_char;
+        let BbFullCharData { inv, chara, .. } = full_char;
 
         let cs = self.get_client_state(self.client_id).unwrap();
         let ref mut client_state = cs.borrow_mut();
+        let (account_id, guildcard, slot) = (client_state.account_id, client_state.bb_guildcard, client_state.sec_data.slot);
         if let Some(ref mut cur_fc) = client_state.full_char {
             info!("Client {} triggered manual save", self.client_id);
+            if !cur_fc.inv.same_items(&inv) || cur_fc.chara.meseta != chara.meseta {
+                // Either the client is out of step with us or it's been
+                // tampered with; log enough to look into the account.
+                let unknown: Vec<_> = inv.items.iter()
+                    .filter(|i| !cur_fc.inv.items.iter().any(|s| s.data.item_id == i.data.item_id && s.data.data == i.data.data && s.data.data2 == i.data.data2))
+                    .map(|i| format!("{:08X} {:?} {:?}", i.data.item_id, i.data.data, i.data.data2))
+                    .collect();
+                let missing: Vec<_> = cur_fc.inv.items.iter()
+                    .filter(|s| !inv.items.iter().any(|i| s.data.item_id == i.data.item_id && s.data.data == i.data.data && s.data.data2 == i.data.data2))
+                    .map(|s| format!("{:08X} {:?} {:?}", s.data.item_id, s.data.data, s.data.data2))
+                    .collect();
+                error!("Client {} (account {}, guild card {}, slot {}, character {:?}) sent a save that doesn't match their inventory on the server. \
+                        Meseta sent {}, held {}. Items sent that they don't have: {:?}. Items they have that weren't sent: {:?}",
+                       self.client_id, account_id, guildcard, slot, cur_fc.chara.name, chara.meseta, cur_fc.chara.meseta, unknown, missing);
+            }
             cur_fc.inv = inv;
             cur_fc.chara = chara;
-            cur_fc.bank = bank;
         } else {
             warn!("Client sent full character but we didn't have one loaded for them. This is an abnormal state.");
             return
         }
     }
+
+    /// Apply an inventory change announced by a client to the server's copy
+    /// of their character. Yields false if they have no character loaded, in
+    /// which case the subcommand shouldn't be passed on. An item we don't know
+    /// about is only logged, since our copy can fall behind between saves.
+    /// Other subcommands are always fine.
+    pub fn track_inventory(&self, client: usize, m: &BbSubCmd60) -> bool {
+        match m {
+            &BbSubCmd60::Bb60EquipItem { .. } |
+            &BbSubCmd60::Bb60UnequipItem { .. } |
+            &BbSubCmd60::Bb60UseItem { .. } |
+            &BbSubCmd60::Bb60SortItems { .. } |
+            &BbSubCmd60::Bb60DestroyItem { .. } |
+            &BbSubCmd60::Bb60FeedMag { .. } => (),
+            _ => return true
+        }
+        let cs = self.get_client_state(client).unwrap();
+        let mut c = cs.borrow_mut();
+        let fc = match c.full_char.as_mut() {
+            Some(fc) => fc,
+            None => {
+                error!("Client {} sent an item subcommand without a character loaded: {:?}", client, m);
+                return false
+            }
+        };
+        let ok = match m {
+            &BbSubCmd60::Bb60EquipItem { ref data, .. } => fc.inv.set_equipped(data.item_id, true),
+            &BbSubCmd60::Bb60UnequipItem { ref data, .. } => fc.inv.set_equipped(data.item_id, false),
+            &BbSubCmd60::Bb60UseItem { ref data, .. } => fc.inv.take(data.item_id, 1, 0xFFFFFFFF).is_some(),
+            &BbSubCmd60::Bb60SortItems { ref data, .. } => fc.inv.sort(&data.item_ids),
+            &BbSubCmd60::Bb60DestroyItem { ref data, .. } => fc.inv.take(data.item_id, data.amount, 0xFFFFFFFF).is_some(),
+            &BbSubCmd60::Bb60FeedMag { ref data, .. } => self.feed_mag(fc, data.mag_id, data.item_id),
+            _ => return true
+        };
+        if !ok {
+            warn!("Client {} sent an item subcommand that doesn't match their inventory: {:?}", client, m);
+        }
+        true
+    }
+
+    /// Feed one of an item to a mag in the same inventory. Yields false if
+    /// either is missing or the mag won't eat it.
+    fn feed_mag(&self, fc: &mut BbFullCharData, mag_id: u32, item_id: u32) -> bool {
+        let food = match fc.inv.find(item_id) {
+            Some(i) => i.data.clone(),
+            None => return false
+        };
+        let (class, section) = (fc.chara.class, fc.chara.section);
+        let fed = match fc.inv.items.iter_mut().find(|i| i.data.item_id == mag_id) {
+            Some(m) => mag::feed(&self.item_pmt, &self.mag_edit, &mut m.data, &food, class, section),
+            None => false
+        };
+        fed && fc.inv.take(item_id, 1, 0xFFFFFFFF).is_some()
+    }
+
+    /// Find the client on this block with the given guild card.
+    fn client_by_guildcard(&self, guildcard: u32) -> Option<usize> {
+        self.clients.borrow().iter()
+            .find(|&(_, cs)| cs.borrow().bb_guildcard == guildcard)
+            .map(|(&id, _)| id)
+    }
+
+    /// Send a client their current team information.
+    fn send_team_info(&self, client: usize) {
+        let cs = match self.get_client_state(client) {
+            Some(cs) => cs,
+            None => return
+        };
+        let c = cs.borrow();
+        let mut info = BbTeamInfo::default();
+        info.guildcard = c.bb_guildcard;
+        info.team_id = c.team_id;
+        info.priv_level = c.team_priv;
+        info.team_name = c.team_name.clone();
+        info.guildcard2 = c.bb_guildcard;
+        if let Some(ref fc) = c.full_char {
+            info.name = fc.chara.name.clone();
+        }
+        self.send_to_client(client, Message::BbTeamInfo(0, info));
+    }
+
+    /// Change a client's team, and tell them about it. A team ID of 0 takes
+    /// them out of their team.
+    fn set_team(&self, client: usize, team_id: u32, privilege: u32, name: &str) {
+        {
+            let cs = match self.get_client_state(client) {
+                Some(cs) => cs,
+                None => return
+            };
+            let ref mut c = cs.borrow_mut();
+            c.team_id = team_id;
+            c.team_priv = privilege;
+            c.team_name = name.to_string();
+            if let Some(ref mut fc) = c.full_char {
+                fc.key_config.team_id = team_id;
+                fc.key_config.team_priv = privilege as u16;
+                fc.key_config.team_name = name.to_string();
+                fc.team_name = name.to_string();
+            }
+        }
+        self.send_team_info(client);
+    }
+
+    /// Apply a team change the shipgate announced to this handler's client.
+    /// The shipgate sends these for every action that succeeds, wherever it
+    /// was taken, so it's how both sides of an action find out about it.
+    pub fn bb_team_changed(&mut self, m: BbTeamChanged) {
+        let client = self.client_id;
+        self.set_team(client, m.team_id, m.privilege, &m.team_name);
+    }
+
+    /// Explain a failed team action to the client.
+    fn send_team_error(&self, status: u32) {
+        let msg = match status {
+            TEAM_NAME_TAKEN => "\tEA team with that\nname already exists.",
+            TEAM_NOT_ALLOWED => "\tEYour rank in the team\ndoesn't allow that.",
+            TEAM_ALREADY_IN_TEAM => "\tEThat player is already\nin a team.",
+            TEAM_NOT_IN_TEAM => "\tEThat player isn't\nin your team.",
+            _ => "\tEThe team could not\nbe updated."
+        };
+        self.send_error(self.client_id, msg);
+    }
+
+    fn team_state(&self) -> (u32, u32, u32, String, String) {
+        let cr = self.get_client_state(self.client_id).unwrap();
+        let ref c = cr.borrow();
+        let name = c.full_char.as_ref().map(|fc| fc.chara.name.clone()).unwrap_or_default();
+        (c.account_id, c.team_id, c.team_priv, c.team_name.clone(), name)
+    }
+
+    pub fn bb_team_create(&mut self, m: BbTeamCreate) {
+        use ::shipgate::msg::BbTeamCreate as SgBbTC;
+        let (account_id, team_id, _, _, char_name) = self.team_state();
+        if team_id != 0 {
+            self.send_to_client(self.client_id, Message::BbTeamCreateAck(1, BbTeamCreateAck));
+            self.send_team_error(TEAM_ALREADY_IN_TEAM);
+            return
+        }
+        let BbTeamCreate(name) = m;
+        info!("Client {} is creating team {}", self.client_id, name);
+        let sgm = SgBbTC { account_id: account_id, name: name, char_name: char_name };
+        self.sg_sender.request(self.client_id, sgm, move|h, m| {
+            if let Sgm::BbTeamAck(_, BbTeamAck { status, .. }) = m {
+                if status != 0 {
+                    h.send_to_client(h.client_id, Message::BbTeamCreateAck(1, BbTeamCreateAck));
+                    h.send_team_error(status);
+                    return
+                }
+                h.send_to_client(h.client_id, Message::BbTeamCreateAck(0, BbTeamCreateAck));
+            }
+        }).unwrap();
+    }
+
+    pub fn bb_team_add_member(&mut self, m: BbTeamAddMember) {
+        use ::shipgate::msg::BbTeamAddMember as SgBbTAM;
+        let (account_id, _, _, _, _) = self.team_state();
+        // Invitations are made in person, so the player must be on this block.
+        let target = match self.client_by_guildcard(m.0) {
+            Some(t) => t,
+            None => {
+                self.send_to_client(self.client_id, Message::BbTeamAddMemberAck(1, BbTeamAddMemberAck));
+                self.send_error(self.client_id, "\tEThat player couldn't\nbe found.");
+                return
+            }
+        };
+        let (target_account_id, target_name) = {
+            let cs = self.get_client_state(target).unwrap();
+            let c = cs.borrow();
+            (c.account_id, c.full_char.as_ref().map(|fc| fc.chara.name.clone()).unwrap_or_default())
+        };
+        let sgm = SgBbTAM { account_id: account_id, target_account_id: target_account_id, target_name: target_name };
+        self.sg_sender.request(self.client_id, sgm, move|h, m| {
+            if let Sgm::BbTeamAck(_, BbTeamAck { status, .. }) = m {
+                if status != 0 {
+                    h.send_to_client(h.client_id, Message::BbTeamAddMemberAck(1, BbTeamAddMemberAck));
+                    h.send_team_error(status);
+                    return
+                }
+                h.send_to_client(h.client_id, Message::BbTeamAddMemberAck(0, BbTeamAddMemberAck));
+            }
+        }).unwrap();
+    }
+
+    pub fn bb_team_remove_member(&mut self, m: BbTeamRemoveMember) {
+        use ::shipgate::msg::BbTeamRemoveMember as SgBbTRM;
+        let (account_id, _, _, _, _) = self.team_state();
+        let guildcard = m.0;
+        let sgm = SgBbTRM { account_id: account_id, target_guildcard: guildcard };
+        self.sg_sender.request(self.client_id, sgm, move|h, m| {
+            if let Sgm::BbTeamAck(_, BbTeamAck { status, .. }) = m {
+                if status != 0 {
+                    h.send_to_client(h.client_id, Message::BbTeamRemoveMemberAck(1, BbTeamRemoveMemberAck));
+                    h.send_team_error(status);
+                    return
+                }
+                h.send_to_client(h.client_id, Message::BbTeamRemoveMemberAck(0, BbTeamRemoveMemberAck));
+            }
+        }).unwrap();
+    }
+
+    pub fn bb_team_member_list(&mut self) {
+        use ::shipgate::msg::BbGetTeamMembers as SgBbGTM;
+        let (account_id, _, _, _, _) = self.team_state();
+        self.sg_sender.request(self.client_id, SgBbGTM { account_id: account_id }, move|h, m| {
+            if let Sgm::BbGetTeamMembersAck(_, a) = m {
+                if a.status != 0 {
+                    h.send_team_error(a.status);
+                    return
+                }
+                let entries: Vec<_> = a.members.into_iter().enumerate().map(|(i, m)| {
+                    BbTeamMemberEntry {
+                        index: i as u32 + 1,
+                        priv_level: m.privilege,
+                        guildcard: m.guildcard,
+                        name: m.name
+                    }
+                }).collect();
+                h.send_to_client(h.client_id, Message::BbTeamMemberList(0, BbTeamMemberList(entries)));
+            }
+        }).unwrap();
+    }
+
+    pub fn bb_team_disband(&mut self) {
+        use ::shipgate::msg::BbTeamDisband as SgBbTD;
+        let (account_id, _, _, _, _) = self.team_state();
+        self.sg_sender.request(self.client_id, SgBbTD { account_id: account_id }, move|h, m| {
+            if let Sgm::BbTeamAck(_, BbTeamAck { status, .. }) = m {
+                if status != 0 {
+                    h.send_team_error(status);
+                }
+            }
+        }).unwrap();
+    }
+
+    pub fn bb_team_change_priv(&mut self, privilege: u32, m: BbTeamChangePriv) {
+        use ::shipgate::msg::BbTeamChangePriv as SgBbTCP;
+        let (account_id, _, _, _, _) = self.team_state();
+        let guildcard = m.0;
+        let sgm = SgBbTCP { account_id: account_id, target_guildcard: guildcard, privilege: privilege };
+        self.sg_sender.request(self.client_id, sgm, move|h, m| {
+            if let Sgm::BbTeamAck(_, BbTeamAck { status, .. }) = m {
+                if status != 0 {
+                    h.send_team_error(status);
+                }
+            }
+        }).unwrap();
+    }
+}
+
+impl SessionHandler for BlockHandler {
+    fn sg_sender(&mut self) -> &mut SgCbMgr<BlockHandler> {
+        &mut self.sg_sender
+    }
+
+    fn client_id(&self) -> usize {
+        self.client_id
+    }
+
+    fn set_session(&mut self, session: (u32, u32)) -> bool {
+        match self.get_client_state(self.client_id) {
+            Some(cs) => { cs.borrow_mut().session = Some(session); true },
+            None => false
+        }
+    }
+
+    fn refuse_session(&mut self, _status: u32) {
+        self.send_fatal_error(self.client_id, "This account is already logged in.");
+    }
 }
@@ -259,6 +259,9 @@ impl Lobby {
     pub fn handle_bb_subcmd_60(&mut self, handler: &mut BlockHandler, m: BbSubCmd60) -> Result<(), LobbyError> {
         // We'll eventually do more on this.
         let cid = handler.client_id;
+        if !handler.track_inventory(cid, &m) {
+            return Ok(())
+        }
         self.bb_broadcast(handler, Some(cid), m.into())
     }
 
@@ -20,16 +20,21 @@ use psomsg::bb::*;
 
 use psodata::battleparam::BattleParamTables;
 use psodata::leveltable::LevelTable;
+use psodata::itempmt::ItemPMT;
+use psodata::itemmagedit::ItemMagEdit;
 
 use ::shipgate::msg::Message as Sgm;
-use ::shipgate::msg::BbPutCharacter;
+use ::shipgate::msg::BbSessionDisconnect;
 use ::shipgate::client::SgSender;
 use ::services::message::NetMsg;
 use ::shipgate::client::callbacks::SgCbMgr;
+use ::shipgate::client::session::kick_session;
 use ::services::{ServiceMsg, Service, ServiceType};
 use ::loop_handler::LoopMsg;
 use ::maps::Areas;
 use ::droptables::DropTable;
+use ::rates::{RatesConf, SharedRates};
+use ::quests::Quests;
 
 pub mod client;
 pub mod handler;
@@ -40,6 +45,7 @@ use self::handler::BlockHandler;
 use self::client::ClientState;
 use self::lobbyhandler::Lobby;
 use self::partyhandler::Party;
+use self::partyhandler::enemygen::RareRates;
 
 pub struct BlockService {
     receiver: Receiver<ServiceMsg>,
@@ -55,7 +61,12 @@ pub struct BlockService {
     online_maps: Arc<Areas>,
     offline_maps: Arc<Areas>,
     level_table: Arc<LevelTable>,
-    drop_table: Arc<DropTable>
+    drop_table: Arc<DropTable>,
+    item_pmt: Arc<ItemPMT>,
+    mag_edit: Arc<ItemMagEdit>,
+    quests: Arc<Quests>,
+    rare_rates: Arc<RareRates>,
+    rates: SharedRates
 }
 
 impl BlockService {
@@ -69,9 +80,20 @@ impl BlockService {
                  online_maps: Arc<Areas>,
                  offline_maps: Arc<Areas>,
                  level_table: Arc<LevelTable>,
-                 drop_table: Arc<DropTable>) -> Service {
+                 drop_table: Arc<DropTable>,
+                 item_pmt: Arc<ItemPMT>,
+                 mag_edit: Arc<ItemMagEdit>,
+                 quests: Arc<Quests>,
+                 rare_rates: RareRates,
+                 rates: Option<RatesConf>) -> Service {
         let (tx, rx) = channel();
 
+        let shared_rates = SharedRates::new(rates.as_ref().map(|rc| rc.rates).unwrap_or_default());
+        if let Some(ref rc) = rates {
+            let r = shared_rates.clone();
+            rc.spawn_watcher(move|new| r.set_local(new));
+        }
+
         let listener = TcpListener::bind(bind).expect("Couldn't create tcplistener");
 
         let sg_sender = sg_sender.clone_with(tx.clone());
@@ -91,7 +113,12 @@ impl BlockService {
                 online_maps: online_maps,
                 offline_maps: offline_maps,
                 level_table: level_table,
-                drop_table: drop_table
+                drop_table: drop_table,
+                item_pmt: item_pmt,
+                mag_edit: mag_edit,
+                quests: quests,
+                rare_rates: Arc::new(rare_rates),
+                rates: shared_rates
             };
             d.run();
         });
@@ -111,6 +138,12 @@ impl BlockService {
             self.online_maps.clone(),
             self.offline_maps.clone(),
             self.level_table.clone(),
+            self.drop_table.clone(),
+            self.item_pmt.clone(),
+            self.mag_edit.clone(),
+            self.quests.clone(),
+            self.rare_rates.clone(),
+            self.rates.clone(),
             self.party_counter.clone()
         )
     }
@@ -184,17 +217,15 @@ impl BlockService {
                     }
 
                     // Now we will persist their current character to the shipgate.
+                    info!("Saving {}'s character due to disconnect", id);
+                    h.save_character(id);
+
+                    // Their connection no longer counts towards their session.
                     {
                         let cs = h.get_client_state(id).unwrap();
                         let ref client_state = cs.borrow();
-                        if let Some(ref full_char) = client_state.full_char {
-                            info!("Saving {}'s character due to disconnect", id);
-                            self.sg_sender.send(Sgm::BbPutCharacter(0, BbPutCharacter {
-                                account_id: client_state.account_id,
-                                slot: client_state.sec_data.slot,
-                                save_acct_data: 0,
-                                full_char: full_char.clone()
-                            })).unwrap();
+                        if let Some((account_id, token)) = client_state.session {
+                            self.sg_sender.send(BbSessionDisconnect { account_id: account_id, token: token }).unwrap();
                         }
                     }
 
@@ -220,7 +251,18 @@ impl BlockService {
                         Message::BbUpdateOptions(_, m) => { h.bb_update_options(m) },
                         Message::BbUpdateKeys(_, m) => { h.bb_update_keys(m) },
                         Message::BbUpdateJoy(_, m) => { h.bb_update_joy(m) },
+                        Message::BbAddGuildCard(_, m) => { h.bb_add_guildcard(m) },
+                        Message::BbTeamCreate(_, m) => { h.bb_team_create(m) },
+                        Message::BbTeamAddMember(_, m) => { h.bb_team_add_member(m) },
+                        Message::BbTeamRemoveMember(_, m) => { h.bb_team_remove_member(m) },
+                        Message::BbTeamMemberListReq(_, _) => { h.bb_team_member_list() },
+                        Message::BbTeamDisband(_, _) => { h.bb_team_disband() },
+                        Message::BbTeamChangePriv(p, m) => { h.bb_team_change_priv(p, m) },
                         Message::MenuSelect(_, m) => { h.menu_select(m) },
+                        Message::BbQuestList(_, _) => { h.bb_quest_list() },
+                        Message::BbQuestLoadDone(_, _) => { h.bb_quest_load_done() },
+                        // The client acknowledges each quest file and chunk.
+                        Message::BbQuestFile(_, _) | Message::BbQuestChunk(_, _) => (),
                         Message::DoneBursting(_, _) => { h.done_burst() },
                         Message::BbFullChar(_, b) => { h.bb_full_char(b) },
                         a => {
@@ -228,6 +270,23 @@ impl BlockService {
                         }
                     }
                 },
+                ServiceMsg::ShipGateMsg(Sgm::BbSessionKick(_, k)) => {
+                    // Their characters are saved as they disconnect.
+                    let b = self.clients.borrow();
+                    kick_session(&self.sender, k, b.iter().map(|(&id, cs)| (id, cs.borrow().session)));
+                },
+                ServiceMsg::ShipGateMsg(Sgm::BbTeamChanged(_, c)) => {
+                    let client = self.clients.borrow().iter()
+                        .find(|&(_, cs)| cs.borrow().account_id == c.account_id)
+                        .map(|(&id, _)| id);
+                    if let Some(id) = client {
+                        self.make_handler(id).bb_team_changed(c);
+                    }
+                },
+                ServiceMsg::ShipGateMsg(Sgm::SetRates(_, r)) => {
+                    info!("Using the shipgate's rates: {:?}", r.0);
+                    self.rates.set_network(r.0);
+                },
                 ServiceMsg::ShipGateMsg(m) => {
                     let req = m.get_response_key();
                     debug!("Shipgate Request {}: Response received", req);
@@ -1,71 +1,132 @@
+use rand::random;
+
 use psodata::map::MapEnemy;
 use ::maps::InstanceEnemy;
 use ::block::lobbyhandler::event::Event;
 
-pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool) -> Vec<InstanceEnemy> {
+/// The most rare enemies one party can have. The client is told which enemies
+/// are rare in a list of this length.
+pub const MAX_RARES: usize = 16;
+
+/// The chance, from 0 to 1, of each kind of enemy spawning as its rare form.
+#[derive(Clone, Debug)]
+pub struct RareRates {
+    pub hildeblue: f64,
+    /// Al Rappy, Love Rappy and the event rappies, and Del Rappy.
+    pub rappy: f64,
+    pub nar_lily: f64,
+    pub pouilly_slime: f64,
+    pub merissa_aa: f64,
+    pub pazuzu: f64,
+    pub dorphon_eclair: f64,
+    pub kondrieu: f64
+}
+
+impl Default for RareRates {
+    fn default() -> RareRates {
+        RareRates {
+            hildeblue: 1.0 / 512.0,
+            rappy: 1.0 / 512.0,
+            nar_lily: 1.0 / 512.0,
+            pouilly_slime: 1.0 / 512.0,
+            merissa_aa: 1.0 / 512.0,
+            pazuzu: 1.0 / 512.0,
+            dorphon_eclair: 1.0 / 512.0,
+            kondrieu: 1.0 / 10.0
+        }
+    }
+}
+
+/// The rare enemies rolled for a party so far, by index in its enemy list.
+#[derive(Debug)]
+pub struct RareRolls<'a> {
+    rates: &'a RareRates,
+    pub enemies: Vec<u16>
+}
+
+impl<'a> RareRolls<'a> {
+    pub fn new(rates: &'a RareRates) -> RareRolls<'a> {
+        RareRolls {
+            rates: rates,
+            enemies: Vec::new()
+        }
+    }
+
+    /// Decide whether the enemy at `index` is rare. Enemies the map marks as
+    /// rare always are; the rest are rolled while the party has room for more
+    /// rares.
+    fn roll(&mut self, index: usize, marked: bool, rate: f64) -> bool {
+        if self.enemies.len() >= MAX_RARES || index > 0xFFFF {
+            return marked
+        }
+        if marked || (rate > 0.0 && random::<f64>() < rate) {
+            self.enemies.push(index as u16);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Make the instance enemies for a map enemy, the first of which will be at
+/// `index` in the party's enemy list.
+pub fn convert_enemy(me: &MapEnemy, index: usize, episode: u8, event: u16, alt_enemies: bool, rares: &mut RareRolls) -> Vec<InstanceEnemy> {
     let mut ret = Vec::new();
-    // TODO Rare enemies
+    let marked = me.reserved2[10] & 0x800000 > 0;
     match me.base {
         0x0040 => {
             // Hildebear, Hildetorr
-            debug!("Hildebear/Hildetorr");
+            let rate = rares.rates.hildeblue;
+            let acc = if rares.roll(index, me.skin & 0x01 > 0, rate) {1} else {0};
+            let name = if acc == 1 { "Hildeblue" } else { "Hildebear" };
+            debug!("{}", name);
             ret.push(InstanceEnemy {
-                param_entry: 0x49 + (me.skin & 0x01) as usize,
-                rt_entry: 0x01 + (me.skin & 0x01) as usize,
-                name: "Hildebear/Hildetorr"
+                param_entry: 0x49 + acc,
+                rt_entry: 0x01 + acc,
+                name: name
             });
         },
         0x0041 => {
             // Rappies, lots of them
+            let rate = rares.rates.rappy;
+            let acc = if rares.roll(index, me.skin & 0x01 > 0, rate) {1} else {0};
             match episode {
                 3 => {
-                    if alt_enemies {
-                        debug!("Sand/Del Rappy (Desert)");
-                        ret.push(InstanceEnemy {
-                            param_entry: 0x17 + (me.skin & 0x01) as usize,
-                            rt_entry: 0x11 + (me.skin & 0x01) as usize,
-                            name: "Sand/Del Rappy (Desert)"
-                        })
+                    let name = if acc == 1 { "Del Rappy" } else { "Sand Rappy" };
+                    let bp = if alt_enemies {
+                        debug!("{} (Desert)", name);
+                        0x17 + acc
                     } else {
-                        debug!("Sand/Del Rappy (Wilds/Crater)");
-                        ret.push(InstanceEnemy {
-                            param_entry: 0x05 + (me.skin & 0x01) as usize,
-                            rt_entry: 0x11 + (me.skin + 0x01) as usize,
-                            name: "Sand/Del Rappy (Wilds/Crater)"
-                        });
-                    }
+                        debug!("{} (Crater)", name);
+                        0x05 + acc
+                    };
+                    ret.push(InstanceEnemy {
+                        param_entry: bp,
+                        rt_entry: 0x11 + acc,
+                        name: name
+                    });
                 },
                 1 => {
-                    debug!("Rag/El/Al/Pal Rappy (Ep1)");
+                    let name = if acc == 1 { "Al Rappy" } else { "Rag Rappy" };
+                    debug!("{} (Ep1)", name);
                     ret.push(InstanceEnemy {
-                        param_entry: 0x18 + (me.skin & 0x01) as usize,
-                        rt_entry: 0x11 + (me.skin + 0x01) as usize,
-                        name: "Rappy"
+                        param_entry: 0x18 + acc,
+                        rt_entry: 0x05 + acc,
+                        name: name
                     });
                 },
                 2 => {
-                    let rt = if me.skin & 0x01 > 0 { match event {
-                        e if e == Event::Christmas as u16 => {
-                            debug!("Saint Rappy");
-                            79
-                        },
-                        e if e == Event::Easter as u16 => {
-                            debug!("Egg Rappy");
-                            81
-                        },
-                        e if e == Event::Halloween as u16 => {
-                            debug!("Hallo Rappy");
-                            80
-                        },
-                        _ => {
-                            debug!("Rag Rappy (Ep2)");
-                            51
-                        }
-                    }} else {0x05};
+                    let (rt, name) = if acc == 1 { match event {
+                        e if e == Event::Christmas as u16 => (79, "Saint Rappy"),
+                        e if e == Event::Easter as u16 => (81, "Egg Rappy"),
+                        e if e == Event::Halloween as u16 => (80, "Hallo Rappy"),
+                        _ => (51, "Love Rappy")
+                    }} else {(0x05, "Rag Rappy")};
+                    debug!("{} (Ep2)", name);
                     ret.push(InstanceEnemy {
-                        param_entry: 0x18 + (me.skin & 0x01) as usize,
+                        param_entry: 0x18 + acc,
                         rt_entry: rt,
-                        name: "Rappy"
+                        name: name
                     })
 
                 },
@@ -121,9 +182,10 @@ pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool)
                     name: "Del Lily"
                 });
             } else {
-                debug!("Poison/Nar Lily");
-                let acc = if me.reserved2[10] & 0x800000 > 0 {1} else {0};
+                let rate = rares.rates.nar_lily;
+                let acc = if rares.roll(index, marked, rate) {1} else {0};
                 let name = if acc == 1 { "Nar Lily" } else { "Poison Lily" };
+                debug!("{}", name);
                 ret.push(InstanceEnemy {
                     param_entry: 0x04 + acc,
                     rt_entry: 0x0D + acc,
@@ -150,11 +212,12 @@ pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool)
         },
         0x0064 => {
             debug!("Slime and 4 clones");
-            let acc = if me.reserved2[10] & 0x800000 > 0 {1} else {0};
+            let rate = rares.rates.pouilly_slime;
+            let acc = if rares.roll(index, marked, rate) {1} else {0};
             ret.push(InstanceEnemy {
                 param_entry: 0x30 - acc,
                 rt_entry: 0x13 + acc,
-                name: "Slime"
+                name: if acc == 1 { "Pouilly Slime" } else { "Pofuilly Slime" }
             });
             for _ in 0..4 {
                 ret.push(InstanceEnemy {
@@ -639,11 +702,14 @@ pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool)
             });
         },
         0x0112 => {
-            debug!("Merissa");
+            let rate = rares.rates.merissa_aa;
+            let acc = if rares.roll(index, me.skin & 0x01 > 0, rate) {1} else {0};
+            let name = if acc == 1 { "Merissa AA" } else { "Merissa A" };
+            debug!("{}", name);
             ret.push(InstanceEnemy {
-                param_entry: 0x19 + (me.skin & 0x01) as usize,
-                rt_entry: 0x04 + (me.skin & 0x01) as usize,
-                name: "Merissa"
+                param_entry: 0x19 + acc,
+                rt_entry: 0x04 + acc,
+                name: name
             });
         },
         0x0113 => {
@@ -655,8 +721,10 @@ pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool)
             });
         },
         0x0114 => {
-            debug!("Zu/Pazuzu");
-            let acc = me.skin & 0x01;
+            let rate = rares.rates.pazuzu;
+            let acc = if rares.roll(index, me.skin & 0x01 > 0, rate) {1} else {0};
+            let name = if acc == 1 { "Pazuzu" } else { "Zu" };
+            debug!("{}", name);
             let bp = if alt_enemies {
                 0x07 + acc + 0x14
             } else {
@@ -665,7 +733,7 @@ pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool)
             ret.push(InstanceEnemy {
                 param_entry: bp as usize,
                 rt_entry: 0x07 + acc as usize,
-                name: "Zu/Pazuzu"
+                name: name
             });
         },
         0x0115 => {
@@ -680,12 +748,14 @@ pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool)
             });
         },
         0x0116 => {
-            debug!("Dorphon/Eclair");
-            let acc = me.skin & 0x01;
+            let rate = rares.rates.dorphon_eclair;
+            let acc = if rares.roll(index, me.skin & 0x01 > 0, rate) {1} else {0};
+            let name = if acc == 1 { "Dorphon Eclair" } else { "Dorphon" };
+            debug!("{}", name);
             ret.push(InstanceEnemy {
                 param_entry: 0x0F + acc as usize,
                 rt_entry: 0x0C + acc as usize,
-                name: "Dorphon/Eclair"
+                name: name
             });
         },
         0x0117 => {
@@ -709,7 +779,8 @@ pub fn convert_enemy(me: &MapEnemy, episode: u8, event: u16, alt_enemies: bool)
             debug!("Saint Milion, Shambertin or Kondrieu");
             let acc = me.skin & 0x01;
             let bp = 0x22;
-            let rt = if me.reserved2[10] & 0x800000 > 0 {0x15} else {0x13 + acc};
+            let rate = rares.rates.kondrieu;
+            let rt = if rares.roll(index, marked, rate) {0x15} else {0x13 + acc};
             ret.push(InstanceEnemy {
                 param_entry: bp,
                 rt_entry: rt as usize,
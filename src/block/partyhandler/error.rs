@@ -3,5 +3,6 @@
 pub enum PartyError {
     IsFull,
     NotInParty,
-    NotBursting
+    NotBursting,
+    QuestInProgress
 }
@@ -0,0 +1,100 @@
+//! Items lying on the floor of a party's areas. Every item a client can pick up
+//! has to be in here first, so the server decides what exists.
+
+use std::collections::HashMap;
+
+use psomsg::bb::*;
+
+#[derive(Clone, Debug)]
+pub struct FloorItem {
+    pub area: u32,
+    pub x: f32,
+    pub z: f32,
+    pub data: ItemData
+}
+
+impl FloorItem {
+    /// The message that places this item on the floor for a client.
+    pub fn drop_stack_msg(&self) -> Bb60DropStack {
+        let mut item = [0u8; 12];
+        item.copy_from_slice(&self.data.data[..12]);
+        let mut item2 = [0u8; 4];
+        item2.copy_from_slice(&self.data.data2[..4]);
+        Bb60DropStack {
+            area: self.area,
+            x: self.x,
+            z: self.z,
+            item: item,
+            item_id: self.data.item_id,
+            item2: item2
+        }
+    }
+
+    /// The message that places this item on the floor as a new drop.
+    pub fn item_drop_msg(&self, source: u8, req: u16) -> Bb60ItemDrop {
+        let mut item = [0u8; 12];
+        item.copy_from_slice(&self.data.data[..12]);
+        let mut item2 = [0u8; 4];
+        item2.copy_from_slice(&self.data.data2[..4]);
+        Bb60ItemDrop {
+            area: self.area as u8,
+            source: source,
+            req: req,
+            x: self.x,
+            z: self.z,
+            unk: 0,
+            item: item,
+            item_id: self.data.item_id,
+            item2: item2
+        }
+    }
+}
+
+/// The message that puts an item into a client's inventory.
+pub fn create_item_msg(data: &ItemData) -> Bb60CreateItem {
+    let mut item = [0u8; 12];
+    item.copy_from_slice(&data.data[..12]);
+    let mut item2 = [0u8; 4];
+    item2.copy_from_slice(&data.data2[..4]);
+    Bb60CreateItem {
+        item: item,
+        item_id: data.item_id,
+        item2: item2,
+        unused: 0
+    }
+}
+
+/// The floor items of one party, by area.
+#[derive(Clone, Debug, Default)]
+pub struct FloorItems {
+    areas: HashMap<u32, Vec<FloorItem>>
+}
+
+impl FloorItems {
+    /// Whether any area has an item with the ID.
+    pub fn contains(&self, item_id: u32) -> bool {
+        self.areas.values().any(|v| v.iter().any(|i| i.data.item_id == item_id))
+    }
+
+    /// Put an item on the floor. Yields false if an item with that ID is
+    /// already on the floor.
+    pub fn add(&mut self, item: FloorItem) -> bool {
+        if self.contains(item.data.item_id) {
+            return false
+        }
+        self.areas.entry(item.area).or_insert_with(Vec::new).push(item);
+        true
+    }
+
+    /// Take the item with the ID off the floor of the area, if it's there.
+    pub fn take(&mut self, area: u32, item_id: u32) -> Option<FloorItem> {
+        let items = match self.areas.get_mut(&area) {
+            Some(v) => v,
+            None => return None
+        };
+        match items.iter().position(|i| i.data.item_id == item_id) {
+            Some(p) => Some(items.remove(p)),
+            None => None
+        }
+    }
+}
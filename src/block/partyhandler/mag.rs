@@ -0,0 +1,409 @@
+//! Mags: what feeding does to them, how they evolve as they level, and the
+//! stats they give the character wearing them.
+//!
+//! A mag keeps its DEF, POW, DEX and MIND as little-endian words in bytes 4
+//! through 11, each a level times 100 plus the progress towards the next. The
+//! third byte is the sum of the four levels, and the fourth holds the photon
+//! blasts. Of the second data block, the bytes are synchro, IQ, which photon
+//! blast slots are filled, and the color.
+
+use std::cmp::{min, max};
+
+use psodata::chara::{ItemData, CharStats};
+use psodata::itempmt::ItemPMT;
+use psodata::itemmagedit::ItemMagEdit;
+
+pub const MAX_MAG_LEVEL: u32 = 200;
+pub const MAX_SYNCHRO: i32 = 120;
+pub const MAX_IQ: i32 = 200;
+
+/// Stats in the order they're stored.
+const DEF: usize = 0;
+const POW: usize = 1;
+const DEX: usize = 2;
+const MIND: usize = 3;
+
+/// Mags each class type evolves into at level 10.
+const MAG_VARUNA: u8 = 0x01;
+const MAG_KALKI: u8 = 0x0D;
+const MAG_VRITRA: u8 = 0x19;
+
+/// What each of the level 10 mags becomes at level 35, by whichever of POW,
+/// DEX and MIND is highest.
+static LEVEL_35: [(u8, [u8; 3]); 3] = [
+    (MAG_VARUNA, [0x0E, 0x0F, 0x04]), // Rudra, Marutah, Vayu
+    (MAG_KALKI, [0x02, 0x03, 0x0B]), // Mitra, Surya, Tapas
+    (MAG_VRITRA, [0x1B, 0x14, 0x1A]) // Sumba, Ashvinau, Namuci
+];
+
+/// What a mag becomes at level 50, and every five levels after, by the class
+/// type of its owner, whether their section ID is even or odd, and whichever
+/// of POW, DEX and MIND is highest.
+static LEVEL_50: [[[u8; 3]; 2]; 3] = [
+    // Varaha, Kama, Bhirava
+    [[0x05, 0x06, 0x0C], [0x06, 0x0C, 0x05]],
+    // Kaitabha, Yaksa, Garuda
+    [[0x0A, 0x10, 0x12], [0x10, 0x12, 0x0A]],
+    // Ushasu, Apsaras, Kumara
+    [[0x07, 0x08, 0x09], [0x08, 0x09, 0x07]]
+];
+
+pub fn is_mag(item: &ItemData) -> bool {
+    item.data[0] == 0x02
+}
+
+fn stat(mag: &ItemData, stat: usize) -> u16 {
+    mag.data[4 + stat * 2] as u16 | (mag.data[5 + stat * 2] as u16) << 8
+}
+
+fn set_stat(mag: &mut ItemData, stat: usize, value: u16) {
+    mag.data[4 + stat * 2] = value as u8;
+    mag.data[5 + stat * 2] = (value >> 8) as u8;
+}
+
+/// The levels of DEF, POW, DEX and MIND.
+pub fn stat_levels(mag: &ItemData) -> [u16; 4] {
+    [stat(mag, DEF) / 100, stat(mag, POW) / 100, stat(mag, DEX) / 100, stat(mag, MIND) / 100]
+}
+
+pub fn level(mag: &ItemData) -> u32 {
+    stat_levels(mag).iter().fold(0, |a, &l| a + l as u32)
+}
+
+/// Add a mag's stats to a character's. Each level of DEF is worth a point of
+/// DFP, POW two of ATP, DEX half of ATA, and MIND two of MST.
+pub fn add_stat_bonus(mag: &ItemData, stats: &mut CharStats) {
+    let levels = stat_levels(mag);
+    stats.dfp = stats.dfp.saturating_add(levels[DEF]);
+    stats.atp = stats.atp.saturating_add(levels[POW] * 2);
+    stats.ata = stats.ata.saturating_add(levels[DEX] / 2);
+    stats.mst = stats.mst.saturating_add(levels[MIND] * 2);
+}
+
+/// Which of POW, DEX and MIND is highest, as an index into the evolution
+/// tables. Ties go to the first.
+fn highest_stat(mag: &ItemData) -> usize {
+    let levels = stat_levels(mag);
+    let mut best = 0;
+    for i in 1..3 {
+        if levels[POW + i] > levels[POW + best] {
+            best = i;
+        }
+    }
+    best
+}
+
+/// The class type (hunter, ranger or force) of a character class.
+fn class_type(class: u8) -> usize {
+    match class {
+        3 | 4 | 5 | 11 => 1,
+        6 | 7 | 8 | 10 => 2,
+        _ => 0
+    }
+}
+
+/// Whether the mag has a photon blast in any of its slots.
+fn has_photon_blast(mag: &ItemData, pb: u8) -> bool {
+    let flags = mag.data2[2];
+    let pbs = mag.data[3];
+    if flags & 1 != 0 && pbs & 0x07 == pb {
+        return true
+    }
+    if flags & 2 != 0 && (pbs >> 3) & 0x07 == pb {
+        return true
+    }
+    if flags & 4 != 0 {
+        return left_photon_blast(mag) == Some(pb)
+    }
+    false
+}
+
+/// The left slot only has two bits, so it counts among the photon blasts not
+/// in the other two slots.
+fn left_candidates(mag: &ItemData) -> Vec<u8> {
+    let center = mag.data[3] & 0x07;
+    let right = (mag.data[3] >> 3) & 0x07;
+    (0..6).filter(|&pb| pb != center && pb != right).collect()
+}
+
+fn left_photon_blast(mag: &ItemData) -> Option<u8> {
+    left_candidates(mag).get((mag.data[3] >> 6) as usize).cloned()
+}
+
+/// Teach the mag a photon blast in its first free slot, unless it knows it
+/// already or has no room.
+pub fn add_photon_blast(mag: &mut ItemData, pb: u8) {
+    if pb >= 6 || has_photon_blast(mag, pb) {
+        return
+    }
+    let flags = mag.data2[2];
+    if flags & 1 == 0 {
+        mag.data[3] |= pb;
+        mag.data2[2] |= 1;
+    } else if flags & 2 == 0 {
+        mag.data[3] |= pb << 3;
+        mag.data2[2] |= 2;
+    } else if flags & 4 == 0 {
+        let slot = left_candidates(mag).iter().position(|&p| p == pb).unwrap_or(0) as u8;
+        mag.data[3] |= slot << 6;
+        mag.data2[2] |= 4;
+    }
+}
+
+/// The type a mag should become now that it has reached its level, if any.
+fn evolution(mag_edit: &ItemMagEdit, mag: &ItemData, class: u8, section: u8) -> Option<u8> {
+    let level = level(mag);
+    match mag_edit.evolution(mag.data[1]) {
+        0 if level >= 10 => Some([MAG_VARUNA, MAG_KALKI, MAG_VRITRA][class_type(class)]),
+        1 if level >= 35 => LEVEL_35.iter()
+            .find(|&&(from, _)| from == mag.data[1])
+            .map(|&(_, to)| to[highest_stat(mag)]),
+        2 if level >= 50 => Some(LEVEL_50[class_type(class)][section as usize & 1][highest_stat(mag)]),
+        3 if level >= 50 && level % 5 == 0 => {
+            // Only mags that came from the level 50 table change again.
+            let types = &LEVEL_50[class_type(class)][section as usize & 1];
+            if LEVEL_50.iter().any(|c| c.iter().any(|t| t.contains(&mag.data[1]))) {
+                Some(types[highest_stat(mag)])
+            } else {
+                None
+            }
+        },
+        _ => None
+    }
+}
+
+/// Feed a tool to a mag. `class` and `section` are of the character feeding
+/// it, for evolution. Yields false, leaving the mag as it was, if the mag
+/// doesn't eat that.
+pub fn feed(pmt: &ItemPMT, mag_edit: &ItemMagEdit, mag: &mut ItemData, food: &ItemData, class: u8, section: u8) -> bool {
+    if !is_mag(mag) || food.data[0] != 0x03 {
+        return false
+    }
+    let result = match pmt.mag_feed_result(mag.data[1], food.data[1], food.data[2]) {
+        Some(r) => *r,
+        None => return false
+    };
+
+    let synchro = mag.data2[0] as i32 + result.synchro as i32;
+    mag.data2[0] = min(max(synchro, 0), MAX_SYNCHRO) as u8;
+    let iq = mag.data2[1] as i32 + result.iq as i32;
+    mag.data2[1] = min(max(iq, 0), MAX_IQ) as u8;
+
+    // Progress past 100 is a level, unless the mag is at the highest level,
+    // in which case it stops just short.
+    let before = level(mag);
+    let mut total = before;
+    for &(s, delta) in [(DEF, result.def), (POW, result.pow), (DEX, result.dex), (MIND, result.mind)].iter() {
+        let value = stat(mag, s);
+        let mut stat_level = (value / 100) as i32;
+        let mut progress = (value % 100) as i32 + delta as i32;
+        if progress >= 100 {
+            if total < MAX_MAG_LEVEL {
+                stat_level += 1;
+                progress -= 100;
+                total += 1;
+            } else {
+                progress = 99;
+            }
+        }
+        progress = max(progress, 0);
+        set_stat(mag, s, (stat_level * 100 + progress) as u16);
+    }
+    mag.data[2] = total as u8;
+
+    if total > before {
+        if let Some(to) = evolution(mag_edit, mag, class, section) {
+            if to != mag.data[1] {
+                mag.data[1] = to;
+                if let Some(m) = pmt.mag(to) {
+                    add_photon_blast(mag, m.photon_blast);
+                }
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use psodata::chara::ItemData;
+    use psodata::itemmagedit::{ItemMagEdit, NUM_MAG_TYPES};
+    use psodata::itempmt::{ItemPMT, Mag, MagFeedResult};
+
+    use super::*;
+
+    const HUMAR: u8 = 0;
+    const RAMAR: u8 = 3;
+
+    /// Mags learn nothing except Varuna, which learns Golla. Monomates add 50
+    /// to POW, 10 IQ and 10 synchro; Dimates take 50 from DEF and 100 from
+    /// both; Trimates add 100 to DEX and Monofluids 100 to MIND.
+    fn pmt() -> ItemPMT {
+        let mut pmt = ItemPMT::default();
+        let mut mag = Mag::default();
+        mag.photon_blast = 0xFF;
+        pmt.mags = vec![mag; NUM_MAG_TYPES];
+        pmt.mags[MAG_VARUNA as usize].photon_blast = 2;
+        let mut feed = vec![MagFeedResult::default(); 11];
+        feed[0].pow = 50;
+        feed[0].iq = 10;
+        feed[0].synchro = 10;
+        feed[1].def = -50;
+        feed[1].iq = -100;
+        feed[1].synchro = -100;
+        feed[2].dex = 100;
+        feed[3].mind = 100;
+        pmt.mag_feed_tables = vec![feed];
+        pmt
+    }
+
+    /// Mag, Varuna, Marutah and Varaha are at evolution stages 0 to 3.
+    fn mag_edit() -> ItemMagEdit {
+        let mut evolution = vec![4; NUM_MAG_TYPES];
+        evolution[0x00] = 0;
+        evolution[MAG_VARUNA as usize] = 1;
+        evolution[0x0F] = 2;
+        evolution[0x05] = 3;
+        ItemMagEdit { evolution: evolution }
+    }
+
+    fn mag(kind: u8, stats: [u16; 4]) -> ItemData {
+        let mut item = ItemData::default();
+        item.data[0] = 0x02;
+        item.data[1] = kind;
+        for (s, &v) in stats.iter().enumerate() {
+            set_stat(&mut item, s, v);
+        }
+        item.data[2] = level(&item) as u8;
+        item
+    }
+
+    fn tool(kind: u8, index: u8) -> ItemData {
+        let mut item = ItemData::default();
+        item.data[0] = 0x03;
+        item.data[1] = kind;
+        item.data[2] = index;
+        item
+    }
+
+    const MONOMATE: (u8, u8) = (0x00, 0x00);
+    const DIMATE: (u8, u8) = (0x00, 0x01);
+    const TRIMATE: (u8, u8) = (0x00, 0x02);
+    const MONOFLUID: (u8, u8) = (0x01, 0x00);
+
+    fn feed_with(m: &mut ItemData, food: (u8, u8), class: u8, section: u8) -> bool {
+        feed(&pmt(), &mag_edit(), m, &tool(food.0, food.1), class, section)
+    }
+
+    #[test]
+    fn test_feed_carries_progress() {
+        let mut m = mag(0x00, [500, 580, 0, 0]);
+        assert!(feed_with(&mut m, MONOMATE, HUMAR, 0));
+        assert_eq!(stat(&m, POW), 630);
+        assert_eq!(stat_levels(&m), [5, 6, 0, 0]);
+        assert_eq!(m.data[2], 11);
+
+        // Progress doesn't go below the level.
+        let mut m = mag(0x00, [520, 0, 0, 0]);
+        assert!(feed_with(&mut m, DIMATE, HUMAR, 0));
+        assert_eq!(stat(&m, DEF), 500);
+
+        // Mags don't eat everything, and only mags eat.
+        let before = m.data.clone();
+        assert!(!feed(&pmt(), &mag_edit(), &mut m, &tool(0x0B, 0x00), HUMAR, 0));
+        assert_eq!(m.data, before);
+        let mut not_mag = tool(0x00, 0x00);
+        assert!(!feed_with(&mut not_mag, MONOMATE, HUMAR, 0));
+    }
+
+    #[test]
+    fn test_feed_level_cap() {
+        let mut m = mag(0x04, [5000, 5080, 5000, 5000]);
+        assert_eq!(level(&m), MAX_MAG_LEVEL);
+        assert!(feed_with(&mut m, MONOMATE, HUMAR, 0));
+        assert_eq!(stat(&m, POW), 5099);
+        assert_eq!(level(&m), MAX_MAG_LEVEL);
+        assert_eq!(m.data[2] as u32, MAX_MAG_LEVEL);
+    }
+
+    #[test]
+    fn test_feed_clamps_synchro_and_iq() {
+        let mut m = mag(0x04, [500, 0, 0, 0]);
+        m.data2[0] = 115;
+        m.data2[1] = 195;
+        assert!(feed_with(&mut m, MONOMATE, HUMAR, 0));
+        assert_eq!(m.data2[0] as i32, MAX_SYNCHRO);
+        assert_eq!(m.data2[1] as i32, MAX_IQ);
+        assert!(feed_with(&mut m, DIMATE, HUMAR, 0));
+        assert!(feed_with(&mut m, DIMATE, HUMAR, 0));
+        assert_eq!(m.data2[0], 0);
+        assert_eq!(m.data2[1], 0);
+    }
+
+    #[test]
+    fn test_evolution() {
+        // Level 10 by class type, learning the new type's photon blast.
+        let mut m = mag(0x00, [500, 480, 0, 0]);
+        assert!(feed_with(&mut m, MONOMATE, HUMAR, 0));
+        assert_eq!(m.data[1], MAG_VARUNA);
+        assert!(has_photon_blast(&m, 2));
+        assert_eq!(m.data2[2], 1);
+        let mut m = mag(0x00, [500, 480, 0, 0]);
+        assert!(feed_with(&mut m, MONOMATE, RAMAR, 0));
+        assert_eq!(m.data[1], MAG_KALKI);
+
+        // Level 35 by the highest of POW, DEX and MIND.
+        let mut m = mag(MAG_VARUNA, [500, 1080, 1000, 900]);
+        assert!(feed_with(&mut m, MONOMATE, HUMAR, 0));
+        assert_eq!(m.data[1], 0x0E);
+        let mut m = mag(MAG_VARUNA, [500, 1000, 1000, 900]);
+        assert!(feed_with(&mut m, TRIMATE, HUMAR, 0));
+        assert_eq!(m.data[1], 0x0F);
+
+        // Level 50 by class type, section ID and highest stat.
+        let mut m = mag(0x0F, [500, 1400, 1500, 1500]);
+        assert!(feed_with(&mut m, MONOFLUID, HUMAR, 1));
+        assert_eq!(m.data[1], 0x05);
+        let mut m = mag(0x0F, [500, 1400, 1500, 1500]);
+        assert!(feed_with(&mut m, MONOFLUID, HUMAR, 0));
+        assert_eq!(m.data[1], 0x0C);
+
+        // And again every five levels after.
+        let mut m = mag(0x05, [500, 1500, 1500, 1500]);
+        assert!(feed_with(&mut m, TRIMATE, HUMAR, 1));
+        assert_eq!(level(&m), 51);
+        assert_eq!(m.data[1], 0x05);
+        let mut m = mag(0x05, [500, 1980, 1500, 1500]);
+        assert!(feed_with(&mut m, MONOMATE, HUMAR, 1));
+        assert_eq!(level(&m), 55);
+        assert_eq!(m.data[1], 0x06);
+
+        // Nothing happens short of the level.
+        let mut m = mag(0x00, [400, 480, 0, 0]);
+        assert!(feed_with(&mut m, MONOMATE, HUMAR, 0));
+        assert_eq!(m.data[1], 0x00);
+    }
+
+    #[test]
+    fn test_photon_blast_slots() {
+        let mut m = mag(0x00, [0, 0, 0, 0]);
+        add_photon_blast(&mut m, 1);
+        add_photon_blast(&mut m, 1);
+        add_photon_blast(&mut m, 3);
+        assert_eq!(m.data[3], 1 | 3 << 3);
+        assert_eq!(m.data2[2], 3);
+        assert_eq!(left_candidates(&m), vec![0, 2, 4, 5]);
+
+        add_photon_blast(&mut m, 4);
+        assert_eq!(m.data[3] >> 6, 2);
+        assert_eq!(m.data2[2], 7);
+        assert_eq!(left_photon_blast(&m), Some(4));
+        assert!(has_photon_blast(&m, 1) && has_photon_blast(&m, 3) && has_photon_blast(&m, 4));
+        assert!(!has_photon_blast(&m, 0));
+
+        // No room for more.
+        let full = m.data.clone();
+        add_photon_blast(&mut m, 5);
+        assert_eq!(m.data, full);
+    }
+}
@@ -1,28 +1,40 @@
 //! Where the majority of the game occurs.
 
 use std::sync::Arc;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::mem;
 
 pub mod error;
 pub mod enemygen;
+pub mod floor;
+pub mod mag;
+pub mod shop;
+pub mod tekker;
 
-use rand::random;
+use rand::{random, thread_rng};
 
 use psomsg::bb::Message as BbMsg;
 use psomsg::bb::*;
 
-use psodata::map::MapEnemy;
-
-use ::maps::{Areas, InstanceEnemy, Ep1Areas, Ep2Areas, Ep4Areas};
+use ::quests::Quest;
+use ::maps::{Areas, InstanceEnemy, InstanceObject, VariationData, Ep1Areas, Ep2Areas, Ep4Areas};
+use ::droptables::DropMode;
 
 use super::handler::BlockHandler;
 
 use self::error::PartyError;
-use self::enemygen::convert_enemy;
+use self::enemygen::{convert_enemy, RareRates, RareRolls};
+use self::floor::{FloorItem, FloorItems, create_item_msg};
+use self::shop::{Shop, MAX_SHOP_ITEMS};
+use self::tekker::TEKKER_COST;
+
+/// Bank items are numbered from here, with the player's slot in bits 20-21.
+const BANK_ITEM_ID_BASE: u32 = 0x99000000;
 
 static SLASH_COMMAND_HELP_MSG: &'static str = "\tC6Slash commands\tC7
 /help -- Show this message
 /giveexp <exp> -- Give yourself <exp>
+/bank -- Switch between your character's bank and your common bank
 ";
 
 #[derive(Clone, Debug)]
@@ -35,6 +47,7 @@ pub struct Party {
     pub challenge: bool,
     pub single_player: bool,
     pub unique_id: u32,
+    event: u16,
     section_id: Option<u8>,
     members: [Option<usize>; 4],
     bursting: [bool; 4],
@@ -42,11 +55,29 @@ pub struct Party {
     maps: Arc<Areas>,
     variants: Vec<u32>,
     enemies: Vec<InstanceEnemy>,
+    rare_enemies: Vec<u16>,
+    dropped_enemies: HashSet<u16>,
+    objects: Vec<InstanceObject>,
+    opened_boxes: HashSet<u16>,
     bc_queue: VecDeque<(usize, Message)>,
-    items: Vec<InvItem>,
+    floor: FloorItems,
     next_drop_pos: [Option<NextDropPos>; 4],
+    shops: [Option<Shop>; 4],
+    identified: [Option<ItemData>; 4],
     player_drop_counter: [u32; 4],
-    party_drop_counter: u32
+    party_drop_counter: u32,
+    /// The quest being played, as an index into `Quests::quests`.
+    pub quest: Option<usize>,
+    quest_loading: [bool; 4],
+    /// The party's own enemies and objects, put aside while a quest is played.
+    free_play: Option<FreePlay>
+}
+
+#[derive(Clone, Debug)]
+struct FreePlay {
+    enemies: Vec<InstanceEnemy>,
+    rare_enemies: Vec<u16>,
+    objects: Vec<InstanceObject>
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -59,24 +90,26 @@ struct NextDropPos {
 }
 
 impl Party {
-    pub fn new(name: &str, password: Option<&str>, episode: u8, difficulty: u8, battle: bool, challenge: bool, single_player: bool, event: u16, maps: Arc<Areas>, unique_id: u32) -> Party {
-        // pick random variants for each map based on episode
-        let (variants, enemies) = match episode {
+    pub fn new(name: &str, password: Option<&str>, episode: u8, difficulty: u8, battle: bool, challenge: bool, single_player: bool, event: u16, maps: Arc<Areas>, rare_rates: &RareRates, unique_id: u32) -> Party {
+        // pick random variants for each map based on episode, rolling rare
+        // enemies as they're placed
+        let mut rares = RareRolls::new(rare_rates);
+        let (variants, enemies, objects) = match episode {
             1 => {
                 info!("Generating episode 1 party");
-                Party::random_variants_ep1(&maps.ep1, event, difficulty > 0)
+                Party::random_variants_ep1(&maps.ep1, event, difficulty > 0, &mut rares)
             },
             2 => {
                 info!("Generating episode 2 party");
-                Party::random_variants_ep2(&maps.ep2, event)
+                Party::random_variants_ep2(&maps.ep2, event, &mut rares)
             },
             3 => {
                 info!("Generating episode 4 party");
-                Party::random_variants_ep4(&maps.ep4, event)
+                Party::random_variants_ep4(&maps.ep4, event, &mut rares)
             },
             _ => panic!("unsupported episode")
         };
-        info!("{} total enemies", enemies.len());
+        info!("{} total enemies ({} rare), {} total objects", enemies.len(), rares.enemies.len(), objects.len());
         Party {
             name: name.to_owned(),
             password: password.map(|s| s.to_owned()),
@@ -86,6 +119,7 @@ impl Party {
             challenge: challenge,
             single_player: single_player,
             unique_id: unique_id,
+            event: event,
             section_id: None,
             members: Default::default(),
             bursting: Default::default(),
@@ -94,10 +128,19 @@ impl Party {
             maps: maps,
             variants: variants,
             enemies: enemies,
-            items: Vec::new(),
+            rare_enemies: rares.enemies,
+            dropped_enemies: Default::default(),
+            objects: objects,
+            opened_boxes: Default::default(),
+            floor: Default::default(),
             next_drop_pos: Default::default(),
+            shops: Default::default(),
+            identified: Default::default(),
             player_drop_counter: Default::default(),
-            party_drop_counter: 0x00810000
+            party_drop_counter: 0x00810000,
+            quest: None,
+            quest_loading: Default::default(),
+            free_play: None
         }
     }
 
@@ -173,6 +216,9 @@ impl Party {
         }
         let r: Message = Message::BbGameJoin(self.num_players() as u32, l);
         handler.send_to_client(player, r);
+        // every client has to be told the same rares, or they'd disagree on
+        // which enemies are which
+        handler.send_to_client(player, BbRareMonsterList(self.rare_enemies.clone()).into());
 
         self.bursting[new_client_id as usize] = true;
 
@@ -246,6 +292,15 @@ impl Party {
                 self.members[i as usize] = None;
                 // ensure their bursting flag is unset
                 self.bursting[i as usize] = false;
+                self.shops[i as usize] = None;
+                self.identified[i as usize] = None;
+                // don't keep the others waiting on them to load a quest
+                if self.quest_loading[i as usize] {
+                    self.quest_loading[i as usize] = false;
+                    if !self.quest_loading.iter().any(|&l| l) {
+                        self.bb_broadcast(handler, Some(player), BbQuestLoadDone.into()).unwrap();
+                    }
+                }
 
                 // tell the other clients that this player has left, and maybe
                 // the new elected leader
@@ -266,6 +321,104 @@ impl Party {
         Ok(ret)
     }
 
+    /// Start a quest. The party's enemies and objects are replaced with the
+    /// quest's, so EXP and drops follow what the quest placed, and its files
+    /// are sent to everyone.
+    pub fn load_quest(&mut self, handler: &mut BlockHandler, index: usize, quest: &Quest) -> Result<(), PartyError> {
+        if self.quest.is_some() {
+            return Err(PartyError::QuestInProgress)
+        }
+        info!("Party \"{}\" is starting quest {}", &self.name[2..], quest.number);
+
+        let rare_rates = handler.rare_rates.clone();
+        let mut rares = RareRolls::new(&rare_rates);
+        let mut enemies = Vec::new();
+        for &(area, ref e) in quest.enemies.iter() {
+            let i = enemies.len();
+            // the Episode 4 desert has its own versions of the wilds' enemies
+            let alt_enemies = self.episode == 3 && area >= 6 && area <= 8;
+            enemies.append(&mut convert_enemy(e, i, self.episode, self.event, alt_enemies, &mut rares));
+        }
+        if self.episode == 1 && self.difficulty > 0 {
+            for e in enemies.iter_mut().filter(|e| e.param_entry == 0x37) {
+                e.param_entry = 0x38;
+            }
+        }
+        info!("{} quest enemies ({} rare), {} quest objects", enemies.len(), rares.enemies.len(), quest.objects.len());
+        let objects = quest.objects.iter().map(|&(_, ref o)| InstanceObject { data: o.clone() }).collect();
+        self.free_play = Some(FreePlay {
+            enemies: mem::replace(&mut self.enemies, enemies),
+            rare_enemies: mem::replace(&mut self.rare_enemies, rares.enemies),
+            objects: mem::replace(&mut self.objects, objects)
+        });
+        self.dropped_enemies.clear();
+        self.opened_boxes.clear();
+        self.quest = Some(index);
+
+        let name = format!("PSO/{}", quest.name);
+        for (i, co) in self.members.iter().enumerate() {
+            let cid = match co {
+                &Some(cid) => cid,
+                None => continue
+            };
+            self.quest_loading[i] = true;
+            handler.send_to_client(cid, BbRareMonsterList(self.rare_enemies.clone()).into());
+            for &(ext, data) in [("bin", &quest.bin), ("dat", &quest.dat)].iter() {
+                let filename = quest.file_name(ext);
+                let f = BbQuestFile {
+                    name: name.clone(),
+                    filename: filename.clone(),
+                    length: data.len() as u32
+                };
+                handler.send_to_client(cid, f.into());
+                for (n, chunk) in data.chunks(QUEST_CHUNK_SIZE).enumerate() {
+                    let c = BbQuestChunk {
+                        filename: filename.clone(),
+                        data: chunk.to_vec()
+                    };
+                    handler.send_to_client(cid, Message::BbQuestChunk(n as u32, c));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// End the quest being played, if there is one, and put back the party's
+    /// own enemies and objects.
+    pub fn end_quest(&mut self, handler: &mut BlockHandler) {
+        let index = match self.quest.take() {
+            Some(i) => i,
+            None => return
+        };
+        info!("Party \"{}\" finished quest {}", &self.name[2..], index);
+        if let Some(free_play) = self.free_play.take() {
+            self.enemies = free_play.enemies;
+            self.rare_enemies = free_play.rare_enemies;
+            self.objects = free_play.objects;
+        }
+        self.dropped_enemies.clear();
+        self.opened_boxes.clear();
+        self.quest_loading = Default::default();
+        self.bb_broadcast(handler, None, BbRareMonsterList(self.rare_enemies.clone()).into()).unwrap();
+    }
+
+    /// A player has loaded the quest. Once everyone has, they're all told to
+    /// start.
+    pub fn handle_bb_quest_load_done(&mut self, handler: &mut BlockHandler) -> Result<(), PartyError> {
+        let slot = match self.client_id_for_player(handler.client_id) {
+            Some(s) => s as usize,
+            None => return Err(PartyError::NotInParty)
+        };
+        if !self.quest_loading[slot] {
+            return Ok(())
+        }
+        self.quest_loading[slot] = false;
+        if !self.quest_loading.iter().any(|&l| l) {
+            self.bb_broadcast(handler, None, BbQuestLoadDone.into()).unwrap();
+        }
+        Ok(())
+    }
+
     pub fn handle_bb_game_name(&mut self, handler: &mut BlockHandler) -> Result<(), PartyError> {
         handler.send_to_client(handler.client_id, BbGameName(self.name.clone()).into());
         Ok(())
@@ -396,6 +549,10 @@ impl Party {
                         handler.send_to_client(sender, reply);
                         return Ok(())
                     },
+                    "/bank" => {
+                        handler.switch_bank(sender);
+                        return Ok(())
+                    },
                     "/giveexp" => {
                         if let Some(exp) = s_w.next().and_then(|ww| ww.parse().ok()) {
                             info!("Client {} awarded themselves {} exp", sender, exp);
@@ -433,6 +590,9 @@ impl Party {
                 }
             }
         }
+        if !handler.track_inventory(sender, &m) {
+            return Ok(())
+        }
         match m.clone() {
             BbSubCmd60::Bb60ReqExp { data: r, .. } => {
                 self.handle_bb_60_req_exp(handler, r);
@@ -486,15 +646,39 @@ impl Party {
         }
         match &m {
             &BbSubCmd62::Bb62OpenBank { ref data, .. } => {
-                self.handle_bb_openbank(handler, data.clone());
+                self.handle_bb_openbank(handler, sender, data.clone());
+                handled = true;
+            },
+            &BbSubCmd62::Bb62BankAction { ref data, .. } => {
+                self.handle_bb_bank_action(handler, sender, data.clone());
                 handled = true;
             },
             &BbSubCmd62::Bb62ShopReq { ref data, .. } => {
-                self.handle_bb_shopreq(handler, data.clone());
+                self.handle_bb_shopreq(handler, sender, data.clone());
+                handled = true;
+            },
+            &BbSubCmd62::Bb62ShopBuy { ref data, .. } => {
+                self.handle_bb_shop_buy(handler, sender, data.clone());
+                handled = true;
+            },
+            &BbSubCmd62::Bb62IdentifyItem { ref data, .. } => {
+                self.handle_bb_identify_item(handler, sender, data.clone());
+                handled = true;
+            },
+            &BbSubCmd62::Bb62AcceptIdentify { ref data, .. } => {
+                self.handle_bb_accept_identify(handler, sender, data.clone());
                 handled = true;
             },
             &BbSubCmd62::Bb62PickUp { ref data, .. } => {
-                self.handle_bb_pick_up(handler, dest, data.clone());
+                self.handle_bb_pick_up(handler, sender, dest, data.clone());
+                handled = true;
+            },
+            &BbSubCmd62::Bb62ItemReq { ref data, .. } => {
+                self.handle_bb_item_req(handler, sender, data.clone());
+                handled = true;
+            },
+            &BbSubCmd62::Bb62BoxItemReq { ref data, .. } => {
+                self.handle_bb_box_item_req(handler, sender, data.clone());
                 handled = true;
             }
             _ => ()
@@ -591,12 +775,13 @@ impl Party {
             };
 
             if let Some(bp) = bp {
+                let rates = handler.rates.get();
                 if m.last_hitter == 1 {
-                    let exp = bp.exp;
+                    let exp = rates.scale_exp(bp.exp, true);
                     info!("Client {} request verified; +{} EXP for last-hitting on {} ({})", cid, exp, enemy.name, m.enemy_id);
                     self.award_exp(cid, handler, exp);
                 } else {
-                    let exp = bp.exp * 80 / 100;
+                    let exp = rates.scale_exp(bp.exp, false);
                     info!("Client {} request verified; +{} EXP for assisting on {} ({})", cid, exp, enemy.name, m.enemy_id);
                     self.award_exp(cid, handler, exp);
                 }
@@ -614,8 +799,29 @@ impl Party {
     pub fn handle_bb_dropitem(&mut self, handler: &mut BlockHandler, m: Bb60DropItem, slot: u8) {
         let cid = handler.client_id;
         info!("Client {} dropping item: {:?}", cid, m);
-        warn!("Item dropping is stubbed");
-        // TODO Add this item to the lobby inventory.
+        if self.client_id_for_player(cid) != Some(slot) {
+            warn!("Client {} tried to drop an item as client ID {}", cid, slot);
+            return
+        }
+
+        let data = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            c.full_char.as_mut().and_then(|fc| fc.inv.take(m.item_id, 0, 0))
+        };
+        let data = match data {
+            Some(d) => d,
+            None => {
+                warn!("Client {} tried to drop item {:08X}, which isn't in their inventory", cid, m.item_id);
+                return
+            }
+        };
+        self.floor.add(FloorItem {
+            area: m.area as u32,
+            x: m.x,
+            z: m.z,
+            data: data
+        });
 
         self.bb_broadcast(handler, Some(cid), BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60DropItem { client_id: slot, unused: 0, data: m})).unwrap();
     }
@@ -639,62 +845,470 @@ impl Party {
     pub fn handle_bb_delete_item(&mut self, handler: &mut BlockHandler, m: Bb60DeleteItem, slot: u8) {
         let cid = handler.client_id;
 
-        warn!("Stack dropping is stubbed");
-
-        if let Some(nd) = self.next_drop_pos[slot as usize] {
-            // we need to get the item they are dropping from their inventory, but we don't do character tracking yet
-            // first, drop the stack for everyone
-            info!("Dropping item stack from item ID {}", m.item_id);
-            self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60DropStack { client_id: slot, unused: 0, data: Bb60DropStack {
-                area: nd.area,
-                x: nd.x,
-                z: nd.z,
-                item: [4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-                item_id: self.player_drop_counter[slot as usize],
-                item2: [nd.amount as u8, 0, 0, 0]
-            }})).unwrap();
+        let nd = match self.next_drop_pos[slot as usize] {
+            Some(nd) if nd.item_id == m.item_id && self.client_id_for_player(cid) == Some(slot) => nd,
+            _ => {
+                warn!("Client {} tried to drop stack without sending drop pos first", cid);
+                handler.send_fatal_error(cid, "\tEIllegal message.");
+                return
+            }
+        };
+        self.next_drop_pos[slot as usize] = None;
 
+        // Part of a stack becomes a new item, with the next ID the client
+        // expects from us.
+        let new_id = self.player_drop_counter[slot as usize];
+        let data = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            let fc = c.full_char.as_mut().unwrap();
+            if m.item_id == 0xFFFFFFFF {
+                if m.amount == 0 || fc.chara.meseta < m.amount {
+                    None
+                } else {
+                    fc.chara.meseta -= m.amount;
+                    Some(ItemData::meseta(m.amount, new_id))
+                }
+            } else {
+                fc.inv.take(m.item_id, m.amount, new_id)
+            }
+        };
+        let data = match data {
+            Some(d) => d,
+            None => {
+                warn!("Client {} tried to drop {} of item {:08X}, which they don't have", cid, m.amount, m.item_id);
+                return
+            }
+        };
+        if data.item_id == new_id {
             self.player_drop_counter[slot as usize] += 1;
+        }
 
-            // broadcast delete item from inventory
-            self.bb_broadcast(handler, Some(cid), BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60DeleteItem { client_id: slot, unused: 0, data: m })).unwrap();
+        let item = FloorItem {
+            area: nd.area,
+            x: nd.x,
+            z: nd.z,
+            data: data
+        };
+        info!("Dropping item stack {:08X} from item ID {:08X}", item.data.item_id, m.item_id);
+        // first, drop the stack for everyone
+        self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60DropStack { client_id: slot, unused: 0, data: item.drop_stack_msg() })).unwrap();
+        self.floor.add(item);
 
-            self.next_drop_pos[slot as usize] = None;
-        } else {
-            warn!("Client {} tried to drop stack without sending drop pos first", cid);
-            handler.send_fatal_error(cid, "\tEIllegal message.");
+        // broadcast delete item from inventory
+        self.bb_broadcast(handler, Some(cid), BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60DeleteItem { client_id: slot, unused: 0, data: m })).unwrap();
+    }
+
+    pub fn handle_bb_pick_up(&mut self, handler: &mut BlockHandler, cid: usize, _dest: u32, m: Bb62PickUp) {
+        debug!("Client {} picking up item {:08X}", cid, m.item_id);
+        let slot = match self.client_id_for_player(cid) {
+            Some(s) => s,
+            None => return
+        };
+
+        // Whoever asks first gets it; anyone after finds it gone.
+        let item = match self.floor.take(m.area, m.item_id) {
+            Some(i) => i,
+            None => {
+                debug!("Item {:08X} isn't on the floor of area {}", m.item_id, m.area);
+                return
+            }
+        };
+
+        let picked_up = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            let fc = c.full_char.as_mut().unwrap();
+            if item.data.is_meseta() {
+                if fc.chara.meseta >= MAX_MESETA {
+                    false
+                } else {
+                    fc.chara.meseta = ::std::cmp::min(fc.chara.meseta + item.data.meseta_amount(), MAX_MESETA);
+                    true
+                }
+            } else {
+                fc.inv.add(item.data.clone())
+            }
+        };
+        if !picked_up {
+            self.floor.add(item);
+            handler.send_error(cid, "\tEYou can't carry\nany more of that.");
             return
         }
+
+        self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60PickedUp { client_id: slot, unused: 0, data: Bb60PickedUp {
+            client_id: slot as u16,
+            area: m.area as u16,
+            item_id: m.item_id
+        }})).unwrap();
+        self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60CreateItem { client_id: slot, unused: 0, data: create_item_msg(&item.data) })).unwrap();
     }
 
-    pub fn handle_bb_pick_up(&mut self, handler: &mut BlockHandler, _dest: u32, _m: Bb62PickUp) {
-        let cid = handler.client_id;
-        debug!("Client {} picking up item", cid);
-        warn!("Item pick up is stubbed");
-        // do nothing yet, we need to track characters and lobby items to be able to do this
+    /// The leader's client asks for the drop of each enemy it sees die. The
+    /// server rolls it, so every client sees the same item.
+    pub fn handle_bb_item_req(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62ItemReq) {
+        if self.client_id_for_player(cid).is_none() {
+            return
+        }
+        let mode = match self.drop_mode() {
+            Some(mode) => mode,
+            None => return
+        };
+        let rt_entry = match self.enemies.get(m.req as usize) {
+            Some(enemy) => {
+                debug!("Client {} requested drop for {} ({}, param entry {:02X}, rt entry {:02X})", cid, enemy.name, m.req, enemy.param_entry, enemy.rt_entry);
+                enemy.rt_entry
+            },
+            None => {
+                warn!("Client {} requested drop for an enemy that doesn't exist: {}", cid, m.req);
+                return
+            }
+        };
+        if !self.dropped_enemies.insert(m.req) {
+            debug!("Enemy {} already dropped", m.req);
+            return
+        }
+
+        let data = handler.drop_table.enemy_drop(&mut thread_rng(), &mode, &handler.rates.get(), rt_entry, m.area);
+        if let Some(data) = data {
+            self.drop_new_item(handler, data, m.area, m.x, m.y, 1, m.req);
+        }
     }
 
-    pub fn handle_bb_openbank(&mut self, handler: &mut BlockHandler, m: Bb62OpenBank) {
-        let cid = handler.client_id;
+    /// Like enemy drops, box drops are requested by the leader's client.
+    pub fn handle_bb_box_item_req(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62BoxItemReq) {
+        if self.client_id_for_player(cid).is_none() {
+            return
+        }
+        let mode = match self.drop_mode() {
+            Some(mode) => mode,
+            None => return
+        };
+        if m.req as usize >= self.objects.len() {
+            warn!("Client {} requested drop for a box that doesn't exist: {}", cid, m.req);
+            return
+        }
+        if !self.opened_boxes.insert(m.req) {
+            debug!("Box {} already dropped", m.req);
+            return
+        }
+
+        let data = handler.drop_table.box_drop(&mut thread_rng(), &mode, &handler.rates.get(), &self.objects[m.req as usize].data, m.area);
+        if let Some(data) = data {
+            self.drop_new_item(handler, data, m.area, m.x, m.z, 2, m.req);
+        }
+    }
+
+    /// Everything the drop tables need to know about this party. None until
+    /// the section ID is known.
+    fn drop_mode(&self) -> Option<DropMode> {
+        self.section_id.map(|sid| DropMode {
+            episode: self.episode,
+            challenge: self.challenge,
+            difficulty: self.difficulty,
+            section_id: sid
+        })
+    }
+
+    /// Give a freshly rolled item an ID and put it on the floor for everyone.
+    fn drop_new_item(&mut self, handler: &mut BlockHandler, mut data: ItemData, area: u8, x: f32, z: f32, source: u8, req: u16) {
+        data.item_id = self.party_drop_counter;
+        self.party_drop_counter += 1;
+        let item = FloorItem {
+            area: area as u32,
+            x: x,
+            z: z,
+            data: data
+        };
+        info!("Dropping item {:08X} ({:?}) in area {}", item.data.item_id, item.data.data, area);
+        self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60ItemDrop { client_id: 0, unused: 0, data: item.item_drop_msg(source, req) })).unwrap();
+        self.floor.add(item);
+    }
+
+    /// Show the player their bank. Bank items get IDs of their own each time
+    /// it's opened, so they can't clash with anything in the party.
+    pub fn handle_bb_openbank(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62OpenBank) {
         debug!("Client {} opening bank: {:?}", cid, m);
-        warn!("Bank inventory is stubbed");
-        // deliver a blank bank inventory msg...
-        handler.send_to_client(cid, BbMsg::BbSubCmd6D(0, BbSubCmd6D::Bb6DBankInv { flags: 0, unused: 0, data: Bb6DBankInv::default() }));
+        let slot = match self.client_id_for_player(cid) {
+            Some(s) => s,
+            None => return
+        };
+        let bank = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            let fc = c.full_char.as_mut().unwrap();
+            fc.bank.assign_ids(BANK_ITEM_ID_BASE | (slot as u32) << 20);
+            Bb6DBankInv {
+                checksum: random(),
+                meseta: fc.bank.meseta,
+                items: fc.bank.items.clone()
+            }
+        };
+        handler.send_to_client(cid, BbMsg::BbSubCmd6D(0, BbSubCmd6D::Bb6DBankInv { flags: 0, unused: 0, data: bank }));
     }
 
-    pub fn handle_bb_shopreq(&mut self, handler: &mut BlockHandler, m: Bb62ShopReq) {
-        let cid = handler.client_id;
+    /// Move an item or meseta between the player's inventory and their bank.
+    /// The bank is saved straight away.
+    pub fn handle_bb_bank_action(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62BankAction) {
+        let slot = match self.client_id_for_player(cid) {
+            Some(s) => s,
+            None => return
+        };
+        let deposit = match m.action {
+            0 => true,
+            1 => false,
+            _ => return
+        };
+        let new_id = self.player_drop_counter[slot as usize];
+
+        // The item that has to be shown to change hands, if any.
+        let moved = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            let fc = c.full_char.as_mut().unwrap();
+            if m.item_id == 0xFFFFFFFF {
+                let amount = m.meseta_amount;
+                if deposit && fc.chara.meseta >= amount && fc.bank.meseta + amount <= MAX_BANK_MESETA {
+                    fc.chara.meseta -= amount;
+                    fc.bank.meseta += amount;
+                } else if !deposit && fc.bank.meseta >= amount && fc.chara.meseta + amount <= MAX_MESETA {
+                    fc.bank.meseta -= amount;
+                    fc.chara.meseta += amount;
+                } else {
+                    warn!("Client {} can't move {} meseta {} the bank", cid, amount, if deposit { "into" } else { "out of" });
+                    return
+                }
+                info!("Client {} {} {} meseta", cid, if deposit { "deposited" } else { "withdrew" }, amount);
+                None
+            } else if deposit {
+                if fc.inv.find(m.item_id).map(|i| i.flags & ITEM_EQUIPPED != 0).unwrap_or(true) {
+                    warn!("Client {} tried to deposit item {:08X}, which they don't have or have equipped", cid, m.item_id);
+                    return
+                }
+                let data = match fc.inv.take(m.item_id, m.item_amount as u32, new_id) {
+                    Some(d) => d,
+                    None => {
+                        warn!("Client {} tried to deposit {} of item {:08X}, which they don't have", cid, m.item_amount, m.item_id);
+                        return
+                    }
+                };
+                if !fc.bank.add(data.clone()) {
+                    fc.inv.add(data);
+                    handler.send_error(cid, "\tEYour bank is full.");
+                    return
+                }
+                info!("Client {} deposited item {:08X} ({:?})", cid, m.item_id, data.data);
+                Some(data)
+            } else {
+                let data = match fc.bank.take(m.item_id, m.item_amount as u32, new_id) {
+                    Some(d) => d,
+                    None => {
+                        warn!("Client {} tried to withdraw {} of item {:08X}, which isn't in their bank", cid, m.item_amount, m.item_id);
+                        return
+                    }
+                };
+                let mut withdrawn = data.clone();
+                withdrawn.item_id = new_id;
+                if !fc.inv.add(withdrawn.clone()) {
+                    fc.bank.add(data);
+                    handler.send_error(cid, "\tEYou can't carry\nany more of that.");
+                    return
+                }
+                info!("Client {} withdrew item {:08X} ({:?})", cid, m.item_id, withdrawn.data);
+                Some(withdrawn)
+            }
+        };
+
+        match moved {
+            Some(ref data) if deposit => {
+                // Everyone else still thinks the player is holding it.
+                if data.item_id == new_id {
+                    self.player_drop_counter[slot as usize] += 1;
+                }
+                self.bb_broadcast(handler, Some(cid), BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60DeleteItem { client_id: slot, unused: 0, data: Bb60DeleteItem {
+                    item_id: m.item_id,
+                    amount: data.stack_count()
+                }})).unwrap();
+            },
+            Some(ref data) => {
+                self.player_drop_counter[slot as usize] += 1;
+                self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60CreateItem { client_id: slot, unused: 0, data: create_item_msg(data) })).unwrap();
+            },
+            None => ()
+        }
+        handler.save_character(cid);
+    }
+
+    /// Roll a shop's stock for the player and show it to them. It stays as
+    /// it is until they open a shop again.
+    pub fn handle_bb_shopreq(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62ShopReq) {
         debug!("Client {} opening shop: {:?}", cid, m);
-        warn!("Shop inventory is stubbed");
+        let slot = match self.client_id_for_player(cid) {
+            Some(s) => s,
+            None => return
+        };
+        let level = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let c = cr.borrow();
+            c.full_char.as_ref().unwrap().chara.level + 1
+        };
+        let shop = Shop::generate(&mut thread_rng(), &handler.item_pmt, m.shop_type, level, self.difficulty);
+
         let mut reply = Bb62ShopInv::default();
-        reply.num_items = 1;
-        reply.items[0].item_data[0] = 0x00000103;
-        reply.items[0].reserved = 0xFFFFFFFF;
-        reply.items[0].cost = 10;
         reply.shop_type = m.shop_type;
+        reply.num_items = shop.items.len() as u8;
+        for (i, item) in shop.items.iter().take(MAX_SHOP_ITEMS).enumerate() {
+            let d = &item.data.data;
+            for w in 0..3 {
+                reply.items[i].item_data[w] = (d[w * 4] as u32) | (d[w * 4 + 1] as u32) << 8 | (d[w * 4 + 2] as u32) << 16 | (d[w * 4 + 3] as u32) << 24;
+            }
+            reply.items[i].reserved = 0xFFFFFFFF;
+            reply.items[i].cost = item.price;
+        }
+        self.shops[slot as usize] = Some(shop);
         handler.send_to_client(cid, BbMsg::BbSubCmd62(0, BbSubCmd62::Bb62ShopInv { client_id: 0, unused: 0, data: reply }));
     }
 
+    /// Sell an item from the shop the player last opened. The client names
+    /// the new item itself.
+    pub fn handle_bb_shop_buy(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62ShopBuy) {
+        let slot = match self.client_id_for_player(cid) {
+            Some(s) => s,
+            None => return
+        };
+        let item = match self.shops[slot as usize] {
+            Some(ref shop) if shop.shop_type == m.shop_type => shop.items.get(m.shop_index as usize).cloned(),
+            _ => None
+        };
+        let item = match item {
+            Some(i) => i,
+            None => {
+                warn!("Client {} tried to buy item {} of shop {}, which isn't for sale", cid, m.shop_index, m.shop_type);
+                return
+            }
+        };
+
+        let mut data = item.data.clone();
+        data.item_id = m.item_id;
+        let amount = if data.is_stackable() { ::std::cmp::max(m.amount as u32, 1) } else { 1 };
+        if amount > data.max_stack() {
+            warn!("Client {} tried to buy {} of shop item {:?}, more than a stack holds", cid, amount, data.data);
+            return
+        }
+        data.set_stack_count(amount);
+        let price = item.price * amount;
+
+        let bought = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            let fc = c.full_char.as_mut().unwrap();
+            if fc.chara.meseta < price {
+                warn!("Client {} can't afford {} of shop item {:?} for {} meseta", cid, amount, data.data, price);
+                false
+            } else if fc.inv.find(m.item_id).is_some() || !fc.inv.add(data.clone()) {
+                warn!("Client {} has no room for shop item {:?} as {:08X}", cid, data.data, m.item_id);
+                false
+            } else {
+                fc.chara.meseta -= price;
+                info!("Client {} bought {} of item {:?} for {} meseta, {} left", cid, amount, data.data, price, fc.chara.meseta);
+                true
+            }
+        };
+        if !bought {
+            return
+        }
+
+        // Keep our own IDs for the player clear of the one they picked.
+        if m.item_id >= self.player_drop_counter[slot as usize] {
+            self.player_drop_counter[slot as usize] = m.item_id + 1;
+        }
+        self.bb_broadcast(handler, Some(cid), BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60CreateItem { client_id: slot, unused: 0, data: create_item_msg(&data) })).unwrap();
+    }
+
+    /// Have the tekker look at an unidentified weapon. The player pays now,
+    /// but the weapon only changes once they accept.
+    pub fn handle_bb_identify_item(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62IdentifyItem) {
+        let slot = match self.client_id_for_player(cid) {
+            Some(s) => s,
+            None => return
+        };
+        let result = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            let fc = c.full_char.as_mut().unwrap();
+            let item = match fc.inv.find(m.item_id) {
+                Some(i) if tekker::is_unidentified(&i.data) => i.data.clone(),
+                _ => {
+                    warn!("Client {} asked to identify {:08X}, which isn't an unidentified weapon they have", cid, m.item_id);
+                    return
+                }
+            };
+            if fc.chara.meseta < TEKKER_COST {
+                None
+            } else {
+                fc.chara.meseta -= TEKKER_COST;
+                Some(tekker::identify(&mut thread_rng(), &handler.item_pmt, &item, fc.chara.section))
+            }
+        };
+        let result = match result {
+            Some(r) => r,
+            None => {
+                handler.send_error(cid, "\tEYou don't have enough\nmeseta for the tekker.");
+                return
+            }
+        };
+        debug!("Client {} had {:08X} identified as {:?}", cid, m.item_id, result.data);
+
+        let mut item = [0u8; 12];
+        item.copy_from_slice(&result.data[..12]);
+        let mut item2 = [0u8; 4];
+        item2.copy_from_slice(&result.data2[..4]);
+        self.identified[slot as usize] = Some(result);
+        handler.send_to_client(cid, BbMsg::BbSubCmd62(0, BbSubCmd62::Bb62IdentifyResult { client_id: slot, unused: 0, data: Bb62IdentifyResult {
+            item: item,
+            item_id: m.item_id,
+            item2: item2
+        }}));
+    }
+
+    /// Put the weapon the tekker identified into the player's inventory in
+    /// place of the unidentified one.
+    pub fn handle_bb_accept_identify(&mut self, handler: &mut BlockHandler, cid: usize, m: Bb62AcceptIdentify) {
+        let slot = match self.client_id_for_player(cid) {
+            Some(s) => s,
+            None => return
+        };
+        let result = match self.identified[slot as usize].take() {
+            Some(r) if r.item_id == m.item_id => r,
+            _ => {
+                warn!("Client {} accepted an identification of {:08X} they didn't ask for", cid, m.item_id);
+                return
+            }
+        };
+        let replaced = {
+            let cr = handler.get_client_state(cid).unwrap();
+            let mut c = cr.borrow_mut();
+            let fc = c.full_char.as_mut().unwrap();
+            match fc.inv.items.iter_mut().find(|i| i.data.item_id == m.item_id) {
+                Some(i) => {
+                    i.data = result.clone();
+                    true
+                },
+                None => false
+            }
+        };
+        if !replaced {
+            warn!("Client {} no longer has {:08X} to identify", cid, m.item_id);
+            return
+        }
+
+        self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60DeleteItem { client_id: slot, unused: 0, data: Bb60DeleteItem {
+            item_id: m.item_id,
+            amount: 1
+        }})).unwrap();
+        self.bb_broadcast(handler, None, BbMsg::BbSubCmd60(0, BbSubCmd60::Bb60CreateItem { client_id: slot, unused: 0, data: create_item_msg(&result) })).unwrap();
+    }
+
     fn award_exp(&self, client: usize, handler: &mut BlockHandler, exp: u32) {
         let mut leveled_up = false;
         let mut current_level;
@@ -732,8 +1346,6 @@ impl Party {
                     chara.chara.stats.dfp += lte.dfp as u16;
                     chara.chara.stats.ata += lte.ata as u16;
 
-                    // TODO add equipped mag bonuses
-
                     // Update their level.
                     current_level += 1;
 
@@ -745,6 +1357,13 @@ impl Party {
 
             chara.chara.exp += exp;
             chara.chara.level = current_level as u32;
+
+            // The client shows the stats with the equipped mag's added.
+            if leveled_up {
+                if let Some(m) = chara.inv.items.iter().find(|i| i.flags & ITEM_EQUIPPED != 0 && mag::is_mag(&i.data)) {
+                    mag::add_stat_bonus(&m.data, &mut stats);
+                }
+            }
         }
         let slot = self.client_id_for_player(client).unwrap();
 
@@ -811,16 +1430,17 @@ impl Party {
         None
     }
 
-    fn random_variants_ep1(maps: &Ep1Areas, event: u16, normal: bool) -> (Vec<u32>, Vec<InstanceEnemy>) {
+    fn random_variants_ep1(maps: &Ep1Areas, event: u16, normal: bool, rares: &mut RareRolls) -> (Vec<u32>, Vec<InstanceEnemy>, Vec<InstanceObject>) {
         let mut variants = vec![0; 0x20];
         let mut enemies = Vec::with_capacity(0xB50);
-        Party::append_enemies(&maps.city.enemies, &mut enemies, 1, event, false);
+        let mut objects = Vec::new();
+        Party::append_area(&maps.city, &mut enemies, &mut objects, 1, event, false, rares);
 
         // Forest
         variants[3] = (random::<usize>() % maps.forest1.len()) as u32;
         variants[5] = (random::<usize>() % maps.forest2.len()) as u32;
-        Party::append_enemies(&maps.forest1[variants[3] as usize].enemies, &mut enemies, 1, event, false);
-        Party::append_enemies(&maps.forest2[variants[5] as usize].enemies, &mut enemies, 1, event, false);
+        Party::append_area(&maps.forest1[variants[3] as usize], &mut enemies, &mut objects, 1, event, false, rares);
+        Party::append_area(&maps.forest2[variants[5] as usize], &mut enemies, &mut objects, 1, event, false, rares);
 
         {
             let keys: Vec<_> = maps.cave1.keys().collect();
@@ -828,54 +1448,54 @@ impl Party {
             let &(m, v) = keys[i];
             variants[6] = m;
             variants[7] = v;
-            Party::append_enemies(&maps.cave1.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.cave1.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
             let keys: Vec<_> = maps.cave2.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[8] = m;
             variants[9] = v;
-            Party::append_enemies(&maps.cave2.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.cave2.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
             let keys: Vec<_> = maps.cave3.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[10] = m;
             variants[11] = v;
-            Party::append_enemies(&maps.cave3.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.cave3.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
             let keys: Vec<_> = maps.machine1.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[12] = m;
             variants[13] = v;
-            Party::append_enemies(&maps.machine1.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.machine1.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
             let keys: Vec<_> = maps.machine2.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[14] = m;
             variants[15] = v;
-            Party::append_enemies(&maps.machine2.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.machine2.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
             let keys: Vec<_> = maps.ancient1.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[16] = m;
             variants[17] = v;
-            Party::append_enemies(&maps.ancient1.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.ancient1.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
             let keys: Vec<_> = maps.ancient2.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[18] = m;
             variants[19] = v;
-            Party::append_enemies(&maps.ancient2.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.ancient2.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
             let keys: Vec<_> = maps.ancient3.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[20] = m;
             variants[21] = v;
-            Party::append_enemies(&maps.ancient3.get(&(m, v)).unwrap().enemies, &mut enemies, 1, event, false);
+            Party::append_area(maps.ancient3.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 1, event, false, rares);
         }
-        Party::append_enemies(&maps.boss1.enemies, &mut enemies, 1, event, false);
-        Party::append_enemies(&maps.boss2.enemies, &mut enemies, 1, event, false);
-        Party::append_enemies(&maps.boss3.enemies, &mut enemies, 1, event, false);
-        Party::append_enemies(&maps.boss4.enemies, &mut enemies, 1, event, false);
+        Party::append_area(&maps.boss1, &mut enemies, &mut objects, 1, event, false, rares);
+        Party::append_area(&maps.boss2, &mut enemies, &mut objects, 1, event, false, rares);
+        Party::append_area(&maps.boss3, &mut enemies, &mut objects, 1, event, false, rares);
+        Party::append_area(&maps.boss4, &mut enemies, &mut objects, 1, event, false, rares);
 
         if !normal {
             // Dark Falz has a different param entry on Hard+ because of third phase
@@ -884,13 +1504,14 @@ impl Party {
             }
         }
 
-        (variants, enemies)
+        (variants, enemies, objects)
     }
 
-    fn random_variants_ep2(maps: &Ep2Areas, event: u16) -> (Vec<u32>, Vec<InstanceEnemy>) {
+    fn random_variants_ep2(maps: &Ep2Areas, event: u16, rares: &mut RareRolls) -> (Vec<u32>, Vec<InstanceEnemy>, Vec<InstanceObject>) {
         let mut variants = vec![0; 0x20];
         let mut enemies = Vec::new();
-        Party::append_enemies(&maps.city.enemies, &mut enemies, 2, event, false);
+        let mut objects = Vec::new();
+        Party::append_area(&maps.city, &mut enemies, &mut objects, 2, event, false, rares);
 
         {
             let keys: Vec<_> = maps.ruins1.keys().collect();
@@ -898,69 +1519,70 @@ impl Party {
             let &(m, v) = keys[i];
             variants[2] = m;
             variants[3] = v;
-            Party::append_enemies(&maps.ruins1.get(&(m, v)).unwrap().enemies, &mut enemies, 2, event, false);
+            Party::append_area(maps.ruins1.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 2, event, false, rares);
             let keys: Vec<_> = maps.ruins2.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[4] = m;
             variants[5] = v;
-            Party::append_enemies(&maps.ruins2.get(&(m, v)).unwrap().enemies, &mut enemies, 2, event, false);
+            Party::append_area(maps.ruins2.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 2, event, false, rares);
             let keys: Vec<_> = maps.space1.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[6] = m;
             variants[7] = v;
-            Party::append_enemies(&maps.space1.get(&(m, v)).unwrap().enemies, &mut enemies, 2, event, false);
+            Party::append_area(maps.space1.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 2, event, false, rares);
             let keys: Vec<_> = maps.space2.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[8] = m;
             variants[9] = v;
-            Party::append_enemies(&maps.space2.get(&(m, v)).unwrap().enemies, &mut enemies, 2, event, false);
+            Party::append_area(maps.space2.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 2, event, false, rares);
 
             variants[11] = (random::<usize>() % maps.jungle1.len()) as u32;
             variants[13] = (random::<usize>() % maps.jungle2.len()) as u32;
             variants[15] = (random::<usize>() % maps.jungle3.len()) as u32;
             variants[19] = (random::<usize>() % maps.jungle5.len()) as u32;
-            Party::append_enemies(&maps.jungle1[variants[11] as usize].enemies, &mut enemies, 2, event, false);
-            Party::append_enemies(&maps.jungle2[variants[13] as usize].enemies, &mut enemies, 2, event, false);
-            Party::append_enemies(&maps.jungle3[variants[15] as usize].enemies, &mut enemies, 2, event, false);
+            Party::append_area(&maps.jungle1[variants[11] as usize], &mut enemies, &mut objects, 2, event, false, rares);
+            Party::append_area(&maps.jungle2[variants[13] as usize], &mut enemies, &mut objects, 2, event, false, rares);
+            Party::append_area(&maps.jungle3[variants[15] as usize], &mut enemies, &mut objects, 2, event, false, rares);
 
             let keys: Vec<_> = maps.jungle4.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[16] = m;
             variants[17] = v;
-            Party::append_enemies(&maps.jungle4.get(&(m, v)).unwrap().enemies, &mut enemies, 2, event, false);
+            Party::append_area(maps.jungle4.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 2, event, false, rares);
 
-            Party::append_enemies(&maps.jungle5[variants[19] as usize].enemies, &mut enemies, 2, event, false);
+            Party::append_area(&maps.jungle5[variants[19] as usize], &mut enemies, &mut objects, 2, event, false, rares);
 
             let keys: Vec<_> = maps.seabed1.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[20] = m;
             variants[21] = v;
-            Party::append_enemies(&maps.seabed1.get(&(m, v)).unwrap().enemies, &mut enemies, 2, event, false);
+            Party::append_area(maps.seabed1.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 2, event, false, rares);
             let keys: Vec<_> = maps.seabed2.keys().collect();
             let i = random::<usize>() % keys.len();
             let &(m, v) = keys[i];
             variants[22] = m;
             variants[23] = v;
-            Party::append_enemies(&maps.seabed2.get(&(m, v)).unwrap().enemies, &mut enemies, 2, event, false);
+            Party::append_area(maps.seabed2.get(&(m, v)).unwrap(), &mut enemies, &mut objects, 2, event, false, rares);
         }
         // bosses
-        Party::append_enemies(&maps.boss5.enemies, &mut enemies, 2, event, false);
-        Party::append_enemies(&maps.boss6.enemies, &mut enemies, 2, event, false);
-        Party::append_enemies(&maps.boss7.enemies, &mut enemies, 2, event, false);
-        Party::append_enemies(&maps.boss8.enemies, &mut enemies, 2, event, false);
+        Party::append_area(&maps.boss5, &mut enemies, &mut objects, 2, event, false, rares);
+        Party::append_area(&maps.boss6, &mut enemies, &mut objects, 2, event, false, rares);
+        Party::append_area(&maps.boss7, &mut enemies, &mut objects, 2, event, false, rares);
+        Party::append_area(&maps.boss8, &mut enemies, &mut objects, 2, event, false, rares);
 
-        (variants, enemies)
+        (variants, enemies, objects)
     }
 
-    fn random_variants_ep4(maps: &Ep4Areas, event: u16) -> (Vec<u32>, Vec<InstanceEnemy>) {
+    fn random_variants_ep4(maps: &Ep4Areas, event: u16, rares: &mut RareRolls) -> (Vec<u32>, Vec<InstanceEnemy>, Vec<InstanceObject>) {
         let mut variants = vec![0; 0x20];
         let mut enemies = Vec::new();
-        Party::append_enemies(&maps.city.enemies, &mut enemies, 3, event, false);
+        let mut objects = Vec::new();
+        Party::append_area(&maps.city, &mut enemies, &mut objects, 3, event, false, rares);
 
         variants[3] = (random::<usize>() % maps.wilds1.len()) as u32;
         variants[5] = (random::<usize>() % maps.wilds2.len()) as u32;
@@ -971,23 +1593,29 @@ impl Party {
         variants[15] = (random::<usize>() % maps.desert2.len()) as u32;
         variants[16] = (random::<usize>() % maps.desert3.len()) as u32;
 
-        Party::append_enemies(&maps.wilds1[variants[3] as usize].enemies, &mut enemies, 3, event, false);
-        Party::append_enemies(&maps.wilds2[variants[5] as usize].enemies, &mut enemies, 3, event, false);
-        Party::append_enemies(&maps.wilds3[variants[7] as usize].enemies, &mut enemies, 3, event, false);
-        Party::append_enemies(&maps.wilds4[variants[9] as usize].enemies, &mut enemies, 3, event, false);
-        Party::append_enemies(&maps.crater[variants[11] as usize].enemies, &mut enemies, 3, event, false);
-        Party::append_enemies(&maps.desert1[variants[12] as usize].enemies, &mut enemies, 3, event, true);
-        Party::append_enemies(&maps.desert2[variants[15] as usize].enemies, &mut enemies, 3, event, true);
-        Party::append_enemies(&maps.desert3[variants[16] as usize].enemies, &mut enemies, 3, event, true);
+        Party::append_area(&maps.wilds1[variants[3] as usize], &mut enemies, &mut objects, 3, event, false, rares);
+        Party::append_area(&maps.wilds2[variants[5] as usize], &mut enemies, &mut objects, 3, event, false, rares);
+        Party::append_area(&maps.wilds3[variants[7] as usize], &mut enemies, &mut objects, 3, event, false, rares);
+        Party::append_area(&maps.wilds4[variants[9] as usize], &mut enemies, &mut objects, 3, event, false, rares);
+        Party::append_area(&maps.crater[variants[11] as usize], &mut enemies, &mut objects, 3, event, false, rares);
+        Party::append_area(&maps.desert1[variants[12] as usize], &mut enemies, &mut objects, 3, event, true, rares);
+        Party::append_area(&maps.desert2[variants[15] as usize], &mut enemies, &mut objects, 3, event, true, rares);
+        Party::append_area(&maps.desert3[variants[16] as usize], &mut enemies, &mut objects, 3, event, true, rares);
 
-        Party::append_enemies(&maps.boss9.enemies, &mut enemies, 3, event, false);
+        Party::append_area(&maps.boss9, &mut enemies, &mut objects, 3, event, false, rares);
 
-        (variants, enemies)
+        (variants, enemies, objects)
     }
 
-    fn append_enemies(map_enemies: &[MapEnemy], instance_enemies: &mut Vec<InstanceEnemy>, episode: u8, event: u16, alt_enemies: bool) {
-        for m in map_enemies.iter() {
-            instance_enemies.append(&mut convert_enemy(m, episode, event, alt_enemies));
+    fn append_area(area: &VariationData, instance_enemies: &mut Vec<InstanceEnemy>, instance_objects: &mut Vec<InstanceObject>, episode: u8, event: u16, alt_enemies: bool, rares: &mut RareRolls) {
+        for m in area.enemies.iter() {
+            let index = instance_enemies.len();
+            instance_enemies.append(&mut convert_enemy(m, index, episode, event, alt_enemies, rares));
+        }
+        for o in area.objects.iter() {
+            instance_objects.push(InstanceObject {
+                data: o.clone()
+            });
         }
     }
 }
@@ -0,0 +1,288 @@
+//! Shop stock. A shop's stock is rolled each time a player opens it, for their
+//! level and the party's difficulty, and kept until they open another so that
+//! a purchase can refer to an item by its place in the list.
+
+use std::cmp::{min, max};
+
+use rand::Rng;
+
+use psodata::chara::ItemData;
+use psodata::itempmt::ItemPMT;
+
+use ::droptables::gen::{SPECIALS, MAX_FRAME, MAX_SHIELD, MAX_UNIT, special_rank};
+
+pub const SHOP_TOOL: u8 = 0;
+pub const SHOP_WEAPON: u8 = 1;
+pub const SHOP_ARMOR: u8 = 2;
+
+/// Most items a shop can list.
+pub const MAX_SHOP_ITEMS: usize = 24;
+
+/// Items with this many stars or more are rares, which shops never sell.
+const RARE_STARS: u8 = 9;
+
+/// The level each difficulty opens at. Stock is never worse than a character
+/// of that level would see.
+static DIFFICULTY_LEVELS: [u32; 4] = [1, 20, 40, 80];
+
+/// Tools always in stock once the level is reached.
+static TOOLS: [(u8, u8, u32); 12] = [
+    (0x00, 0x00, 1), // Monomate
+    (0x01, 0x00, 1), // Monofluid
+    (0x06, 0x00, 1), // Antidote
+    (0x06, 0x01, 1), // Antiparalysis
+    (0x07, 0x00, 1), // Telepipe
+    (0x08, 0x00, 1), // Trap Vision
+    (0x03, 0x00, 5), // Sol Atomizer
+    (0x04, 0x00, 10), // Moon Atomizer
+    (0x00, 0x01, 11), // Dimate
+    (0x01, 0x01, 11), // Difluid
+    (0x00, 0x02, 26), // Trimate
+    (0x01, 0x02, 26) // Trifluid
+];
+
+/// Techniques sold on disks, and the highest level of each. Grants and Megid
+/// are only found.
+static TECHS: [(u8, u8); 17] = [
+    (0, 15), (1, 15), (2, 15), // Foie, Gifoie, Rafoie
+    (3, 15), (4, 15), (5, 15), // Barta, Gibarta, Rabarta
+    (6, 15), (7, 15), (8, 15), // Zonde, Gizonde, Razonde
+    (10, 15), (11, 15), (12, 15), (13, 15), // Deband, Jellen, Zalure, Shifta
+    (14, 1), (15, 15), (16, 7), (17, 1) // Ryuker, Resta, Anti, Reverser
+];
+
+#[derive(Clone, Debug)]
+pub struct ShopItem {
+    pub data: ItemData,
+    pub price: u32
+}
+
+#[derive(Clone, Debug)]
+pub struct Shop {
+    pub shop_type: u8,
+    pub items: Vec<ShopItem>
+}
+
+impl Shop {
+    /// Roll the stock of a shop for a character of `level` (counting from 1).
+    /// Unknown shop types have nothing for sale.
+    pub fn generate<R: Rng>(rng: &mut R, pmt: &ItemPMT, shop_type: u8, level: u32, difficulty: u8) -> Shop {
+        let level = max(level, DIFFICULTY_LEVELS[min(difficulty as usize, 3)]);
+        let mut items = match shop_type {
+            SHOP_TOOL => tools(rng, level),
+            SHOP_WEAPON => weapons(rng, pmt, level),
+            SHOP_ARMOR => armors(rng, pmt, level),
+            _ => Vec::new()
+        };
+        items.truncate(MAX_SHOP_ITEMS);
+        let items = items.into_iter().filter_map(|data| {
+            let price = price(pmt, &data);
+            if price == 0 {
+                warn!("No price for shop item {:?}", data.data);
+                None
+            } else {
+                Some(ShopItem {
+                    data: data,
+                    price: price
+                })
+            }
+        }).collect();
+        Shop {
+            shop_type: shop_type,
+            items: items
+        }
+    }
+}
+
+fn tools<R: Rng>(rng: &mut R, level: u32) -> Vec<ItemData> {
+    let mut items = Vec::new();
+    for &(kind, index, min_level) in TOOLS.iter() {
+        if level < min_level {
+            continue
+        }
+        let mut item = ItemData::default();
+        item.data[0] = 0x03;
+        item.data[1] = kind;
+        item.data[2] = index;
+        item.set_stack_count(1);
+        items.push(item);
+    }
+
+    // Disks fill out the rest, a few levels up to the character's best.
+    let best = min(level / 8 + 1, 15) as u8;
+    let disks = rng.gen_range(4, 9);
+    for _ in 0..disks {
+        let (tech, highest) = TECHS[rng.gen_range(0, TECHS.len())];
+        let top = min(best, highest);
+        let tech_level = rng.gen_range(max(top as i32 - 4, 1) as u8, top + 1);
+        if items.iter().any(|i| i.data[1] == 0x02 && i.data[4] == tech && i.data[2] == tech_level - 1) {
+            continue
+        }
+        let mut item = ItemData::default();
+        item.data[0] = 0x03;
+        item.data[1] = 0x02;
+        item.data[2] = tech_level - 1;
+        item.data[4] = tech;
+        items.push(item);
+    }
+    items
+}
+
+fn weapons<R: Rng>(rng: &mut R, pmt: &ItemPMT, level: u32) -> Vec<ItemData> {
+    let best = min(level / 25, 4) as u8;
+    let count = rng.gen_range(8, 13);
+    let mut items = Vec::new();
+    for _ in 0..count {
+        let mut item = ItemData::default();
+        item.data[0] = 0x00;
+        // Saber through Wand
+        item.data[1] = rng.gen_range(1, 13);
+        item.data[2] = rng.gen_range(best.saturating_sub(1), best + 1);
+        let max_grind = match pmt.weapon(item.data[1], item.data[2]) {
+            Some(w) if pmt.stars(w.base.id) < RARE_STARS => w.max_grind,
+            _ => continue
+        };
+        item.data[3] = rng.gen_range(0, min(max_grind as u32, level / 10) + 1) as u8;
+
+        if rng.gen_range(0, 4) == 0 {
+            let specials = SPECIALS[rng.gen_range(0, min(1 + level as usize / 50, 2))];
+            item.data[4] = specials[rng.gen_range(0, specials.len())];
+        }
+
+        // Up to two attributes other than hit, none of them twice.
+        let mut slot = 0;
+        for _ in 0..2 {
+            if rng.gen_range(0, 3) != 0 {
+                continue
+            }
+            let attr = rng.gen_range(1, 5);
+            if (0..slot).any(|s| item.data[6 + s * 2] == attr) {
+                continue
+            }
+            let highest = min(10 + level / 5, 30) / 5;
+            item.data[6 + slot * 2] = attr;
+            item.data[7 + slot * 2] = rng.gen_range(1, highest + 1) as u8 * 5;
+            slot += 1;
+        }
+
+        push_new(&mut items, item);
+    }
+    items
+}
+
+/// Add an item unless the same one is already listed.
+fn push_new(items: &mut Vec<ItemData>, item: ItemData) {
+    if !items.iter().any(|i| i.data == item.data) {
+        items.push(item);
+    }
+}
+
+/// One of the best few common armors the character can wear.
+fn pick_armor<R: Rng>(rng: &mut R, pmt: &ItemPMT, kind: u8, level: u32) -> Option<u8> {
+    let (table, highest) = match kind {
+        1 => (&pmt.frames, MAX_FRAME),
+        _ => (&pmt.barriers, MAX_SHIELD)
+    };
+    let wearable: Vec<usize> = table.iter().enumerate()
+        .take(highest as usize + 1)
+        .filter(|&(_, a)| a.required_level as u32 + 1 <= level && pmt.stars(a.base.id) < RARE_STARS)
+        .map(|(i, _)| i)
+        .collect();
+    if wearable.is_empty() {
+        return None
+    }
+    let from = wearable.len().saturating_sub(6);
+    Some(wearable[rng.gen_range(from, wearable.len())] as u8)
+}
+
+fn armors<R: Rng>(rng: &mut R, pmt: &ItemPMT, level: u32) -> Vec<ItemData> {
+    let mut items = Vec::new();
+    for _ in 0..rng.gen_range(4, 7) {
+        if let Some(index) = pick_armor(rng, pmt, 1, level) {
+            let mut item = ItemData::default();
+            item.data[0] = 0x01;
+            item.data[1] = 0x01;
+            item.data[2] = index;
+            item.data[5] = rng.gen_range(0, min(level / 20, 3) as u8 + 2);
+            push_new(&mut items, item);
+        }
+    }
+    for _ in 0..rng.gen_range(4, 7) {
+        if let Some(index) = pick_armor(rng, pmt, 2, level) {
+            let mut item = ItemData::default();
+            item.data[0] = 0x01;
+            item.data[1] = 0x02;
+            item.data[2] = index;
+            push_new(&mut items, item);
+        }
+    }
+    let best_stars = min(2 + level / 40, RARE_STARS as u32 - 1) as u8;
+    let units: Vec<usize> = pmt.units.iter().enumerate()
+        .take(MAX_UNIT as usize + 1)
+        .filter(|&(_, u)| pmt.stars(u.base.id) <= best_stars)
+        .map(|(i, _)| i)
+        .collect();
+    if !units.is_empty() {
+        for _ in 0..rng.gen_range(2, 5) {
+            let mut item = ItemData::default();
+            item.data[0] = 0x01;
+            item.data[1] = 0x03;
+            item.data[2] = units[rng.gen_range(0, units.len())] as u8;
+            push_new(&mut items, item);
+        }
+    }
+    items
+}
+
+/// What a shop charges for one of an item, or 0 if the item has no price.
+pub fn price(pmt: &ItemPMT, item: &ItemData) -> u32 {
+    let d = &item.data;
+    match (d[0], d[1]) {
+        (0x00, kind) => {
+            let w = match pmt.weapon(kind, d[2]) {
+                Some(w) => w,
+                None => return 0
+            };
+            let divisor = pmt.weapon_divisors.get(kind as usize).cloned().unwrap_or(0.0) as f64;
+            if divisor <= 0.0 {
+                return 0
+            }
+            let atp = (w.atp_max as u32 + d[3] as u32) as f64;
+            let attrs: i32 = (0..3).filter(|&s| d[6 + s * 2] != 0).map(|s| d[7 + s * 2] as i8 as i32).sum();
+            let stars = special_rank(d[4]);
+            (atp * atp / divisor * (attrs + 100) as f64 / 100.0) as u32 + 1000 * stars * stars
+        },
+        (0x01, kind @ 0x01) | (0x01, kind @ 0x02) => {
+            let a = match pmt.armor(kind, d[2]) {
+                Some(a) => a,
+                None => return 0
+            };
+            let divisor = if kind == 1 { pmt.sale_divisors.armor } else { pmt.sale_divisors.shield } as f64;
+            if divisor <= 0.0 {
+                return 0
+            }
+            let stats = (a.dfp as u32 + a.evp as u32) as f64;
+            let slots = if kind == 1 { d[5] as u32 } else { 0 };
+            (stats * stats / divisor) as u32 + 70 * (slots + 1) * (a.required_level as u32 + 1)
+        },
+        (0x01, 0x03) => {
+            match pmt.unit(d[2]) {
+                Some(u) => (pmt.stars(u.base.id) as f64 * pmt.sale_divisors.unit as f64) as u32,
+                None => 0
+            }
+        },
+        (0x03, 0x02) => {
+            match pmt.tool(0x02, d[4]) {
+                Some(t) => max(t.cost, 0) as u32 * (d[2] as u32 + 1),
+                None => 0
+            }
+        },
+        (0x03, kind) => {
+            match pmt.tool(kind, d[2]) {
+                Some(t) => max(t.cost, 0) as u32,
+                None => 0
+            }
+        },
+        _ => 0
+    }
+}
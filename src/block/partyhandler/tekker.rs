@@ -0,0 +1,135 @@
+//! The tekker, who identifies weapons that drop unidentified. What a weapon
+//! really is may be a little better or worse than how it dropped: its grind
+//! can move by up to three, and its special by a rank either way. Weapons of
+//! the type a section ID is lucky with tend to come out better.
+
+use std::cmp::{min, max};
+
+use rand::Rng;
+
+use psodata::chara::{ItemData, WEAPON_UNIDENTIFIED};
+use psodata::itempmt::ItemPMT;
+
+use ::droptables::gen::{weighted, special_rank};
+
+/// Meseta charged to identify a weapon.
+pub const TEKKER_COST: u32 = 100;
+
+/// The weapon type each section ID is lucky with, Viridia through Whitill.
+static LUCKY_WEAPONS: [u8; 10] = [
+    0x09, // Shot
+    0x07, // Rifle
+    0x02, // Sword
+    0x04, // Partisan
+    0x08, // Mechgun
+    0x0A, // Cane
+    0x0B, // Rod
+    0x03, // Dagger
+    0x06, // Handgun
+    0x05 // Slicer
+];
+
+/// Weights of grind adjustments from -3 to +3, for unlucky and lucky weapons.
+static GRIND_WEIGHTS: [[i32; 7]; 2] = [
+    [5, 10, 20, 30, 20, 10, 5],
+    [2, 5, 15, 30, 23, 15, 10]
+];
+
+/// Weights of a special dropping a rank, staying, and rising a rank.
+static SPECIAL_WEIGHTS: [[i32; 3]; 2] = [
+    [10, 80, 10],
+    [5, 80, 15]
+];
+
+pub fn is_unidentified(item: &ItemData) -> bool {
+    item.data[0] == 0x00 && item.data[4] & WEAPON_UNIDENTIFIED != 0
+}
+
+/// The weapon an unidentified one turns out to be, for a character of the
+/// section ID.
+pub fn identify<R: Rng>(rng: &mut R, pmt: &ItemPMT, item: &ItemData, section: u8) -> ItemData {
+    let mut ret = item.clone();
+    ret.data[4] &= !WEAPON_UNIDENTIFIED;
+    let lucky = LUCKY_WEAPONS.get(section as usize) == Some(&item.data[1]);
+    let luck = if lucky { 1 } else { 0 };
+
+    let max_grind = pmt.weapon(item.data[1], item.data[2]).map(|w| w.max_grind as i32).unwrap_or(0);
+    let adjust = weighted(rng, GRIND_WEIGHTS[luck].iter().cloned()).unwrap_or(3) as i32 - 3;
+    ret.data[3] = min(max(item.data[3] as i32 + adjust, 0), max(max_grind, item.data[3] as i32)) as u8;
+
+    // Specials of one kind are numbered in order of rank, so moving a rank
+    // is moving by one, as long as the kind goes that far.
+    let special = ret.data[4];
+    let rank = special_rank(special);
+    if rank > 0 {
+        let (moved, moved_rank) = match weighted(rng, SPECIAL_WEIGHTS[luck].iter().cloned()) {
+            Some(0) => (special - 1, rank - 1),
+            Some(2) => (special + 1, rank + 1),
+            _ => (special, rank)
+        };
+        if special_rank(moved) == moved_rank {
+            ret.data[4] = moved;
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use psodata::chara::ItemData;
+    use psodata::itempmt::{ItemPMT, Weapon};
+
+    use super::*;
+
+    fn pmt() -> ItemPMT {
+        let mut pmt = ItemPMT::default();
+        let mut saber = Weapon::default();
+        saber.max_grind = 35;
+        pmt.weapons = vec![Vec::new(), vec![saber]];
+        pmt
+    }
+
+    fn saber(grind: u8, special: u8) -> ItemData {
+        let mut item = ItemData::default();
+        item.data[1] = 0x01;
+        item.data[3] = grind;
+        item.data[4] = special | WEAPON_UNIDENTIFIED;
+        item
+    }
+
+    #[test]
+    fn test_identify_is_deterministic() {
+        let pmt = pmt();
+        let item = saber(10, 0x06);
+        let a = identify(&mut XorShiftRng::from_seed([1, 2, 3, 4]), &pmt, &item, 0);
+        let b = identify(&mut XorShiftRng::from_seed([1, 2, 3, 4]), &pmt, &item, 0);
+        assert_eq!(a.data, b.data);
+        assert!(!is_unidentified(&a));
+    }
+
+    #[test]
+    fn test_identify_stays_in_bounds() {
+        let pmt = pmt();
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let mut seen_up = false;
+        let mut seen_down = false;
+        for _ in 0..1000 {
+            // Heart's kind goes from Heart to Geist.
+            let item = identify(&mut rng, &pmt, &saber(0, 0x05), 3);
+            assert!(item.data[3] <= 3);
+            assert!(item.data[4] == 0x05 || item.data[4] == 0x06);
+            seen_up |= item.data[4] == 0x06;
+
+            // Charge has no other ranks.
+            let item = identify(&mut rng, &pmt, &saber(35, 0x0C), 3);
+            assert!(item.data[3] >= 32 && item.data[3] <= 35);
+            assert_eq!(item.data[4], 0x0C);
+
+            let item = identify(&mut rng, &pmt, &saber(10, 0x07), 3);
+            seen_down |= item.data[4] == 0x06;
+        }
+        assert!(seen_up && seen_down);
+    }
+}
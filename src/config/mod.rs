@@ -1,4 +1,5 @@
-use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+use std::str::FromStr;
 
 use toml::{Parser, Table};
 
@@ -7,6 +8,12 @@ use psodb_common::Result as DbResult;
 use psodb_sqlite::Sqlite;
 
 use ::game::Version;
+use ::patch::balance::BalanceMode;
+use ::shipgate::session::DuplicateLogin;
+use ::login::bb::checksum::{ChecksumFilter, ChecksumPolicy};
+use ::login::bb::names::{NameRules, MAX_NAME_LEN};
+use ::block::partyhandler::enemygen::RareRates;
+use ::rates::RatesConf;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -22,16 +29,28 @@ pub enum ServiceConf {
     Patch {
         bind: SocketAddr,
         motd: String,
-        v4_servers: Vec<SocketAddrV4>,
-        random_balance: bool
+        /// Data servers and their weights.
+        v4_servers: Vec<(SocketAddrV4, u32)>,
+        v6_servers: Vec<(SocketAddrV6, u32)>,
+        balance: BalanceMode,
+        /// Seconds between data server health checks. 0 disables.
+        probe_interval: u64,
+        /// Failed health checks in a row before a data server is dropped.
+        probe_failures: u32
     },
     Data {
-        bind: SocketAddr
+        bind: SocketAddr,
+        /// Directory to serve patch files from. Defaults to `{data_path}/patch`.
+        path: Option<String>,
+        /// Seconds between checks of the patch directory for changes. 0 disables.
+        rescan_interval: u64
     },
     Login {
         bind: SocketAddr,
         version: Version,
         addr: SocketAddrV4,
+        checksums: ChecksumFilter,
+        names: NameRules
     },
     Ship {
         bind: SocketAddr,
@@ -42,12 +61,17 @@ pub enum ServiceConf {
     Block {
         bind: SocketAddr,
         num: u16,
-        event: u16
+        event: u16,
+        rare_rates: RareRates,
+        rates: Option<RatesConf>
     },
     ShipGate {
         bind: SocketAddr,
         password: String,
-        db: DbConf
+        db: DbConf,
+        duplicate_login: DuplicateLogin,
+        recover_days: u32,
+        rates: Option<RatesConf>
     }
     // ...
 }
@@ -65,6 +89,139 @@ pub struct BlockConf {
     pub addr: SocketAddrV4
 }
 
+/// Parse a login service's client checksum policy. Checksums may be given as
+/// integers or as hex strings.
+fn parse_checksums(t: &Table) -> Result<ChecksumFilter, String> {
+    let mut filter = ChecksumFilter::default();
+    match t.get("checksum_policy").and_then(|v| v.as_str()).map(|v| v.parse()) {
+        Some(Ok(p)) => filter.policy = p,
+        Some(Err(e)) => return Err(e),
+        None => ()
+    }
+    if let Some(m) = t.get("checksum_message").and_then(|v| v.as_str()) {
+        filter.message = m.to_string();
+    }
+    if let Some(v) = t.get("checksums") {
+        let vs = match v.as_slice() {
+            Some(vs) => vs,
+            None => return Err("login service checksums field is not an array".to_string())
+        };
+        for v in vs.iter() {
+            let cs = if let Some(i) = v.as_integer() {
+                i as u32
+            } else if let Some(s) = v.as_str() {
+                let hex = if s.starts_with("0x") { &s[2..] } else { s };
+                match u32::from_str_radix(hex, 16) {
+                    Ok(cs) => cs,
+                    Err(e) => return Err(format!("Invalid checksum {}: {}", s, e))
+                }
+            } else {
+                return Err("login service checksums must be integers or hex strings".to_string())
+            };
+            filter.allowed.insert(cs);
+        }
+    }
+    if filter.policy == ChecksumPolicy::Reject && filter.allowed.is_empty() {
+        return Err("login service checksum_policy is reject, but no checksums are allowed".to_string())
+    }
+    Ok(filter)
+}
+
+/// Parse a login service's character name rules.
+fn parse_name_rules(t: &Table) -> Result<NameRules, String> {
+    let mut rules = NameRules::default();
+    if let Some(v) = t.get("name_min_len").and_then(|v| v.as_integer()) {
+        rules.min_len = v as usize;
+    }
+    if let Some(v) = t.get("name_max_len").and_then(|v| v.as_integer()) {
+        if v as usize > MAX_NAME_LEN {
+            return Err(format!("login service name_max_len can't be more than {}", MAX_NAME_LEN))
+        }
+        rules.max_len = v as usize;
+    }
+    if let Some(v) = t.get("reserved_names") {
+        let vs = match v.as_slice() {
+            Some(vs) => vs,
+            None => return Err("login service reserved_names field is not an array".to_string())
+        };
+        for v in vs.iter() {
+            match v.as_str() {
+                Some(w) => rules.reserved.push(w.to_lowercase()),
+                None => return Err("login service reserved_names must be strings".to_string())
+            }
+        }
+    }
+    if let Some(p) = t.get("reserved_names_file").and_then(|v| v.as_str()) {
+        if let Err(e) = rules.load_reserved(p) {
+            return Err(format!("Couldn't read reserved names from {}: {}", p, e))
+        }
+    }
+    rules.unique = t.get("unique_names").and_then(|v| v.as_bool()).unwrap_or(false);
+    Ok(rules)
+}
+
+/// Parse a patch service's data server list. Each entry is either an address
+/// string, or a table with `addr` and an optional `weight` (default 1).
+fn parse_data_servers<A: FromStr>(t: &Table, key: &str, form: &str) -> Result<Vec<(A, u32)>, String> {
+    let mut servers = Vec::new();
+    let values = match t.get(key) {
+        Some(v) => match v.as_slice() {
+            Some(vs) => vs,
+            None => return Err(format!("patch service {} field is not an array", key))
+        },
+        None => return Ok(servers)
+    };
+    for v in values {
+        let (addr, weight) = match v.as_table() {
+            Some(st) => (st.get("addr").and_then(|v| v.as_str()), st.get("weight")),
+            None => (v.as_str(), None)
+        };
+        let weight = match weight {
+            Some(w) => match w.as_integer() {
+                Some(i) if i >= 1 && i <= ::std::u32::MAX as i64 => i as u32,
+                _ => return Err(format!("patch service data server weight in {} is not a positive integer", key))
+            },
+            None => 1
+        };
+        match addr.and_then(|s| s.parse().ok()) {
+            Some(sockaddr) => servers.push((sockaddr, weight)),
+            None => return Err(format!("patch service's data address in {} is not a valid {} string", key, form))
+        }
+    }
+    Ok(servers)
+}
+
+/// Parse a block service's rare enemy rates, given as chances from 0 to 1 in
+/// a `rare_rates` table. Enemies left out keep their usual rate.
+fn parse_rare_rates(t: &Table) -> Result<RareRates, String> {
+    let mut rates = RareRates::default();
+    let rt = match t.get("rare_rates") {
+        Some(v) => match v.as_table() {
+            Some(rt) => rt,
+            None => return Err("block service rare_rates field is not a table".to_string())
+        },
+        None => return Ok(rates)
+    };
+    for (k, v) in rt.iter() {
+        let rate = match v.as_float() {
+            Some(r) if r >= 0.0 && r <= 1.0 => r,
+            _ => return Err(format!("block service rare rate {} must be a number from 0 to 1", k))
+        };
+        match &k[..] {
+            "hildeblue" => rates.hildeblue = rate,
+            "rappy" => rates.rappy = rate,
+            "nar_lily" => rates.nar_lily = rate,
+            "pouilly_slime" => rates.pouilly_slime = rate,
+            "merissa_aa" => rates.merissa_aa = rate,
+            "pazuzu" => rates.pazuzu = rate,
+            "dorphon_eclair" => rates.dorphon_eclair = rate,
+            "kondrieu" => rates.kondrieu = rate,
+            _ => return Err(format!("block service has a rate for unknown rare enemy {}", k))
+        }
+    }
+    Ok(rates)
+}
+
 impl DbConf {
     pub fn make_pool(&self) -> DbResult<Pool> {
         match self {
@@ -144,32 +301,48 @@ impl ServiceConf {
                 match ty {
                     "patch" => {
                         let motd = t.get("motd").and_then(|v| v.as_str()).map(|s| s.to_string()).unwrap_or_default();
-                        let random_balance = t.get("random_balance").and_then(|v| v.as_bool()).unwrap_or_default();
-                        let mut v4_servers = Vec::new();
-                        if let Some(v4_values) = t.get("v4_servers").and_then(|v| v.as_slice()) {
-                            for v in v4_values {
-                                if let Some(sockaddr) = v.as_str().and_then(|s| s.parse().ok()) {
-                                    v4_servers.push(sockaddr);
+                        let balance = match t.get("balance").and_then(|v| v.as_str()).map(|v| v.parse()) {
+                            Some(Ok(b)) => b,
+                            Some(Err(e)) => return Err(e),
+                            None => {
+                                // random_balance predates the balance option
+                                if t.get("random_balance").and_then(|v| v.as_bool()).unwrap_or_default() {
+                                    BalanceMode::Random
                                 } else {
-                                    return Err("patch service's data address is not a valid IPv4 address:port string".to_string())
+                                    BalanceMode::RoundRobin
                                 }
                             }
-                        } else {
-                            return Err("patch service v4_servers field is not an array".to_string())
-                        }
-                        if v4_servers.len() == 0 {
-                            return Err("patch service has no IPv4 data nodes declared".to_string())
+                        };
+                        let probe_interval = t.get("probe_interval").and_then(|v| v.as_integer()).map(|v| v as u64).unwrap_or(0);
+                        let probe_failures = match t.get("probe_failures") {
+                            Some(v) => match v.as_integer() {
+                                Some(i) if i >= 1 && i <= ::std::u32::MAX as i64 => i as u32,
+                                _ => return Err("patch service probe_failures is not a positive integer".to_string())
+                            },
+                            None => 2
+                        };
+                        let v4_servers = try!(parse_data_servers(t, "v4_servers", "IPv4 address:port"));
+                        let v6_servers = try!(parse_data_servers(t, "v6_servers", "[IPv6 address]:port"));
+                        if v4_servers.len() == 0 && v6_servers.len() == 0 {
+                            return Err("patch service has no data nodes declared".to_string())
                         }
                         Ok(ServiceConf::Patch {
                             bind: bind,
                             motd: motd,
                             v4_servers: v4_servers,
-                            random_balance: random_balance
+                            v6_servers: v6_servers,
+                            balance: balance,
+                            probe_interval: probe_interval,
+                            probe_failures: probe_failures
                         })
                     },
                     "data" => {
+                        let path = t.get("path").and_then(|v| v.as_str()).map(|s| s.to_string());
+                        let rescan_interval = t.get("rescan_interval").and_then(|v| v.as_integer()).map(|v| v as u64).unwrap_or(30);
                         Ok(ServiceConf::Data {
-                            bind: bind
+                            bind: bind,
+                            path: path,
+                            rescan_interval: rescan_interval
                         })
                     },
                     "login" => {
@@ -189,10 +362,14 @@ impl ServiceConf {
                             Some(Err(e)) => return Err(format!("{:?}", e)),
                             None => return Err("No redirect address specified for login service (It needs to be accessible by clients, but it can be the same as the bind)".to_string())
                         };
+                        let checksums = try!(parse_checksums(t));
+                        let names = try!(parse_name_rules(t));
                         Ok(ServiceConf::Login {
                             bind: bind,
                             version: version,
-                            addr: addr
+                            addr: addr,
+                            checksums: checksums,
+                            names: names
                         })
                     },
                     "ship" => {
@@ -237,10 +414,14 @@ impl ServiceConf {
                     "block" => {
                         let num = t.get("num").and_then(|v| v.as_integer()).map(|v| v as u16).unwrap_or(1);
                         let event = t.get("event").and_then(|v| v.as_integer()).map(|v| v as u16).unwrap_or(0);
+                        let rare_rates = try!(parse_rare_rates(t));
+                        let rates = try!(RatesConf::from_toml_table(t));
                         Ok(ServiceConf::Block {
                             bind: bind,
                             num: num,
-                            event: event
+                            event: event,
+                            rare_rates: rare_rates,
+                            rates: rates
                         })
                     },
                     "shipgate" => {
@@ -262,10 +443,19 @@ impl ServiceConf {
                         } else {
                             return Err("No db configured for shipgate".to_string())
                         }
+                        let duplicate_login = match t.get("duplicate_login").and_then(|v| v.as_str()).map(|v| v.parse()) {
+                            Some(Ok(d)) => d,
+                            Some(Err(e)) => return Err(e),
+                            None => DuplicateLogin::Refuse
+                        };
+                        let recover_days = t.get("recover_days").and_then(|v| v.as_integer()).map(|v| v as u32).unwrap_or(0);
                         Ok(ServiceConf::ShipGate {
                             bind: bind,
                             password: password,
-                            db: db
+                            db: db,
+                            duplicate_login: duplicate_login,
+                            recover_days: recover_days,
+                            rates: try!(RatesConf::from_toml_table(t))
                         })
                     }
                     _ => return Err("invalid service type specified".to_string())
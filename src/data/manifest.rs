@@ -0,0 +1,319 @@
+//! The patch manifest: CRC32 and size tables for every file in the patch
+//! directory, computed once and shared by every client of a data service.
+//!
+//! A manifest is immutable once built, and keeps the contents of the files it
+//! hashed. Rescans build a whole new manifest and swap it in, so a client that
+//! took a snapshot keeps being sent the files it was told about even if they
+//! change underneath it.
+//!
+//! Besides noticing changes on its own, a manifest rescans when the server
+//! gets a SIGHUP.
+
+use std::fs::{self, File};
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crc::crc32::checksum_ieee as crc32;
+
+/// A file available for patching.
+#[derive(Clone, Debug)]
+pub struct ManifestEntry {
+    pub patch_id: u32,
+    pub name: String,
+    pub path: PathBuf,
+    pub checksum: u32,
+    pub size: u32,
+    /// The contents that were hashed.
+    pub data: Arc<Vec<u8>>
+}
+
+/// The files in one directory of the patch tree.
+#[derive(Clone, Debug)]
+pub struct DirTable {
+    /// The directories leading to this one, relative to the patch root.
+    pub dirs: Vec<String>,
+    pub entries: Vec<ManifestEntry>
+}
+
+/// Modification state of the tree, used to notice changes without rehashing.
+type Fingerprint = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+#[derive(Clone, Debug)]
+pub struct PatchManifest {
+    tables: Vec<DirTable>,
+    /// (table, entry) for each patch id.
+    index: Vec<(usize, usize)>,
+    fingerprint: Fingerprint
+}
+
+impl PatchManifest {
+    /// Hash every file under `root`.
+    pub fn build(root: &Path) -> io::Result<PatchManifest> {
+        let mut m = PatchManifest {
+            tables: Vec::new(),
+            index: Vec::new(),
+            fingerprint: try!(fingerprint(root))
+        };
+        if root.exists() {
+            try!(m.walk(root, &mut Vec::new()));
+        } else {
+            warn!("Patch directory {:?} does not exist; no files will be patched", root);
+        }
+        Ok(m)
+    }
+
+    fn walk(&mut self, path: &Path, dirs: &mut Vec<String>) -> io::Result<()> {
+        let mut subdirs = Vec::new();
+        let mut entries = Vec::new();
+        for p in try!(sorted_dir(path)) {
+            let name = match p.file_name().and_then(|n| n.to_str()) {
+                Some(n) => n.to_string(),
+                None => {
+                    warn!("Skipping patch file with a non-UTF-8 name: {:?}", p);
+                    continue
+                }
+            };
+            if p.is_dir() {
+                if name.len() >= 64 {
+                    warn!("Skipping patch directory with a name too long for the client: {:?}", p);
+                    continue
+                }
+                subdirs.push((name, p));
+            } else {
+                if name.len() >= 32 {
+                    warn!("Skipping patch file with a name too long for the client: {:?}", p);
+                    continue
+                }
+                let mut buf = Vec::new();
+                try!(try!(File::open(&p)).read_to_end(&mut buf));
+                entries.push(ManifestEntry {
+                    patch_id: self.index.len() as u32,
+                    name: name,
+                    path: p,
+                    checksum: crc32(&buf[..]),
+                    size: buf.len() as u32,
+                    data: Arc::new(buf)
+                });
+                self.index.push((self.tables.len(), entries.len() - 1));
+            }
+        }
+        if entries.len() > 0 {
+            self.tables.push(DirTable {
+                dirs: dirs.clone(),
+                entries: entries
+            });
+        }
+
+        for (name, p) in subdirs {
+            dirs.push(name);
+            try!(self.walk(&p, dirs));
+            dirs.pop();
+        }
+        Ok(())
+    }
+
+    /// The per-directory tables, in the order the client should see them.
+    pub fn tables(&self) -> &[DirTable] {
+        &self.tables
+    }
+
+    /// Look up a file by the patch id sent to the client.
+    pub fn get(&self, patch_id: u32) -> Option<(&DirTable, &ManifestEntry)> {
+        self.index.get(patch_id as usize).map(|&(t, e)| {
+            let table = &self.tables[t];
+            (table, &table.entries[e])
+        })
+    }
+
+    /// Number of files in the manifest.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Total size in bytes of every file in the manifest.
+    pub fn total_size(&self) -> u64 {
+        self.tables.iter()
+            .flat_map(|t| t.entries.iter())
+            .fold(0, |acc, e| acc + e.size as u64)
+    }
+}
+
+fn sorted_dir(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for e in try!(fs::read_dir(path)) {
+        entries.push(try!(e).path());
+    }
+    // Keep the order stable so patch ids mean the same thing between scans.
+    entries.sort();
+    Ok(entries)
+}
+
+fn fingerprint(root: &Path) -> io::Result<Fingerprint> {
+    let mut fp = Vec::new();
+    if !root.exists() {
+        return Ok(fp)
+    }
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(p) = stack.pop() {
+        let md = try!(fs::metadata(&p));
+        fp.push((p.clone(), md.len(), md.modified().ok()));
+        if md.is_dir() {
+            stack.extend(try!(sorted_dir(&p)).into_iter().rev());
+        }
+    }
+    Ok(fp)
+}
+
+/// Bumped for each rescan an admin asks for.
+static RESCAN_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Ask every manifest to rescan on its next check.
+pub fn request_rescan() {
+    RESCAN_REQUESTS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_: ::libc::c_int) {
+    request_rescan();
+}
+
+/// Rescan every manifest when the server gets a SIGHUP.
+#[cfg(unix)]
+pub fn install_rescan_signal() {
+    unsafe {
+        ::libc::signal(::libc::SIGHUP, on_sighup as extern "C" fn(::libc::c_int) as ::libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_rescan_signal() {}
+
+/// A shared, atomically replaceable patch manifest.
+#[derive(Clone)]
+pub struct Manifest {
+    root: PathBuf,
+    current: Arc<RwLock<Arc<PatchManifest>>>
+}
+
+impl Manifest {
+    /// Build the initial manifest for `root`.
+    pub fn load(root: &str) -> io::Result<Manifest> {
+        let root = PathBuf::from(root);
+        let m = try!(PatchManifest::build(&root));
+        info!("Patch manifest for {:?}: {} files, {} bytes", root, m.len(), m.total_size());
+        Ok(Manifest {
+            root: root,
+            current: Arc::new(RwLock::new(Arc::new(m)))
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Get the current manifest. The snapshot is unaffected by later rescans.
+    pub fn snapshot(&self) -> Arc<PatchManifest> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Rehash the patch directory and swap in the new manifest. On failure the
+    /// old manifest stays in place.
+    pub fn rescan(&self) -> io::Result<()> {
+        let m = try!(PatchManifest::build(&self.root));
+        info!("Rescanned patch manifest for {:?}: {} files, {} bytes", self.root, m.len(), m.total_size());
+        *self.current.write().unwrap() = Arc::new(m);
+        Ok(())
+    }
+
+    /// Check whether anything in the patch directory was added, removed or
+    /// modified since the current manifest was built.
+    pub fn is_stale(&self) -> io::Result<bool> {
+        let fp = try!(fingerprint(&self.root));
+        Ok(fp != self.snapshot().fingerprint)
+    }
+
+    /// Spawn a thread that rescans when an admin asks for it and, if
+    /// `interval` is given, polls the patch directory that often and rescans
+    /// when it changes. Touching the patch directory itself is enough to
+    /// force a rescan.
+    pub fn spawn_watcher(&self, interval: Option<Duration>) {
+        let m = self.clone();
+        thread::spawn(move|| {
+            let tick = Duration::from_secs(1);
+            let mut seen = RESCAN_REQUESTS.load(Ordering::SeqCst);
+            let mut waited = Duration::from_secs(0);
+            loop {
+                thread::sleep(tick);
+                waited += tick;
+                let requests = RESCAN_REQUESTS.load(Ordering::SeqCst);
+                let stale = if requests != seen {
+                    seen = requests;
+                    info!("Rescan of patch directory {:?} requested", m.root);
+                    true
+                } else if interval.map(|i| waited >= i).unwrap_or(false) {
+                    waited = Duration::from_secs(0);
+                    match m.is_stale() {
+                        Ok(s) => s,
+                        Err(e) => {
+                            warn!("Unable to check patch directory {:?} for changes: {}", m.root, e);
+                            false
+                        }
+                    }
+                } else {
+                    false
+                };
+                if stale {
+                    if let Err(e) = m.rescan() {
+                        error!("Unable to rescan patch directory {:?}, keeping old manifest: {}", m.root, e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_rescan_keeps_snapshot() {
+        let root = env::temp_dir().join(format!("idola-manifest-test-{}", ::rand::random::<u32>()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        File::create(root.join("a.txt")).unwrap().write_all(b"old").unwrap();
+        File::create(root.join("sub").join("b.txt")).unwrap().write_all(b"b").unwrap();
+
+        let m = Manifest::load(root.to_str().unwrap()).unwrap();
+        let old = m.snapshot();
+        assert_eq!(old.len(), 2);
+
+        File::create(root.join("a.txt")).unwrap().write_all(b"newer").unwrap();
+        File::create(root.join("c.txt")).unwrap().write_all(b"c").unwrap();
+        assert!(m.is_stale().unwrap());
+        m.rescan().unwrap();
+
+        // The old snapshot still describes, and holds, the old files.
+        assert_eq!(old.len(), 2);
+        let (_, a) = old.get(0).unwrap();
+        assert_eq!(a.name, "a.txt");
+        assert_eq!(a.size, 3);
+        assert_eq!(&a.data[..], b"old");
+        assert_eq!(a.checksum, crc32(b"old"));
+
+        let new = m.snapshot();
+        assert_eq!(new.len(), 3);
+        assert_eq!(&new.get(0).unwrap().1.data[..], b"newer");
+        assert!(!m.is_stale().unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
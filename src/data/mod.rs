@@ -1,4 +1,9 @@
 //! The data service, an extension of the patch service.
+//!
+//! The data service offers every file in its patch manifest to the client.
+//! The client replies with the checksum and size of its own copy, and any file
+//! that differs is sent back in chunks, from the manifest the client was
+//! offered.
 
 use ::services::{Service, ServiceMsg};
 use ::loop_handler::LoopMsg;
@@ -6,6 +11,11 @@ use ::loop_handler::LoopMsg;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
 
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
 use std::thread;
 
 use std::net::SocketAddr;
@@ -13,19 +23,47 @@ use std::net::SocketAddr;
 use mio::tcp::TcpListener;
 use mio::Sender;
 
+use crc::crc32::checksum_ieee as crc32;
+
 use psomsg::patch::*;
 
 use ::services::message::NetMsg;
 
 use ::services::ServiceType;
 
+pub mod manifest;
+
+use self::manifest::{Manifest, PatchManifest, DirTable, ManifestEntry};
+
+/// The largest amount of file data sent in a single DataSend.
+pub const CHUNK_SIZE: usize = 0x6000;
+
+/// Per-client patching progress.
+struct ClientState {
+    manifest: Arc<PatchManifest>,
+    to_send: Vec<u32>
+}
+
 pub struct DataService {
     receiver: Receiver<ServiceMsg>,
-    sender: Sender<LoopMsg>
+    sender: Sender<LoopMsg>,
+    manifest: Manifest,
+    clients: HashMap<usize, ClientState>
+}
+
+fn str_to_bytes(s: &str, dst: &mut [u8]) {
+    for (d, b) in dst.iter_mut().zip(s.bytes()) {
+        *d = b;
+    }
 }
 
 impl DataService {
-    pub fn spawn(bind: &SocketAddr, sender: Sender<LoopMsg>) -> Service {
+    /// Serve the files under `path`, checking them for changes every
+    /// `rescan_interval` if it's given.
+    pub fn spawn(bind: &SocketAddr, sender: Sender<LoopMsg>, path: &str, rescan_interval: Option<Duration>) -> io::Result<Service> {
+        let manifest = try!(Manifest::load(path));
+        manifest.spawn_watcher(rescan_interval);
+
         let (tx, rx) = channel();
 
         let listener = TcpListener::bind(bind).expect("Couldn't create tcplistener");
@@ -33,68 +71,146 @@ impl DataService {
         thread::spawn(move|| {
             let d = DataService {
                 receiver: rx,
-                sender: sender
+                sender: sender,
+                manifest: manifest,
+                clients: HashMap::new()
             };
             d.run()
         });
 
-        Service::new(listener, tx, ServiceType::Patch)
+        Ok(Service::new(listener, tx, ServiceType::Patch))
     }
 
-    pub fn run(self) {
-        let DataService {
-            receiver,
-            sender
-        } = self;
+    fn send(&self, id: usize, m: Message) {
+        self.sender.send(LoopMsg::Client(id, m.into())).unwrap();
+    }
+
+    /// Send the directory changes needed to get from `from` to `to`.
+    fn change_dir(&self, id: usize, from: &[String], to: &[String]) {
+        let common = from.iter().zip(to.iter()).take_while(|&(a, b)| a == b).count();
+        for _ in common..from.len() {
+            self.send(id, Message::OneDirUp(None));
+        }
+        for d in to[common..].iter() {
+            let mut sd = SetDirectory { dirname: Default::default() };
+            str_to_bytes(d, &mut sd.dirname[..]);
+            self.send(id, sd.into());
+        }
+    }
 
-        info!("Data service running");
+    fn send_file_list(&mut self, id: usize) {
+        let manifest = self.manifest.snapshot();
+
+        self.send(id, Message::StartList(None));
+        self.send(id, SetDirectory { dirname: Default::default() }.into());
+        {
+            let mut cwd: &[String] = &[];
+            for t in manifest.tables() {
+                self.change_dir(id, cwd, &t.dirs);
+                cwd = &t.dirs;
+                for e in t.entries.iter() {
+                    let mut fi = FileInfo { patch_id: e.patch_id, filename: Default::default() };
+                    str_to_bytes(&e.name, &mut fi.filename[..]);
+                    self.send(id, fi.into());
+                }
+            }
+            self.change_dir(id, cwd, &[]);
+        }
+        self.send(id, Message::OneDirUp(None));
+        self.send(id, Message::InfoFinished(None));
 
-        for msg in receiver.iter() {
+        self.clients.insert(id, ClientState { manifest: manifest, to_send: Vec::new() });
+    }
+
+    fn file_info_reply(&mut self, id: usize, m: FileInfoReply) {
+        if let Some(cs) = self.clients.get_mut(&id) {
+            match cs.manifest.get(m.patch_id) {
+                Some((_, e)) => {
+                    if e.checksum != m.checksum || e.size != m.size {
+                        cs.to_send.push(m.patch_id);
+                    }
+                },
+                None => warn!("client {} replied about unknown patch id {}", id, m.patch_id)
+            }
+        }
+    }
+
+    fn send_file(&self, id: usize, e: &ManifestEntry) {
+        let mut fs = FileSend { padding: 0, size: e.size, filename: Default::default() };
+        str_to_bytes(&e.name, &mut fs.filename[..]);
+        self.send(id, fs.into());
+        for (i, chunk) in e.data.chunks(CHUNK_SIZE).enumerate() {
+            self.send(id, DataSend {
+                chunk_num: i as u32,
+                checksum: crc32(chunk),
+                chunk_size: chunk.len() as u32,
+                data: chunk.to_vec()
+            }.into());
+        }
+        self.send(id, FileDone { padding: 0 }.into());
+    }
+
+    fn send_files(&mut self, id: usize) {
+        let cs = match self.clients.remove(&id) {
+            Some(cs) => cs,
+            None => {
+                warn!("client {} finished a file list it was never sent", id);
+                self.sender.send(LoopMsg::DropClient(id)).unwrap();
+                return
+            }
+        };
+
+        let files: Vec<(&DirTable, &ManifestEntry)> = cs.to_send.iter().filter_map(|&p| cs.manifest.get(p)).collect();
+
+        if files.len() > 0 {
+            let total_length = files.iter().fold(0, |acc, &(_, e)| acc + e.size);
+            self.send(id, SendInfo { total_length: total_length, total_file: files.len() as u32 }.into());
+        }
+
+        self.send(id, SetDirectory { dirname: Default::default() }.into());
+        let mut cwd: &[String] = &[];
+        for &(t, e) in files.iter() {
+            self.change_dir(id, cwd, &t.dirs);
+            cwd = &t.dirs;
+            self.send_file(id, e);
+        }
+        self.change_dir(id, cwd, &[]);
+        self.send(id, Message::OneDirUp(None));
+        self.send(id, Message::SendDone(None));
+        info!("client {} was updated successfully ({} files sent)", id, files.len());
+    }
+
+    pub fn run(mut self) {
+        info!("Data service running, serving files from {:?}", self.manifest.root());
+
+        loop {
+            let msg = match self.receiver.recv() {
+                Ok(m) => m,
+                Err(_) => return
+            };
             match msg {
                 ServiceMsg::ClientConnected((_addr, id)) => {
                     info!("Client {} connected to data service", id);
                     let w = Message::Welcome(Some(Welcome { server_vector: 0, client_vector: 0 }));
-                    sender.send(LoopMsg::Client(id, w.into())).unwrap();
+                    self.send(id, w);
                 },
                 ServiceMsg::ClientDisconnected(id) => {
-                    info!("Client {} disconnected from data service.", id)
-
+                    info!("Client {} disconnected from data service.", id);
+                    self.clients.remove(&id);
                 },
                 ServiceMsg::ClientSaid(id, NetMsg::Patch(m)) => {
                     match m {
                         Message::Welcome(None) => {
-                            sender.send(LoopMsg::Client(id,
-                                Message::Login(None).into()
-                            )).unwrap();
+                            self.send(id, Message::Login(None));
                         },
                         Message::Login(Some(..)) => {
-                            // Message::StartList(None).serialize(&mut w_s).unwrap();
-                            // Message::SetDirectory(Some(SetDirectory { dirname: StaticVec::default() })).serialize(&mut w_s).unwrap();
-                            // Message::InfoFinished(None).serialize(&mut w_s).unwrap();
-                            sender.send(LoopMsg::Client(id,
-                                Message::StartList(None).into()
-                            )).unwrap();
-                            sender.send(LoopMsg::Client(id,
-                                Message::SetDirectory(Some(SetDirectory { dirname: Default::default() })).into()
-                            )).unwrap();
-                            sender.send(LoopMsg::Client(id,
-                                Message::InfoFinished(None).into()
-                            )).unwrap();
+                            self.send_file_list(id);
+                        },
+                        Message::FileInfoReply(Some(m)) => {
+                            self.file_info_reply(id, m);
                         },
                         Message::FileListDone(_) => {
-                            // Message::SetDirectory(Some(SetDirectory { dirname: StaticVec::default() })).serialize(&mut w_s).unwrap();
-                            // Message::OneDirUp(None).serialize(&mut w_s).unwrap();
-                            // Message::SendDone(None).serialize(&mut w_s).unwrap();
-                            sender.send(LoopMsg::Client(id,
-                                Message::SetDirectory(Some(SetDirectory { dirname: Default::default() })).into()
-                            )).unwrap();
-                            sender.send(LoopMsg::Client(id,
-                                Message::OneDirUp(None).into()
-                            )).unwrap();
-                            sender.send(LoopMsg::Client(id,
-                                Message::SendDone(None).into()
-                            )).unwrap();
-                            info!("client {} was 'updated' successfully", id);
+                            self.send_files(id);
                         },
                         u => { warn!("client sent weird message: {:?}", u) }
                     }
@@ -0,0 +1,402 @@
+//! Rolling single items out of a probability table.
+//!
+//! The tables are indexed by drop level, 0 through 9, rather than by the area
+//! numbers the client uses; `pt_area` converts between them.
+
+use std::cmp::{min, max};
+
+use rand::Rng;
+
+use psodata::chara::{ItemData, WEAPON_UNIDENTIFIED};
+use psodata::itempt::ProbTable;
+use psodata::itemrt::RtEntry;
+
+/// Weapon specials by rank, as given in `ProbTable::element_ranking`.
+pub static SPECIALS: [&'static [u8]; 4] = [
+    // Draw, Heart, Ice, Bind, Heat, Shock, Dim, Panic
+    &[0x01, 0x05, 0x0F, 0x13, 0x17, 0x1B, 0x1F, 0x23],
+    // Drain, Mind, Master's, Frost, Hold, Fire, Thunder, Shadow, Riot
+    &[0x02, 0x06, 0x09, 0x10, 0x14, 0x18, 0x1C, 0x20, 0x24],
+    // Fill, Soul, Lord's, Charge, Spirit, Berserk, Freeze, Seize, Flame,
+    // Storm, Dark, Havoc, Devil's
+    &[0x03, 0x07, 0x0A, 0x0C, 0x0D, 0x0E, 0x11, 0x15, 0x19, 0x1D, 0x21, 0x25, 0x27],
+    // Gush, Geist, King's, Blizzard, Arrest, Burning, Tempest, Hell, Chaos,
+    // Demon's
+    &[0x04, 0x08, 0x0B, 0x12, 0x16, 0x1A, 0x1E, 0x22, 0x26, 0x28]
+];
+
+/// The rank of a weapon special, from 1 to 4, or 0 for none.
+pub fn special_rank(special: u8) -> u32 {
+    SPECIALS.iter().position(|s| s.contains(&(special & 0x3F))).map(|r| r as u32 + 1).unwrap_or(0)
+}
+
+/// Index of technique disks in `ProbTable::tool_freq`.
+const TOOL_TECH_DISK: usize = 26;
+
+/// The tool (second and third item bytes) for each entry of
+/// `ProbTable::tool_freq`. Entries without a tool never drop.
+static TOOLS: [Option<(u8, u8)>; 28] = [
+    Some((0x00, 0x00)), // Monomate
+    Some((0x00, 0x01)), // Dimate
+    Some((0x00, 0x02)), // Trimate
+    Some((0x01, 0x00)), // Monofluid
+    Some((0x01, 0x01)), // Difluid
+    Some((0x01, 0x02)), // Trifluid
+    Some((0x06, 0x00)), // Antidote
+    Some((0x06, 0x01)), // Antiparalysis
+    Some((0x03, 0x00)), // Sol Atomizer
+    Some((0x04, 0x00)), // Moon Atomizer
+    Some((0x05, 0x00)), // Star Atomizer
+    Some((0x07, 0x00)), // Telepipe
+    Some((0x08, 0x00)), // Trap Vision
+    Some((0x0A, 0x00)), // Monogrinder
+    Some((0x0A, 0x01)), // Digrinder
+    Some((0x0A, 0x02)), // Trigrinder
+    Some((0x0B, 0x00)), // Power Material
+    Some((0x0B, 0x01)), // Mind Material
+    Some((0x0B, 0x02)), // Evade Material
+    Some((0x0B, 0x03)), // HP Material
+    Some((0x0B, 0x04)), // TP Material
+    Some((0x0B, 0x05)), // Def Material
+    Some((0x0B, 0x06)), // Luck Material
+    Some((0x09, 0x00)), // Scape Doll
+    None,
+    None,
+    None, // Technique disk, see TOOL_TECH_DISK
+    None
+];
+
+/// Highest frame, shield and unit indices that drop as common items.
+pub const MAX_FRAME: i32 = 0x17;
+pub const MAX_SHIELD: i32 = 0x14;
+pub const MAX_UNIT: i32 = 0x3B;
+
+/// Convert the client's area number to a drop level. Bosses drop like the area
+/// leading up to them.
+pub fn pt_area(episode: u8, area: u8) -> usize {
+    let level = match episode {
+        1 => match area {
+            1..=10 => area - 1,
+            11 => 1, // Dragon
+            12 => 4, // De Rol Le
+            13 => 6, // Vol Opt
+            14 => 9, // Dark Falz
+            _ => 0
+        },
+        2 => match area {
+            1..=4 => area - 1, // Temple and Spaceship
+            5 | 6 => 4, // CCA, Jungle North
+            7 | 8 => 5, // Jungle East, Mountain
+            9 | 12 | 16 => 6, // Seaside, Gal Gryphon
+            10 => 7,
+            11 => 8,
+            13 | 17 => 9, // Olga Flow, Tower
+            14 => 1, // Barba Ray
+            15 => 3, // Gol Dragon
+            _ => 0
+        },
+        3 => match area {
+            1..=8 => area - 1, // Crater and Desert
+            9 => 9, // Saint-Milion
+            _ => 0
+        },
+        _ => 0
+    };
+    level as usize
+}
+
+/// Pick an index with probability proportional to its weight. Negative weights
+/// count as zero. Yields None if no weight is positive.
+pub fn weighted<R: Rng, I: Iterator<Item=i32>>(rng: &mut R, weights: I) -> Option<usize> {
+    let weights: Vec<u32> = weights.map(|w| max(w, 0) as u32).collect();
+    let total = weights.iter().fold(0, |a, &w| a + w);
+    if total == 0 {
+        return None
+    }
+    let mut roll = rng.gen_range(0, total);
+    for (i, &w) in weights.iter().enumerate() {
+        if roll < w {
+            return Some(i)
+        }
+        roll -= w;
+    }
+    None
+}
+
+fn percent<R: Rng>(rng: &mut R, chance: i32) -> bool {
+    chance > 0 && rng.gen_range(0, 100) < chance
+}
+
+/// A common weapon, with grind, special and attributes rolled for the area.
+pub fn weapon<R: Rng>(rng: &mut R, pt: &ProbTable, area: usize) -> Option<ItemData> {
+    let rank = |i: usize| {
+        let floor = max(pt.weapon_upgfloor[i] as i32, 1);
+        pt.weapon_minrank[i] as i32 + area as i32 / floor
+    };
+    let kind = match weighted(rng, (0..12).map(|i| if rank(i) >= 0 { pt.weapon_ratio[i] as i32 } else { 0 })) {
+        Some(k) => k,
+        None => return None
+    };
+
+    let mut item = ItemData::default();
+    item.data[0] = 0x00;
+    item.data[1] = kind as u8 + 1;
+    item.data[2] = min(rank(kind), 4) as u8;
+
+    let pattern = min(max(pt.area_pattern[0][area] as i32, 0), 3) as usize;
+    item.data[3] = weighted(rng, pt.power_pattern.iter().map(|p| p[pattern] as i32)).unwrap_or(0) as u8;
+
+    let special_rank = pt.element_ranking[area] as i32;
+    if special_rank >= 1 && special_rank <= 4 && percent(rng, pt.element_probability[area] as i32) {
+        let specials = SPECIALS[special_rank as usize - 1];
+        item.data[4] = specials[rng.gen_range(0, specials.len())] | WEAPON_UNIDENTIFIED;
+    }
+
+    // Up to three attributes, none of them twice. The first row of
+    // percent_attachment is the chance of no attribute at all.
+    let mut slot = 0;
+    for i in 0..3 {
+        let attr = match weighted(rng, pt.percent_attachment.iter().map(|p| p[area] as i32)) {
+            Some(0) | None => continue,
+            Some(a) => a as u8
+        };
+        if (0..slot).any(|s| item.data[6 + s * 2] == attr) {
+            continue
+        }
+        let pattern = min(max(pt.area_pattern[min(i + 1, 2)][area] as i32, 0), 5) as usize;
+        let pct = match weighted(rng, pt.percent_pattern.iter().map(|p| p[pattern] as i32)) {
+            Some(p) => p as i32 * 5 - 10,
+            None => continue
+        };
+        item.data[6 + slot * 2] = attr;
+        item.data[7 + slot * 2] = pct as i8 as u8;
+        slot += 1;
+    }
+    Some(item)
+}
+
+fn armor_index<R: Rng>(rng: &mut R, pt: &ProbTable, area: usize, highest: i32) -> Option<u8> {
+    weighted(rng, pt.armor_ranking.iter().map(|&r| r as i32))
+        .map(|r| min(max(r as i32 + area as i32 + pt.armor_level, 0), highest) as u8)
+}
+
+/// A common frame, with its slots rolled.
+pub fn frame<R: Rng>(rng: &mut R, pt: &ProbTable, area: usize) -> Option<ItemData> {
+    let index = match armor_index(rng, pt, area, MAX_FRAME) {
+        Some(i) => i,
+        None => return None
+    };
+    let mut item = ItemData::default();
+    item.data[0] = 0x01;
+    item.data[1] = 0x01;
+    item.data[2] = index;
+    item.data[5] = weighted(rng, pt.slot_ranking.iter().map(|&r| r as i32)).unwrap_or(0) as u8;
+    Some(item)
+}
+
+/// A common shield.
+pub fn shield<R: Rng>(rng: &mut R, pt: &ProbTable, area: usize) -> Option<ItemData> {
+    let index = match armor_index(rng, pt, area, MAX_SHIELD) {
+        Some(i) => i,
+        None => return None
+    };
+    let mut item = ItemData::default();
+    item.data[0] = 0x01;
+    item.data[1] = 0x02;
+    item.data[2] = index;
+    Some(item)
+}
+
+/// A common unit no better than the area allows.
+pub fn unit<R: Rng>(rng: &mut R, pt: &ProbTable, area: usize) -> Option<ItemData> {
+    let level = min(pt.unit_level[area] as i32, MAX_UNIT);
+    if level < 0 {
+        return None
+    }
+    let mut item = ItemData::default();
+    item.data[0] = 0x01;
+    item.data[1] = 0x03;
+    item.data[2] = rng.gen_range(0, level + 1) as u8;
+    Some(item)
+}
+
+/// A tool or technique disk.
+pub fn tool<R: Rng>(rng: &mut R, pt: &ProbTable, area: usize) -> Option<ItemData> {
+    let kind = weighted(rng, (0..28).map(|i| {
+        if TOOLS[i].is_some() || i == TOOL_TECH_DISK { pt.tool_freq[i][area] as i32 } else { 0 }
+    }));
+    let mut item = ItemData::default();
+    item.data[0] = 0x03;
+    match kind {
+        Some(TOOL_TECH_DISK) => {
+            let tech = match weighted(rng, pt.tech_freq.iter().map(|t| t[area] as i32)) {
+                Some(t) => t,
+                None => return None
+            };
+            let levels = max(pt.tech_levels[tech][area] as i32, 1);
+            item.data[1] = 0x02;
+            item.data[2] = rng.gen_range(0, levels) as u8;
+            item.data[4] = tech as u8;
+        },
+        Some(k) => {
+            let (a, b) = TOOLS[k].unwrap();
+            item.data[1] = a;
+            item.data[2] = b;
+            item.set_stack_count(1);
+        },
+        None => return None
+    }
+    Some(item)
+}
+
+/// Meseta between the two amounts, inclusive.
+pub fn meseta<R: Rng>(rng: &mut R, range: [u16; 2]) -> Option<ItemData> {
+    let low = range[0] as u32;
+    let high = max(range[1] as u32, low);
+    if high == 0 {
+        return None
+    }
+    Some(ItemData::meseta(rng.gen_range(low, high + 1), 0))
+}
+
+/// The rare item of a rare table entry, if it's rolled. `rate` scales the
+/// entry's probability. Rare weapons need identifying.
+pub fn rare<R: Rng>(rng: &mut R, entry: &RtEntry, rate: f64) -> Option<ItemData> {
+    if entry.item_data == [0; 3] || rng.gen::<f64>() >= entry.probability() * rate {
+        return None
+    }
+    let mut item = ItemData::default();
+    item.data[0..3].copy_from_slice(&entry.item_data);
+    item.set_stack_count(1);
+    if item.data[0] == 0x00 {
+        item.data[4] = WEAPON_UNIDENTIFIED;
+    }
+    Some(item)
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::io::Cursor;
+
+    use rand::{SeedableRng, XorShiftRng};
+
+    use psoserial::Serial;
+
+    use super::*;
+
+    /// A probability table where nothing drops.
+    pub fn blank_pt() -> ProbTable {
+        ProbTable::deserialize(&mut Cursor::new(vec![0u8; 0x1000])).unwrap()
+    }
+
+    /// A rare table entry for the item, with a 1 in 16 chance.
+    pub fn rare_entry(item: [u8; 3]) -> RtEntry {
+        RtEntry {
+            prob: 0xE1,
+            item_data: item
+        }
+    }
+
+    #[test]
+    fn test_pt_area() {
+        assert_eq!(pt_area(1, 1), 0);
+        assert_eq!(pt_area(1, 10), 9);
+        assert_eq!(pt_area(1, 14), 9);
+        assert_eq!(pt_area(2, 4), 3);
+        assert_eq!(pt_area(2, 14), 1);
+        assert_eq!(pt_area(3, 8), 7);
+        assert_eq!(pt_area(3, 9), 9);
+        assert_eq!(pt_area(4, 1), 0);
+    }
+
+    #[test]
+    fn test_weapon_stays_in_bounds() {
+        let area = 3;
+        let mut pt = blank_pt();
+        assert!(weapon(&mut XorShiftRng::from_seed([1, 2, 3, 4]), &pt, area).is_none());
+
+        // Only sabers, grinds 0 to 4, rank 2 specials every time and an
+        // attribute roll for every slot.
+        pt.weapon_ratio[1] = 1;
+        for p in pt.power_pattern.iter_mut().take(5) {
+            p[0] = 1;
+        }
+        pt.element_ranking[area] = 2;
+        pt.element_probability[area] = 100;
+        for a in pt.percent_attachment.iter_mut() {
+            a[area] = 10;
+        }
+        for p in pt.percent_pattern.iter_mut() {
+            p[0] = 1;
+        }
+
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut seen_three = false;
+        for _ in 0..1000 {
+            let item = weapon(&mut rng, &pt, area).unwrap();
+            assert_eq!(&item.data[0..2], &[0x00, 0x02]);
+            assert!(item.data[3] <= 4);
+            assert!(item.data[4] & WEAPON_UNIDENTIFIED != 0);
+            assert_eq!(special_rank(item.data[4]), 2);
+
+            let attrs: Vec<(u8, i8)> = (0..3).map(|s| (item.data[6 + s * 2], item.data[7 + s * 2] as i8)).collect();
+            let count = attrs.iter().take_while(|&&(a, _)| a != 0).count();
+            assert!(attrs[count..].iter().all(|&(a, p)| a == 0 && p == 0));
+            for (i, &(a, p)) in attrs[..count].iter().enumerate() {
+                assert!(a >= 1 && a <= 5);
+                assert!(attrs[..i].iter().all(|&(b, _)| b != a));
+                assert!(p >= -10 && p <= 100 && p % 5 == 0);
+            }
+            seen_three |= count == 3;
+        }
+        assert!(seen_three);
+    }
+
+    #[test]
+    fn test_tech_disk_levels() {
+        let area = 5;
+        let mut pt = blank_pt();
+        assert!(tool(&mut XorShiftRng::from_seed([5, 6, 7, 8]), &pt, area).is_none());
+
+        pt.tool_freq[TOOL_TECH_DISK][area] = 1;
+        pt.tech_freq[4][area] = 1;
+        pt.tech_levels[4][area] = 5;
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let mut highest = 0;
+        for _ in 0..1000 {
+            let item = tool(&mut rng, &pt, area).unwrap();
+            assert_eq!(&item.data[0..2], &[0x03, 0x02]);
+            assert_eq!(item.data[4], 4);
+            assert!(item.data[2] < 5);
+            highest = max(highest, item.data[2]);
+        }
+        assert_eq!(highest, 4);
+
+        // Disks always come in at least level 1.
+        pt.tech_levels[4][area] = 0;
+        for _ in 0..100 {
+            assert_eq!(tool(&mut rng, &pt, area).unwrap().data[2], 0);
+        }
+    }
+
+    #[test]
+    fn test_rare_rate_scales() {
+        let entry = rare_entry([0x00, 0x01, 0x00]);
+        assert_eq!(entry.probability(), 1.0 / 16.0);
+        let mut rng = XorShiftRng::from_seed([9, 10, 11, 12]);
+
+        let count = |rng: &mut XorShiftRng, rate: f64| (0..16000).filter(|_| rare(rng, &entry, rate).is_some()).count();
+        assert_eq!(count(&mut rng, 0.0), 0);
+        assert_eq!(count(&mut rng, 16.0), 16000);
+        let once = count(&mut rng, 1.0);
+        let twice = count(&mut rng, 2.0);
+        assert!(once > 800 && once < 1200);
+        assert!(twice > 1800 && twice < 2200);
+
+        // Rare weapons need identifying; an empty entry never drops.
+        let item = rare(&mut rng, &entry, 16.0).unwrap();
+        assert_eq!(&item.data[0..3], &[0x00, 0x01, 0x00]);
+        assert_eq!(item.data[4], WEAPON_UNIDENTIFIED);
+        assert!(rare(&mut rng, &rare_entry([0; 3]), 16.0).is_none());
+    }
+}
@@ -4,11 +4,32 @@ use std::io;
 use std::fs::File;
 use std::collections::HashMap;
 
-use psodata::itempt::ItemPT;
-use psodata::itemrt::ItemRT;
+use rand::Rng;
+
+use psodata::chara::ItemData;
+use psodata::itempt::{ItemPT, ProbTable};
+use psodata::itemrt::{ItemRT, RtSet};
+use psodata::map::MapObject;
 use psodata::gsl::GslFile;
 use psodata::gsl;
 
+use ::rates::Rates;
+
+pub mod gen;
+
+/// A map object parameter holding 1.0f32, which marks fixed boxes.
+const FIXED_BOX_PARAM: u32 = 0x3F800000;
+
+/// What a party's drops depend on, besides the enemy or box.
+#[derive(Clone, Copy, Debug)]
+pub struct DropMode {
+    /// Party episode; 3 is Episode 4.
+    pub episode: u8,
+    pub challenge: bool,
+    pub difficulty: u8,
+    pub section_id: u8
+}
+
 /// A struct storing all the probability tables inside an ItemPT.gsl file.
 /// Provides methods for retrieving references to them by episode and mode.
 pub struct DropTable {
@@ -99,6 +120,141 @@ impl DropTable {
             rt_ep4: rt_ep4
         })
     }
+
+    /// The probability table for a party, if it was loaded.
+    pub fn prob_table(&self, mode: &DropMode) -> Option<&ProbTable> {
+        let tables = match (mode.episode, mode.challenge) {
+            (1, false) => &self.ep1,
+            (2, false) => &self.ep2,
+            (3, _) => &self.ep4,
+            (1, true) => &self.ep1c,
+            (2, true) => &self.ep2c,
+            _ => return None
+        };
+        tables.as_ref()
+            .and_then(|t| t.get(mode.difficulty as usize))
+            .and_then(|t| t.section(mode.section_id))
+    }
+
+    /// The rare table for a party, if it was loaded. Challenge mode has no
+    /// rares.
+    pub fn rare_table(&self, mode: &DropMode) -> Option<&RtSet> {
+        if mode.challenge {
+            return None
+        }
+        let tables = match mode.episode {
+            1 => &self.rt_ep1,
+            2 => &self.rt_ep2,
+            3 => &self.rt_ep4,
+            _ => return None
+        };
+        tables.as_ref()
+            .and_then(|t| t.get(mode.difficulty as usize))
+            .and_then(|t| t.section(mode.section_id))
+    }
+
+    /// Roll the drop of a killed enemy in the client's area `area`. The item
+    /// is yet to be given an ID.
+    pub fn enemy_drop<R: Rng>(&self, rng: &mut R, mode: &DropMode, rates: &Rates, rt_entry: usize, area: u8) -> Option<ItemData> {
+        let pt = match self.prob_table(mode) {
+            Some(pt) => pt,
+            None => return None
+        };
+        let dar = match pt.enemy_dar.get(rt_entry) {
+            Some(&dar) => (dar as i64 * rates.dar as i64 / 100) as i32,
+            None => return None
+        };
+        if dar <= 0 || rng.gen_range(0, 100) >= dar {
+            return None
+        }
+
+        if let Some(rt) = self.rare_table(mode) {
+            if let Some(item) = rt.enemy_rares.get(rt_entry).and_then(|e| gen::rare(rng, e, rates.rare as f64 / 100.0)) {
+                return Some(item)
+            }
+        }
+
+        let area = gen::pt_area(mode.episode, area);
+        // A third of drops are the enemy's own kind of item, a third tools and
+        // a third meseta.
+        match rng.gen_range(0, 3) {
+            0 => match pt.enemy_drop[rt_entry] {
+                0 => gen::weapon(rng, pt, area),
+                1 => gen::frame(rng, pt, area),
+                2 => gen::shield(rng, pt, area),
+                3 => gen::unit(rng, pt, area),
+                _ => None
+            },
+            1 => gen::tool(rng, pt, area),
+            _ => gen::meseta(rng, pt.enemy_meseta[rt_entry])
+        }
+    }
+
+    /// Roll the drop of a broken box in the client's area `area`. Fixed boxes
+    /// drop their own item, or an item of their own kind.
+    pub fn box_drop<R: Rng>(&self, rng: &mut R, mode: &DropMode, rates: &Rates, obj: &MapObject, area: u8) -> Option<ItemData> {
+        let pt = match self.prob_table(mode) {
+            Some(pt) => pt,
+            None => return None
+        };
+        let pt_area = gen::pt_area(mode.episode, area);
+
+        let kind = match fixed_box(obj) {
+            Some((true, item)) => return Some(item),
+            Some((false, item)) => match (item.data[0], item.data[1]) {
+                (0, _) => 0,
+                (1, 1) => 1,
+                (1, 2) => 2,
+                (1, 3) => 3,
+                (3, _) => 4,
+                (4, _) => 5,
+                _ => return None
+            },
+            None => {
+                if let Some(rt) = self.rare_table(mode) {
+                    for e in rt.box_rares_in(area) {
+                        if let Some(item) = gen::rare(rng, e, rates.rare as f64 / 100.0) {
+                            return Some(item)
+                        }
+                    }
+                }
+                match gen::weighted(rng, pt.box_drop.iter().map(|k| k[pt_area] as i32)) {
+                    Some(k) => k,
+                    None => return None
+                }
+            }
+        };
+        match kind {
+            0 => gen::weapon(rng, pt, pt_area),
+            1 => gen::frame(rng, pt, pt_area),
+            2 => gen::shield(rng, pt, pt_area),
+            3 => gen::unit(rng, pt, pt_area),
+            4 => gen::tool(rng, pt, pt_area),
+            5 => gen::meseta(rng, pt.box_meseta[pt_area]),
+            // Nothing at all
+            _ => None
+        }
+    }
+}
+
+/// If the object is a fixed box, whether it drops exactly the item it names
+/// and the item. The item code is stored byte-swapped in the third parameter.
+fn fixed_box(obj: &MapObject) -> Option<(bool, ItemData)> {
+    if (obj.skin != 0x0092 && obj.skin != 0x0161) || obj.data.len() < 4 || obj.data[0] != FIXED_BOX_PARAM {
+        return None
+    }
+    let code = obj.data[2];
+    let mut item = ItemData::default();
+    item.data[0] = (code >> 24) as u8;
+    item.data[1] = (code >> 16) as u8;
+    item.data[2] = (code >> 8) as u8;
+    item.data[3] = code as u8;
+    if item.is_meseta() {
+        // Meseta boxes give their amount in tens.
+        item = ItemData::meseta((obj.data[3] >> 16) * 10, 0);
+    }
+    item.set_stack_count(1);
+    Some((obj.data[1] == FIXED_BOX_PARAM, item))
 }
 
 fn convert_gslfile_vec_to_hash_map(files: Vec<GslFile>) -> HashMap<String, Vec<u8>> {
@@ -196,3 +352,156 @@ fn build_rt_ep_vec(files: &HashMap<String, Vec<u8>>, episode: &str) -> io::Resul
     ret.push(try!(ItemRT::load_from_buffers(&r[..])));
     Ok(ret)
 }
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use psodata::chara::WEAPON_UNIDENTIFIED;
+    use psodata::itemrt::RtEntry;
+
+    use super::*;
+    use super::gen::test::{blank_pt, rare_entry};
+
+    const MODE: DropMode = DropMode { episode: 1, challenge: false, difficulty: 0, section_id: 0 };
+
+    /// The rare table entry of the enemy the tests kill.
+    const ENEMY: usize = 5;
+
+    fn blank_rt() -> RtSet {
+        RtSet {
+            enemy_rares: vec![RtEntry::default(); 101],
+            box_areas: vec![0; 30],
+            box_rares: vec![RtEntry::default(); 30]
+        }
+    }
+
+    fn drop_table(pt: ProbTable, rt: RtSet) -> DropTable {
+        DropTable {
+            ep1: Some(vec![ItemPT::from_sections(vec![pt])]),
+            ep2: None,
+            ep4: None,
+            ep1c: None,
+            ep2c: None,
+            rt_ep1: Some(vec![ItemRT::from_sections(vec![rt])]),
+            rt_ep2: None,
+            rt_ep4: None
+        }
+    }
+
+    /// An enemy with the given DAR that drops sabers, Monomates or meseta in
+    /// Forest 1.
+    fn enemy_pt(dar: i8) -> ProbTable {
+        let mut pt = blank_pt();
+        pt.enemy_dar[ENEMY] = dar;
+        pt.enemy_drop[ENEMY] = 0;
+        pt.enemy_meseta[ENEMY] = [10, 20];
+        pt.weapon_ratio[0] = 1;
+        pt.tool_freq[0][0] = 1;
+        pt
+    }
+
+    fn rates(dar: u32, rare: u32) -> Rates {
+        let mut r = Rates::default();
+        r.dar = dar;
+        r.rare = rare;
+        r
+    }
+
+    fn drops(table: &DropTable, rng: &mut XorShiftRng, rates: &Rates) -> Vec<ItemData> {
+        (0..1000).filter_map(|_| table.enemy_drop(rng, &MODE, rates, ENEMY, 1)).collect()
+    }
+
+    #[test]
+    fn test_enemy_dar() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let table = drop_table(enemy_pt(0), blank_rt());
+        assert!(drops(&table, &mut rng, &rates(100, 100)).is_empty());
+        assert!(drops(&table, &mut rng, &rates(1000, 100)).is_empty());
+
+        let table = drop_table(enemy_pt(100), blank_rt());
+        let all = drops(&table, &mut rng, &rates(100, 100));
+        assert_eq!(all.len(), 1000);
+        for item in all.iter() {
+            match item.data[0] {
+                0x00 => assert_eq!(item.data[1], 0x01),
+                0x03 => assert_eq!(&item.data[1..3], &[0x00, 0x00]),
+                0x04 => assert!(item.meseta_amount() >= 10 && item.meseta_amount() <= 20),
+                _ => panic!("unexpected drop {:?}", item.data)
+            }
+        }
+        assert!(drops(&table, &mut rng, &rates(0, 100)).is_empty());
+
+        // The DAR rate scales the enemy's own.
+        let half = drops(&table, &mut rng, &rates(50, 100)).len();
+        assert!(half > 400 && half < 600);
+    }
+
+    #[test]
+    fn test_enemy_rare_rate() {
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let mut rt = blank_rt();
+        rt.enemy_rares[ENEMY] = rare_entry([0x00, 0x05, 0x00]);
+        let table = drop_table(enemy_pt(100), rt);
+        let is_rare = |i: &ItemData| i.data[1] == 0x05;
+
+        assert!(!drops(&table, &mut rng, &rates(100, 0)).iter().any(&is_rare));
+        let once = drops(&table, &mut rng, &rates(100, 100)).iter().filter(|i| is_rare(i)).count();
+        assert!(once > 30 && once < 100);
+        let all = drops(&table, &mut rng, &rates(100, 1600));
+        assert!(all.iter().all(|i| is_rare(i) && i.data[4] == WEAPON_UNIDENTIFIED));
+
+        // Challenge mode has no rares.
+        let mut mode = MODE;
+        mode.challenge = true;
+        assert!(table.enemy_drop(&mut rng, &mode, &rates(100, 1600), ENEMY, 1).is_none());
+    }
+
+    fn map_box(data: Vec<u32>) -> MapObject {
+        MapObject {
+            skin: 0x0092,
+            unk1: 0,
+            unk2: 0,
+            obj_id: 0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            rpl: 0,
+            rotation: 0,
+            unk3: 0,
+            unk4: 0,
+            data: data
+        }
+    }
+
+    #[test]
+    fn test_box_drop() {
+        let mut rng = XorShiftRng::from_seed([9, 10, 11, 12]);
+        let mut pt = blank_pt();
+        pt.box_meseta[0] = [1, 5];
+        let mut rt = blank_rt();
+        rt.box_areas[0] = 2;
+        rt.box_rares[0] = rare_entry([0x03, 0x0A, 0x02]);
+        let table = drop_table(pt, rt);
+        let r = rates(100, 1600);
+
+        // Boxes that aren't fixed roll from the tables, which only have
+        // rares in area 2 here.
+        let plain = map_box(Vec::new());
+        let item = table.box_drop(&mut rng, &MODE, &r, &plain, 2).unwrap();
+        assert_eq!(&item.data[0..3], &[0x03, 0x0A, 0x02]);
+        assert!(table.box_drop(&mut rng, &MODE, &r, &plain, 1).is_none());
+
+        // Fixed boxes drop exactly their item, or one of its kind.
+        let fixed = map_box(vec![FIXED_BOX_PARAM, FIXED_BOX_PARAM, 0x03000100, 0]);
+        let item = table.box_drop(&mut rng, &MODE, &r, &fixed, 1).unwrap();
+        assert_eq!(&item.data[0..3], &[0x03, 0x00, 0x01]);
+        let meseta = map_box(vec![FIXED_BOX_PARAM, FIXED_BOX_PARAM, 0x04000000, 30 << 16]);
+        assert_eq!(table.box_drop(&mut rng, &MODE, &r, &meseta, 1).unwrap().meseta_amount(), 300);
+        let meseta_kind = map_box(vec![FIXED_BOX_PARAM, 0, 0x04000000, 0]);
+        for _ in 0..100 {
+            let amount = table.box_drop(&mut rng, &MODE, &r, &meseta_kind, 1).unwrap().meseta_amount();
+            assert!(amount >= 1 && amount <= 5);
+        }
+    }
+}
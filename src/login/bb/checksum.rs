@@ -0,0 +1,55 @@
+//! Client checksum enforcement. The BB client sends a checksum of its own
+//! executable and data when it reaches the character step; comparing it to a
+//! list of known clients keeps modified clients off the server.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// What to do with a client whose checksum isn't on the allow-list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumPolicy {
+    /// Don't check checksums at all.
+    AcceptAll,
+    /// Log unknown checksums, but let the client in.
+    Warn,
+    /// Disconnect the client with a message.
+    Reject
+}
+
+impl FromStr for ChecksumPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ChecksumPolicy, Self::Err> {
+        match s {
+            "accept_all" => Ok(ChecksumPolicy::AcceptAll),
+            "warn" => Ok(ChecksumPolicy::Warn),
+            "reject" => Ok(ChecksumPolicy::Reject),
+            _ => Err(format!("Unknown checksum policy {}", s))
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ChecksumFilter {
+    pub policy: ChecksumPolicy,
+    pub allowed: HashSet<u32>,
+    /// Shown to clients that are rejected.
+    pub message: String
+}
+
+impl Default for ChecksumFilter {
+    fn default() -> ChecksumFilter {
+        ChecksumFilter {
+            policy: ChecksumPolicy::AcceptAll,
+            allowed: HashSet::new(),
+            message: "Your client is not supported by this server.".to_string()
+        }
+    }
+}
+
+impl ChecksumFilter {
+    /// Whether the checksum is on the allow-list. Always true when every
+    /// client is accepted.
+    pub fn is_allowed(&self, checksum: u32) -> bool {
+        self.policy == ChecksumPolicy::AcceptAll || self.allowed.contains(&checksum)
+    }
+}
@@ -13,5 +13,9 @@ pub struct ClientState {
     pub key_config: Vec<u8>,
     pub joy_config: Vec<u8>,
     pub shortcuts: Vec<u8>,
-    pub symbol_chats: Vec<u8>
+    pub symbol_chats: Vec<u8>,
+    /// The serialized guild card file, fetched when the client asks for it.
+    pub guildcard_file: Vec<u8>,
+    /// The (account id, token) of the session registered with the shipgate.
+    pub session: Option<(u32, u32)>
 }
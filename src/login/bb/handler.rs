@@ -12,7 +12,10 @@ use psodata::leveltable::LevelTable;
 
 use time;
 
+use rand::random;
+
 use ::shipgate::client::callbacks::SgCbMgr;
+use ::shipgate::client::session::{SessionHandler, session_connect};
 use ::shipgate::msg::Message as Sgm;
 use ::shipgate::msg::{
     BbLoginChallenge,
@@ -20,12 +23,18 @@ use ::shipgate::msg::{
     ShipList as SgShipList,
     ShipListAck,
     BbGetCharacter,
-    BbPutCharacter
+    BbPutCharacter,
+    BbDeleteCharacter,
+    BbGetGuildCardFile,
+    BbChecksumRejected,
+    BbReserveName
 };
 use ::loop_handler::LoopMsg;
 
 use super::client::ClientState;
 use super::def_inventory::make_defaults;
+use super::checksum::{ChecksumFilter, ChecksumPolicy};
+use super::names::{NameRules, NameError, NAME_REFUSED_CODE};
 
 pub struct BbLoginHandler {
     sender: Sender<LoopMsg>,
@@ -34,11 +43,13 @@ pub struct BbLoginHandler {
     clients: Rc<RefCell<HashMap<usize, ClientState>>>,
     param_files: Arc<(Message, Vec<Message>)>,
     level_table: Arc<LevelTable>,
-    redir_addr: SocketAddrV4
+    redir_addr: SocketAddrV4,
+    checksums: Arc<ChecksumFilter>,
+    names: Arc<NameRules>
 }
 
 impl BbLoginHandler {
-    pub fn new(sender: Sender<LoopMsg>, redir_addr: SocketAddrV4, sg_sender: SgCbMgr<BbLoginHandler>, client_id: usize, clients: Rc<RefCell<HashMap<usize, ClientState>>>, param_files: Arc<(Message, Vec<Message>)>, level_table: Arc<LevelTable>) -> BbLoginHandler {
+    pub fn new(sender: Sender<LoopMsg>, redir_addr: SocketAddrV4, sg_sender: SgCbMgr<BbLoginHandler>, client_id: usize, clients: Rc<RefCell<HashMap<usize, ClientState>>>, param_files: Arc<(Message, Vec<Message>)>, level_table: Arc<LevelTable>, checksums: Arc<ChecksumFilter>, names: Arc<NameRules>) -> BbLoginHandler {
         BbLoginHandler {
             sender: sender,
             sg_sender: sg_sender,
@@ -46,7 +57,9 @@ impl BbLoginHandler {
             clients: clients,
             param_files: param_files,
             level_table: level_table,
-            redir_addr: redir_addr
+            redir_addr: redir_addr,
+            checksums: checksums,
+            names: names
         }
     }
 
@@ -86,76 +99,82 @@ impl BbLoginHandler {
 
                         // If the magic code in the client's security data is 0, we need to redirect to self
                         if sec_data.magic != 0xCAFEB00B {
+                            // This is a new login, so it starts a new session.
                             let mut sec_data: BbSecurityData = Default::default();
                             sec_data.magic = 0xCAFEB00B;
+                            sec_data.set_session_token(random::<u32>() | 1);
+
+                            let token = sec_data.session_token();
+                            session_connect(&mut h, sm.account_id, token, true, move|h| {
+                                {
+                                    let mut b = h.clients.borrow_mut();
+                                    let mut c = b.get_mut(&h.client_id).unwrap();
+                                    c.sec_data = sec_data.clone();
+                                    c.team_id = sm.team_id;
+                                    c.bb_guildcard = sm.guildcard_num;
+                                    c.account_id = sm.account_id;
+                                }
+
+                                let r = Message::BbSecurity(0, BbSecurity {
+                                    err_code: 0,
+                                    tag: 0x00010000,
+                                    guildcard: sm.guildcard_num,
+                                    team_id: 0xFFFFFFFF,
+                                    security_data: sec_data.clone(),
+                                    caps: 0x00000101
+                                });
+                                h.sender.send((h.client_id, r).into()).unwrap();
 
-                            // TODO find out if the account is logged in and disconnect if it is.
-
-                            {
-                                let mut b = h.clients.borrow_mut();
-                                let mut c = b.get_mut(&h.client_id).unwrap();
-                                c.sec_data = sec_data.clone();
-                                c.team_id = sm.team_id;
-                                c.bb_guildcard = sm.guildcard_num;
-                                c.account_id = sm.account_id;
-                            }
-
-                            let r = Message::BbSecurity(0, BbSecurity {
-                                err_code: 0,
-                                tag: 0x00010000,
-                                guildcard: sm.guildcard_num,
-                                team_id: 0xFFFFFFFF,
-                                security_data: sec_data,
-                                caps: 0x00000101
-                            });
-                            h.sender.send((h.client_id, r).into()).unwrap();
-
-                            let r = Message::Redirect(0, Redirect {
-                                ip: *h.redir_addr.ip(),
-                                port: h.redir_addr.port()
+                                let r = Message::Redirect(0, Redirect {
+                                    ip: *h.redir_addr.ip(),
+                                    port: h.redir_addr.port()
+                                });
+                                h.sender.send((h.client_id, r).into()).unwrap();
                             });
-                            h.sender.send((h.client_id, r).into()).unwrap();
                         } else {
                             // Client already has a session; we'll capture their security data.
-                            {
-                                let mut b = h.clients.borrow_mut();
-                                let mut c = b.get_mut(&h.client_id).unwrap();
-                                c.sec_data = sec_data.clone();
-                                c.account_id = sm.account_id;
-                                c.bb_guildcard = sm.guildcard_num;
-                                c.team_id = sm.team_id;
-                                c.options = sm.options;
-                                c.key_config = sm.key_config.clone();
-                                c.joy_config = sm.joy_config.clone();
-                                c.shortcuts = sm.shortcuts.clone();
-                                c.symbol_chats = sm.symbol_chats.clone();
-                            }
-
-                            let r = Message::BbSecurity(0, BbSecurity {
-                                err_code: 0,
-                                tag: 0x00010000,
-                                guildcard: sm.guildcard_num,
-                                team_id: 0xFFFFFFFF,
-                                security_data: sec_data.clone(),
-                                caps: 0x00000101
-                            });
-                            h.sender.send((h.client_id, r).into()).unwrap();
-
-                            // If they've selected a character, they want the ship list now.
-                            if sec_data.sel_char != 0 {
-                                let now = time::now_utc();
-                                let r = Message::Timestamp(0, Timestamp {
-                                    year: now.tm_year as u16,
-                                    month: now.tm_mon as u8,
-                                    day: now.tm_mday as u8,
-                                    hour: now.tm_hour as u8,
-                                    minute: now.tm_min as u8,
-                                    second: now.tm_sec as u8,
-                                    msec: 0
+                            let token = sec_data.session_token();
+                            session_connect(&mut h, sm.account_id, token, false, move|mut h| {
+                                {
+                                    let mut b = h.clients.borrow_mut();
+                                    let mut c = b.get_mut(&h.client_id).unwrap();
+                                    c.sec_data = sec_data.clone();
+                                    c.account_id = sm.account_id;
+                                    c.bb_guildcard = sm.guildcard_num;
+                                    c.team_id = sm.team_id;
+                                    c.options = sm.options;
+                                    c.key_config = sm.key_config.clone();
+                                    c.joy_config = sm.joy_config.clone();
+                                    c.shortcuts = sm.shortcuts.clone();
+                                    c.symbol_chats = sm.symbol_chats.clone();
+                                }
+
+                                let r = Message::BbSecurity(0, BbSecurity {
+                                    err_code: 0,
+                                    tag: 0x00010000,
+                                    guildcard: sm.guildcard_num,
+                                    team_id: 0xFFFFFFFF,
+                                    security_data: sec_data.clone(),
+                                    caps: 0x00000101
                                 });
                                 h.sender.send((h.client_id, r).into()).unwrap();
-                                h.sg_sender.request(h.client_id, SgShipList, move|mut h, m| h.sg_shiplist_ack(m)).unwrap();
-                            }
+
+                                // If they've selected a character, they want the ship list now.
+                                if sec_data.sel_char != 0 {
+                                    let now = time::now_utc();
+                                    let r = Message::Timestamp(0, Timestamp {
+                                        year: now.tm_year as u16,
+                                        month: now.tm_mon as u8,
+                                        day: now.tm_mday as u8,
+                                        hour: now.tm_hour as u8,
+                                        minute: now.tm_min as u8,
+                                        second: now.tm_sec as u8,
+                                        msec: 0
+                                    });
+                                    h.sender.send((h.client_id, r).into()).unwrap();
+                                    h.sg_sender.request(h.client_id, SgShipList, move|mut h, m| h.sg_shiplist_ack(m)).unwrap();
+                                }
+                            });
                         }
                     }
                 }).unwrap();
@@ -208,33 +227,76 @@ impl BbLoginHandler {
 
     pub fn bb_checksum(&mut self, m: BbChecksum) {
         info!("Client {}'s checksum is {:x}", self.client_id, m.0);
+        if !self.checksums.is_allowed(m.0) {
+            let account_id = {
+                let b = self.clients.borrow();
+                b.get(&self.client_id).map(|c| c.account_id).unwrap_or(0)
+            };
+            match self.checksums.policy {
+                ChecksumPolicy::Warn => {
+                    warn!("Client {} (account {}) has unknown checksum {:08x}", self.client_id, account_id, m.0);
+                },
+                _ => {
+                    info!("Rejecting client {} (account {}) with unknown checksum {:08x}", self.client_id, account_id, m.0);
+                    self.sg_sender.send(BbChecksumRejected { account_id: account_id, checksum: m.0 }).unwrap();
+                    let r = Message::LargeMsg(0, LargeMsg(self.checksums.message.clone()));
+                    self.sender.send((self.client_id, r).into()).unwrap();
+                    self.sender.send(LoopMsg::DropClient(self.client_id)).unwrap();
+                    return
+                }
+            }
+        }
         let r = Message::BbChecksumAck(0, BbChecksumAck(true));
         self.sender.send((self.client_id, r).into()).unwrap();
     }
 
     pub fn bb_guildcard_req(&mut self) {
-        use crc::crc32::checksum_ieee as checksum;
-
-        // TODO actual guildcard data
-        let checksum = checksum(&vec![0u8; 54672]);
-        let r = Message::BbGuildCardHdr(0, BbGuildCardHdr {
-            one: 1,
-            len: 54672,
-            checksum: checksum
-        });
-        self.sender.send((self.client_id, r).into()).unwrap();
+        let account_id = {
+            let b = self.clients.borrow();
+            b.get(&self.client_id).unwrap().account_id
+        };
+        self.sg_sender.request(self.client_id, BbGetGuildCardFile { account_id: account_id }, move|h, m| {
+            if let Sgm::BbGetGuildCardFileAck(_, m) = m {
+                use crc::crc32::checksum_ieee as checksum;
+
+                if m.status != 0 {
+                    error!("Couldn't get the guild card file for account {}", account_id);
+                }
+                let data = m.file.to_bytes();
+                let r = Message::BbGuildCardHdr(0, BbGuildCardHdr {
+                    one: 1,
+                    len: data.len() as u32,
+                    checksum: checksum(&data)
+                });
+                {
+                    let mut b = h.clients.borrow_mut();
+                    let mut c = b.get_mut(&h.client_id).unwrap();
+                    c.guildcard_file = data;
+                }
+                h.sender.send((h.client_id, r).into()).unwrap();
+            }
+        }).unwrap();
     }
 
     pub fn bb_guildcard_chunk_req(&mut self, m: BbGuildCardChunkReq) {
         let BbGuildCardChunkReq(_, chunk, cont) = m;
         if cont {
-            let size_remaining: usize = 54672 - (chunk as usize * 0x6800);
-            let size: usize = if size_remaining < 0x6800 { size_remaining } else { 0x6800 };
-            debug!("Sending guild card chunk {} of size {}", chunk, size);
+            let data = {
+                let b = self.clients.borrow();
+                let c = b.get(&self.client_id).unwrap();
+                let start = chunk as usize * 0x6800;
+                if start >= c.guildcard_file.len() {
+                    warn!("Client {} requested guild card chunk {} past the end of the file", self.client_id, chunk);
+                    return
+                }
+                let end = ::std::cmp::min(start + 0x6800, c.guildcard_file.len());
+                c.guildcard_file[start..end].to_vec()
+            };
+            debug!("Sending guild card chunk {} of size {}", chunk, data.len());
             let r = Message::BbGuildCardChunk(0, BbGuildCardChunk {
                 unk: 0,
                 chunk: chunk,
-                data: vec![0u8; size]
+                data: data
             });
             self.sender.send((self.client_id, r).into()).unwrap();
         }
@@ -335,6 +397,45 @@ impl BbLoginHandler {
     pub fn bb_char_info(&mut self, m: BbCharInfo) {
         let BbCharInfo(slot, chardata) = m;
 
+        if chardata.guildcard.len() > 0 {
+            // Check the name of a new character before anything is saved.
+            if let Err(e) = self.names.check(&chardata.name) {
+                info!("Client {} tried to create a character named {:?}: {:?}", self.client_id, chardata.name, e);
+                self.refuse_char(slot);
+                return
+            }
+
+            if self.names.unique {
+                // The shipgate checks and reserves the name in one step, so
+                // two clients can't both be given it.
+                let account_id = {
+                    let b = self.clients.borrow();
+                    b.get(&self.client_id).unwrap().account_id
+                };
+                let sgm = BbReserveName { account_id: account_id, slot: slot as u8, name: chardata.name.clone() };
+                self.sg_sender.request(self.client_id, sgm, move|mut h, m| {
+                    if let Sgm::BbReserveNameAck(_, a) = m {
+                        if a.status != 0 || a.taken != 0 {
+                            info!("Client {} tried to create a character named {:?}: {:?}", h.client_id, chardata.name, NameError::Taken);
+                            h.refuse_char(slot);
+                            return
+                        }
+                        h.save_char_info(slot, chardata.clone());
+                    }
+                }).unwrap();
+                return
+            }
+        }
+
+        self.save_char_info(slot, chardata);
+    }
+
+    fn refuse_char(&mut self, slot: u32) {
+        let r = Message::BbCharAck(0, BbCharAck { slot: slot, code: NAME_REFUSED_CODE });
+        self.sender.send((self.client_id, r).into()).unwrap();
+    }
+
+    fn save_char_info(&mut self, slot: u32, chardata: BbMiniCharData) {
         let sec_data;
         let bb_guildcard;
         let account_id;
@@ -384,6 +485,14 @@ impl BbLoginHandler {
             // We don't need to set the account global data here because we aren't
             // going to save it in the shipgate request.
 
+            // Creating a character over an existing one deletes it. The
+            // shipgate handles requests in order, so this happens before the
+            // new character is saved.
+            self.sg_sender.send(BbDeleteCharacter {
+                account_id: account_id,
+                slot: slot as u8
+            }).unwrap();
+
             self.sg_sender.send(BbPutCharacter {
                 account_id: account_id,
                 slot: slot as u8,
@@ -442,3 +551,33 @@ impl BbLoginHandler {
         }
     }
 }
+
+impl SessionHandler for BbLoginHandler {
+    fn sg_sender(&mut self) -> &mut SgCbMgr<BbLoginHandler> {
+        &mut self.sg_sender
+    }
+
+    fn client_id(&self) -> usize {
+        self.client_id
+    }
+
+    fn set_session(&mut self, session: (u32, u32)) -> bool {
+        match self.clients.borrow_mut().get_mut(&self.client_id) {
+            Some(c) => { c.session = Some(session); true },
+            None => false
+        }
+    }
+
+    fn refuse_session(&mut self, status: u32) {
+        let r = Message::BbSecurity(0, BbSecurity {
+            err_code: status,
+            tag: 0x00010000,
+            guildcard: 0,
+            team_id: 0,
+            security_data: Default::default(),
+            caps: 0x00000101
+        });
+        self.sender.send((self.client_id, r).into()).unwrap();
+        self.sender.send(LoopMsg::DropClient(self.client_id)).unwrap();
+    }
+}
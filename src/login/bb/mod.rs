@@ -29,13 +29,19 @@ use ::services::ServiceType;
 
 use ::shipgate::client::SgSender;
 use ::shipgate::client::callbacks::SgCbMgr;
+use ::shipgate::client::session::kick_session;
+use ::shipgate::msg::{Message as Sgm, BbSessionDisconnect};
 
 pub mod client;
 pub mod handler;
 pub mod def_inventory;
+pub mod checksum;
+pub mod names;
 
 use self::client::ClientState;
 use self::handler::BbLoginHandler;
+use self::checksum::ChecksumFilter;
+use self::names::NameRules;
 
 pub struct BbLoginService {
     receiver: Receiver<ServiceMsg>,
@@ -44,11 +50,13 @@ pub struct BbLoginService {
     clients: Rc<RefCell<HashMap<usize, ClientState>>>,
     param_files: Arc<(Message, Vec<Message>)>,
     level_table: Arc<LevelTable>,
-    redir_addr: SocketAddrV4
+    redir_addr: SocketAddrV4,
+    checksums: Arc<ChecksumFilter>,
+    names: Arc<NameRules>
 }
 
 impl BbLoginService {
-    pub fn spawn(bind: &SocketAddr, redir_addr: SocketAddrV4, sender: Sender<LoopMsg>, key_table: Arc<Vec<u32>>, sg_sender: &SgSender, param_files: Arc<(Message, Vec<Message>)>, level_table: Arc<LevelTable>) -> Service {
+    pub fn spawn(bind: &SocketAddr, redir_addr: SocketAddrV4, sender: Sender<LoopMsg>, key_table: Arc<Vec<u32>>, sg_sender: &SgSender, param_files: Arc<(Message, Vec<Message>)>, level_table: Arc<LevelTable>, checksums: ChecksumFilter, names: NameRules) -> Service {
         let (tx, rx) = channel();
 
         let listener = TcpListener::bind(bind).expect("Couldn't create tcplistener");
@@ -63,7 +71,9 @@ impl BbLoginService {
                 clients: Default::default(),
                 param_files: param_files,
                 level_table: level_table,
-                redir_addr: redir_addr
+                redir_addr: redir_addr,
+                checksums: Arc::new(checksums),
+                names: Arc::new(names)
             };
             d.run()
         });
@@ -79,7 +89,9 @@ impl BbLoginService {
             client_id,
             self.clients.clone(),
             self.param_files.clone(),
-            self.level_table.clone()
+            self.level_table.clone(),
+            self.checksums.clone(),
+            self.names.clone()
         )
     }
 
@@ -107,9 +119,12 @@ impl BbLoginService {
                 ServiceMsg::ClientDisconnected(id) => {
                     info!("Client {} disconnected", id);
 
-                    {
+                    let c = {
                         let mut b = self.clients.borrow_mut();
-                        b.remove(&id);
+                        b.remove(&id)
+                    };
+                    if let Some((account_id, token)) = c.and_then(|c| c.session) {
+                        self.sg_sender.send(BbSessionDisconnect { account_id: account_id, token: token }).unwrap();
                     }
                 },
                 ServiceMsg::ClientSaid(id, NetMsg::Bb(m)) => {
@@ -130,6 +145,12 @@ impl BbLoginService {
                         }
                     }
                 },
+                ServiceMsg::ShipGateMsg(Sgm::BbSessionKick(_, k)) => {
+                    let b = self.clients.borrow();
+                    kick_session(&self.sender, k, b.iter().map(|(&id, c)| (id, c.session)));
+                },
+                ServiceMsg::ShipGateMsg(Sgm::SetRates(..)) => (),
+                ServiceMsg::ShipGateMsg(Sgm::BbTeamChanged(..)) => (),
                 ServiceMsg::ShipGateMsg(m) => {
                     let req = m.get_response_key();
                     debug!("Shipgate Request {}: Response received", req);
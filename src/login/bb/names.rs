@@ -0,0 +1,97 @@
+//! Character name validation for new characters.
+//!
+//! BB names start with a language tag ("\tE" or "\tJ") that the client adds on
+//! its own; the rules here apply to the rest of the name.
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use psodata::chara::strip_name_tag;
+
+/// Longest name that fits the character data, less the language tag.
+pub const MAX_NAME_LEN: usize = 10;
+
+/// Error code sent in BbCharAck when a name is refused.
+pub const NAME_REFUSED_CODE: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameError {
+    TooShort,
+    TooLong,
+    BadCharacter(char),
+    Reserved(String),
+    Taken
+}
+
+#[derive(Clone, Debug)]
+pub struct NameRules {
+    pub min_len: usize,
+    pub max_len: usize,
+    /// Lowercased words that may not appear anywhere in a name.
+    pub reserved: Vec<String>,
+    /// Whether names must be unique across the whole network.
+    pub unique: bool
+}
+
+impl Default for NameRules {
+    fn default() -> NameRules {
+        NameRules {
+            min_len: 1,
+            max_len: MAX_NAME_LEN,
+            reserved: Vec::new(),
+            unique: false
+        }
+    }
+}
+
+fn allowed_char(c: char) -> bool {
+    let within = |lo: char, hi: char| c >= lo && c <= hi;
+    // Printable ASCII
+    within(' ', '~')
+        // Latin-1 letters, less the multiplication and division signs
+        || (within('\u{00C0}', '\u{00FF}') && c != '\u{00D7}' && c != '\u{00F7}')
+        // Japanese punctuation, hiragana and katakana
+        || within('\u{3000}', '\u{30FF}')
+        // CJK ideographs
+        || within('\u{4E00}', '\u{9FFF}')
+        // Full-width forms and half-width katakana
+        || within('\u{FF01}', '\u{FF9F}')
+}
+
+impl NameRules {
+    /// Add the words in a file, one per line, to the reserved list. Blank lines
+    /// and lines starting with # are skipped.
+    pub fn load_reserved(&mut self, path: &str) -> io::Result<()> {
+        let mut s = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut s));
+        for l in s.lines() {
+            let l = l.trim();
+            if l.len() > 0 && !l.starts_with('#') {
+                self.reserved.push(l.to_lowercase());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a name against everything but uniqueness, which needs the
+    /// shipgate.
+    pub fn check(&self, name: &str) -> Result<(), NameError> {
+        let name = strip_name_tag(name);
+        let len = name.chars().count();
+        if len < self.min_len || name.trim().len() == 0 {
+            return Err(NameError::TooShort)
+        }
+        if len > self.max_len {
+            return Err(NameError::TooLong)
+        }
+        if let Some(c) = name.chars().find(|&c| !allowed_char(c)) {
+            return Err(NameError::BadCharacter(c))
+        }
+        let lower = name.to_lowercase();
+        if let Some(w) = self.reserved.iter().find(|w| lower.contains(&w[..])) {
+            return Err(NameError::Reserved(w.clone()))
+        }
+        Ok(())
+    }
+}
@@ -20,6 +20,7 @@ extern crate staticvec;
 extern crate env_logger;
 extern crate toml;
 extern crate time;
+extern crate libc;
 
 pub mod patch;
 pub mod data;
@@ -36,6 +37,8 @@ pub mod services;
 pub mod config;
 pub mod maps;
 pub mod droptables;
+pub mod rates;
+pub mod quests;
 
 use std::io::Cursor;
 
@@ -48,6 +51,8 @@ use mio::EventLoop;
 
 use psodata::battleparam::BattleParamTables;
 use psodata::leveltable::LevelTable;
+use psodata::itempmt::ItemPMT;
+use psodata::itemmagedit::ItemMagEdit;
 use psodata::prs::decompress_prs;
 
 use psoserial::Serial;
@@ -55,6 +60,7 @@ use psoserial::Serial;
 use ::loop_handler::LoopHandler;
 use ::patch::PatchService;
 use ::data::DataService;
+use ::data::manifest::install_rescan_signal;
 use ::login::bb::BbLoginService;
 use ::login::paramfiles::load_paramfiles_msgs;
 use ::shipgate::client::ShipGateClient;
@@ -64,14 +70,93 @@ use ::shipgate::ShipGateService;
 use ::services::Service;
 use ::config::Config;
 use ::config::ServiceConf;
+use ::config::DbConf;
 use ::droptables::DropTable;
 
 use std::fs::File;
 use std::sync::Arc;
+use std::time::Duration;
 
 use ::game::Version;
 use ::bb::read_key_table;
 use ::maps::Areas;
+use ::quests::Quests;
+
+/// The database and recovery period of the shipgate in the config.
+fn shipgate_db(config: &Config) -> Option<(DbConf, u32)> {
+    config.services.iter().filter_map(|c| match c {
+        &ServiceConf::ShipGate { ref db, recover_days, .. } => Some((db.clone(), recover_days)),
+        _ => None
+    }).next()
+}
+
+/// Delete a character directly in the shipgate's database.
+fn delete_char(config: &Config, username: &str, slot: u8) {
+    let (db, recover_days) = match shipgate_db(config) {
+        Some(v) => v,
+        None => {
+            println!("There is no shipgate service in this config.");
+            return
+        }
+    };
+
+    let pool = db.make_pool().expect("Couldn't make database pool.");
+    let conn = pool.get_connection().expect("Couldn't get database connection.");
+    let handle = conn.lock().unwrap();
+    let account = match handle.get_account_by_username(username) {
+        Ok(Some(a)) => a,
+        Ok(None) => {
+            println!("No account named {}.", username);
+            return
+        },
+        Err(e) => panic!("Database error getting account: {:?}", e)
+    };
+    match handle.fetch_bb_character(account.id().unwrap(), slot) {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            println!("{} has no character in slot {}.", username, slot);
+            return
+        },
+        Err(e) => panic!("Database error getting character: {:?}", e)
+    }
+    match handle.delete_bb_character(account.id().unwrap(), slot, recover_days > 0) {
+        Ok(()) if recover_days > 0 => println!("Deleted {}'s character in slot {}. It can be restored for {} days.", username, slot, recover_days),
+        Ok(()) => println!("Deleted {}'s character in slot {}.", username, slot),
+        Err(e) => panic!("Database error deleting character: {:?}", e)
+    }
+}
+
+/// Restore a deleted character directly in the shipgate's database.
+fn restore_char(config: &Config, username: &str, slot: u8) {
+    let (db, recover_days) = match shipgate_db(config) {
+        Some(v) => v,
+        None => {
+            println!("There is no shipgate service in this config.");
+            return
+        }
+    };
+    if recover_days == 0 {
+        println!("Deleted characters are not kept; set recover_days on the shipgate.");
+        return
+    }
+
+    let pool = db.make_pool().expect("Couldn't make database pool.");
+    let conn = pool.get_connection().expect("Couldn't get database connection.");
+    let handle = conn.lock().unwrap();
+    let account = match handle.get_account_by_username(username) {
+        Ok(Some(a)) => a,
+        Ok(None) => {
+            println!("No account named {}.", username);
+            return
+        },
+        Err(e) => panic!("Database error getting account: {:?}", e)
+    };
+    match handle.restore_bb_character(account.id().unwrap(), slot, recover_days as u64 * 86400) {
+        Ok(true) => println!("Restored {}'s character in slot {}.", username, slot),
+        Ok(false) => println!("{} has no character deleted from slot {} in the last {} days, or the slot is in use.", username, slot, recover_days),
+        Err(e) => panic!("Database error restoring character: {:?}", e)
+    }
+}
 
 fn main() {
     env_logger::init().expect("env_logger failed to initialize");
@@ -94,6 +179,16 @@ fn main() {
         config = Config::from_toml_string(&config_string).expect("Failed to parse TOML");
     }
 
+    if args.cmd_delete_char {
+        delete_char(&config, &args.arg_username, args.arg_slot);
+        return
+    }
+
+    if args.cmd_restore_char {
+        restore_char(&config, &args.arg_username, args.arg_slot);
+        return
+    }
+
     // Load the bb key table.
     let bb_keytable;
     {
@@ -130,6 +225,28 @@ fn main() {
     }
     info!("Loaded BB PlyLevelTbl stats information from path: {}/param/PlyLevelTbl.prs", config.data_path);
 
+    // Load ItemPMT.prs
+    let item_pmt;
+    {
+        let mut f = File::open(format!("{}/param/ItemPMT.prs", config.data_path)).expect("Unable to open ItemPMT.prs");
+        let decomp = decompress_prs(&mut f).expect("Unable to decompress ItemPMT.prs");
+        item_pmt = Arc::new(ItemPMT::load_from_buffer(&decomp).expect("Unable to parse decompressed ItemPMT.prs"));
+    }
+    info!("Loaded BB ItemPMT item parameters from path: {}/param/ItemPMT.prs", config.data_path);
+
+    // Load ItemMagEdit.prs
+    let mag_edit;
+    {
+        let mut f = File::open(format!("{}/param/ItemMagEdit.prs", config.data_path)).expect("Unable to open ItemMagEdit.prs");
+        let decomp = decompress_prs(&mut f).expect("Unable to decompress ItemMagEdit.prs");
+        mag_edit = Arc::new(ItemMagEdit::load_from_buffer(&decomp).expect("Unable to parse decompressed ItemMagEdit.prs"));
+    }
+    info!("Loaded BB ItemMagEdit mag evolutions from path: {}/param/ItemMagEdit.prs", config.data_path);
+
+    // Load quests
+    let quests = Arc::new(Quests::load_from_dir(&format!("{}/quests", config.data_path)).expect("Unable to load quests"));
+    info!("Loaded {} quests from path: {}/quests", quests.quests.len(), config.data_path);
+
     // Load ItemPT/RT.gsl
     let drop_table = Arc::new(DropTable::load_from_file(
         &format!("{}/param/ItemPT.gsl", config.data_path),
@@ -144,9 +261,9 @@ fn main() {
     let mut sg: Option<Service> = None;
     if let Some(c) = config.services.iter().find(|c| if let _e @ &&ServiceConf::ShipGate {..} = c { true } else { false } ) {
         match c {
-            &ServiceConf::ShipGate { ref bind, ref password, ref db } => {
+            &ServiceConf::ShipGate { ref bind, ref password, ref db, duplicate_login, recover_days, ref rates } => {
                 let pool = Arc::new(db.make_pool().expect("Couldn't make database pool for ShipGate."));
-                sg = Some(ShipGateService::spawn(bind, event_loop.channel(), password, pool));
+                sg = Some(ShipGateService::spawn(bind, event_loop.channel(), password, pool, duplicate_login, recover_days, rates.clone()));
             },
             _ => unreachable!()
         }
@@ -158,20 +275,33 @@ fn main() {
     let mut services = Vec::new();
     for s in config.services.iter() {
         match s {
-            &ServiceConf::Patch { ref bind, ref v4_servers, ref motd, random_balance } => {
+            &ServiceConf::Patch { ref bind, ref v4_servers, ref v6_servers, ref motd, balance, probe_interval, probe_failures } => {
                 info!("Patch service at {:?}", bind);
+                let probe_interval = if probe_interval > 0 { Some(Duration::from_secs(probe_interval)) } else { None };
                 services.push(PatchService::spawn(
                     bind,
                     event_loop.channel(),
                     v4_servers.clone(),
+                    v6_servers.clone(),
                     motd.clone(),
-                    random_balance));
+                    balance,
+                    probe_interval,
+                    probe_failures));
             },
-            &ServiceConf::Data { ref bind, .. } => {
+            &ServiceConf::Data { ref bind, ref path, rescan_interval, .. } => {
                 info!("Data service at {:?}", bind);
-                services.push(DataService::spawn(bind, event_loop.channel()));
+                let path = path.clone().unwrap_or(format!("{}/patch", config.data_path));
+                let rescan_interval = if rescan_interval > 0 { Some(Duration::from_secs(rescan_interval)) } else { None };
+                install_rescan_signal();
+                match DataService::spawn(bind, event_loop.channel(), &path, rescan_interval) {
+                    Ok(s) => services.push(s),
+                    Err(e) => {
+                        error!("Unable to build the patch manifest for {}: {}", path, e);
+                        return
+                    }
+                }
             },
-            &ServiceConf::Login { ref bind, version, addr, .. } => {
+            &ServiceConf::Login { ref bind, version, addr, ref checksums, ref names } => {
                 info!("Login service at {:?}", bind);
                 match version {
                     Version::BlueBurst => {
@@ -182,7 +312,9 @@ fn main() {
                             bb_keytable.clone(),
                             &sg_sender,
                             param_files.clone(),
-                            level_table.clone()))
+                            level_table.clone(),
+                            checksums.clone(),
+                            names.clone()))
                     },
                     _ => unimplemented!()
                 }
@@ -197,7 +329,7 @@ fn main() {
                     blocks.clone(),
                     my_ipv4));
             },
-            &ServiceConf::Block { ref bind, num, event, .. } => {
+            &ServiceConf::Block { ref bind, num, event, ref rare_rates, ref rates } => {
                 info!("Block service at {:?}", bind);
                 services.push(BlockService::spawn(
                     bind,
@@ -210,7 +342,12 @@ fn main() {
                     online_maps.clone(),
                     offline_maps.clone(),
                     level_table.clone(),
-                    drop_table.clone()));
+                    drop_table.clone(),
+                    item_pmt.clone(),
+                    mag_edit.clone(),
+                    quests.clone(),
+                    rare_rates.clone(),
+                    rates.clone()));
             },
             &ServiceConf::ShipGate { .. } => {
                 match sg {
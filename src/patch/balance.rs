@@ -0,0 +1,208 @@
+//! Load balancing across data servers, with periodic health probes.
+//!
+//! The patch service never sees the data servers' own connection counts, so
+//! "least connections" counts the redirects handed to each server within a
+//! recent window. Patch sessions are short, so this tracks real load closely
+//! enough.
+
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::cmp::max;
+
+use rand::random;
+
+/// How long a redirect counts towards a server's load for least-connections.
+pub const SESSION_WINDOW: u64 = 120;
+
+/// How the next data server is chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceMode {
+    RoundRobin,
+    Random,
+    LeastConnections,
+    Weighted
+}
+
+impl FromStr for BalanceMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<BalanceMode, Self::Err> {
+        use self::BalanceMode::*;
+        match s {
+            "round_robin" => Ok(RoundRobin),
+            "random" => Ok(Random),
+            "least_connections" => Ok(LeastConnections),
+            "weighted" => Ok(Weighted),
+            _ => Err(format!("Unknown balance mode {}", s))
+        }
+    }
+}
+
+/// Whether each server in a list passed its last probes. Shared between the
+/// balancer and the prober thread.
+#[derive(Debug)]
+pub struct Health {
+    up: Mutex<Vec<bool>>
+}
+
+impl Health {
+    pub fn new(len: usize) -> Health {
+        Health { up: Mutex::new(vec![true; len]) }
+    }
+
+    pub fn is_up(&self, idx: usize) -> bool {
+        self.up.lock().unwrap().get(idx).cloned().unwrap_or(false)
+    }
+
+    fn set(&self, idx: usize, up: bool) {
+        if let Some(u) = self.up.lock().unwrap().get_mut(idx) {
+            *u = up;
+        }
+    }
+}
+
+/// Chooses data servers from one address family's list.
+pub struct Balancer<A: Copy> {
+    servers: Vec<A>,
+    weights: Vec<u32>,
+    mode: BalanceMode,
+    health: Arc<Health>,
+    next: usize,
+    /// Smooth weighted round robin state.
+    current_weights: Vec<i64>,
+    /// Recent redirect times per server, for least-connections.
+    recent: Vec<VecDeque<Instant>>
+}
+
+impl<A: Copy> Balancer<A> {
+    pub fn new(servers: Vec<(A, u32)>, mode: BalanceMode) -> Balancer<A> {
+        let len = servers.len();
+        Balancer {
+            servers: servers.iter().map(|&(a, _)| a).collect(),
+            weights: servers.iter().map(|&(_, w)| w).collect(),
+            mode: mode,
+            health: Arc::new(Health::new(len)),
+            next: 0,
+            current_weights: vec![0; len],
+            recent: vec![VecDeque::new(); len]
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    pub fn servers(&self) -> &[A] {
+        &self.servers
+    }
+
+    pub fn health(&self) -> Arc<Health> {
+        self.health.clone()
+    }
+
+    /// Pick the next server. Servers that are down are skipped, unless every
+    /// server is down, in which case they are all considered.
+    pub fn pick(&mut self) -> Option<A> {
+        if self.servers.len() == 0 {
+            return None
+        }
+        let mut candidates: Vec<usize> = (0..self.servers.len()).filter(|&i| self.health.is_up(i)).collect();
+        if candidates.len() == 0 {
+            warn!("Every data server failed its health checks; redirecting anyway");
+            candidates = (0..self.servers.len()).collect();
+        }
+
+        let idx = match self.mode {
+            BalanceMode::RoundRobin => {
+                let len = self.servers.len();
+                let mut i = self.next % len;
+                while !candidates.contains(&i) {
+                    i = (i + 1) % len;
+                }
+                self.next = (i + 1) % len;
+                i
+            },
+            BalanceMode::Random => {
+                candidates[random::<usize>() % candidates.len()]
+            },
+            BalanceMode::LeastConnections => {
+                let now = Instant::now();
+                let window = Duration::from_secs(SESSION_WINDOW);
+                for r in self.recent.iter_mut() {
+                    while r.front().map(|&t| now.duration_since(t) > window).unwrap_or(false) {
+                        r.pop_front();
+                    }
+                }
+                let recent = &self.recent;
+                let weights = &self.weights;
+                // Compare load relative to weight, so a weight 2 server takes
+                // twice the sessions of a weight 1 server.
+                *candidates.iter().min_by_key(|&&i| (recent[i].len() as u64 * 1000) / max(weights[i], 1) as u64).unwrap()
+            },
+            BalanceMode::Weighted => {
+                let total: i64 = candidates.iter().map(|&i| self.weights[i] as i64).sum();
+                for &i in candidates.iter() {
+                    self.current_weights[i] += self.weights[i] as i64;
+                }
+                let best = *candidates.iter().max_by_key(|&&i| self.current_weights[i]).unwrap();
+                self.current_weights[best] -= total;
+                best
+            }
+        };
+
+        self.recent[idx].push_back(Instant::now());
+        Some(self.servers[idx])
+    }
+}
+
+/// Try to connect to a data server and read the start of its welcome message.
+/// Each step gives up after `timeout`, so a server that doesn't answer can't
+/// hold up the checks of the others.
+pub fn probe(addr: &SocketAddr, timeout: Duration) -> bool {
+    let mut stream = match TcpStream::connect_timeout(addr, timeout) {
+        Ok(s) => s,
+        Err(_) => return false
+    };
+    if stream.set_read_timeout(Some(timeout)).is_err() || stream.set_write_timeout(Some(timeout)).is_err() {
+        return false
+    }
+    let mut hdr = [0u8; 4];
+    match stream.read(&mut hdr) {
+        // Patch header: u16 size, then u16 type 0x02 (Welcome)
+        Ok(4) => hdr[2] == 0x02 && hdr[3] == 0x00,
+        _ => false
+    }
+}
+
+/// Spawn a thread that probes every server in `targets` each `interval`. A
+/// server is taken out of rotation after `max_failures` failed probes in a
+/// row, and put back after one success.
+pub fn spawn_prober(targets: Vec<(Vec<SocketAddr>, Arc<Health>)>, interval: Duration, max_failures: u32) {
+    thread::spawn(move|| {
+        let mut failures: Vec<Vec<u32>> = targets.iter().map(|&(ref a, _)| vec![0; a.len()]).collect();
+        loop {
+            for (t, &(ref addrs, ref health)) in targets.iter().enumerate() {
+                for (i, addr) in addrs.iter().enumerate() {
+                    if probe(addr, interval) {
+                        if failures[t][i] >= max_failures {
+                            info!("Data server {} passed its health check and is back in rotation", addr);
+                        }
+                        failures[t][i] = 0;
+                        health.set(i, true);
+                    } else {
+                        failures[t][i] += 1;
+                        if failures[t][i] == max_failures {
+                            warn!("Data server {} failed {} health checks and was taken out of rotation", addr, max_failures);
+                            health.set(i, false);
+                        }
+                    }
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+}
@@ -7,8 +7,10 @@ use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
 
 use std::thread;
+use std::collections::HashMap;
+use std::time::Duration;
 
-use std::net::{SocketAddr, SocketAddrV4};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
 
 use mio::tcp::TcpListener;
 use mio::Sender;
@@ -19,33 +21,55 @@ use ::services::message::NetMsg;
 
 use ::services::ServiceType;
 
-use rand::random;
+pub mod balance;
+
+use self::balance::{Balancer, BalanceMode};
 
 pub struct PatchService {
     receiver: Receiver<ServiceMsg>,
     sender: Sender<LoopMsg>,
-    v4_servers: Vec<SocketAddrV4>,
-    next: usize,
-    motd: String,
-    random_data: bool
+    v4_servers: Balancer<SocketAddrV4>,
+    v6_servers: Balancer<SocketAddrV6>,
+    clients: HashMap<usize, SocketAddr>,
+    motd: String
 }
 
 impl PatchService {
-    pub fn spawn(bind: &SocketAddr, sender: Sender<LoopMsg>, v4_servers: Vec<SocketAddrV4>, motd: String, random_data: bool) -> Service {
+    /// Spawn a patch service. Each data server is paired with its weight. If
+    /// `probe_interval` is set, data servers are health checked that often and
+    /// dropped from rotation after `probe_failures` failures in a row.
+    pub fn spawn(bind: &SocketAddr,
+                 sender: Sender<LoopMsg>,
+                 v4_servers: Vec<(SocketAddrV4, u32)>,
+                 v6_servers: Vec<(SocketAddrV6, u32)>,
+                 motd: String,
+                 balance: BalanceMode,
+                 probe_interval: Option<Duration>,
+                 probe_failures: u32) -> Service {
         let (tx, rx) = channel();
 
         let listener = TcpListener::bind(bind).expect("Couldn't create tcplistener");
 
-        if v4_servers.len() == 0 { panic!("no data redirect servers specified") }
+        if v4_servers.len() == 0 && v6_servers.len() == 0 { panic!("no data redirect servers specified") }
+
+        let v4_servers = Balancer::new(v4_servers, balance);
+        let v6_servers = Balancer::new(v6_servers, balance);
+
+        if let Some(interval) = probe_interval {
+            balance::spawn_prober(vec![
+                (v4_servers.servers().iter().map(|&a| SocketAddr::V4(a)).collect(), v4_servers.health()),
+                (v6_servers.servers().iter().map(|&a| SocketAddr::V6(a)).collect(), v6_servers.health())
+            ], interval, probe_failures);
+        }
 
         thread::spawn(move|| {
             let p = PatchService {
                 receiver: rx,
                 sender: sender,
                 v4_servers: v4_servers,
-                next: 0,
-                motd: motd,
-                random_data: random_data
+                v6_servers: v6_servers,
+                clients: HashMap::new(),
+                motd: motd
             };
             p.run()
         });
@@ -53,19 +77,35 @@ impl PatchService {
         Service::new(listener, tx, ServiceType::Patch)
     }
 
+    /// Pick a data server of the same address family the client connected
+    /// with. IPv4-mapped IPv6 clients are treated as IPv4.
+    fn redirect_for(&mut self, id: usize) -> Option<Message> {
+        let is_v6 = match self.clients.get(&id) {
+            Some(&SocketAddr::V6(ref a)) => a.ip().to_ipv4().is_none() || a.ip().segments()[5] != 0xFFFF,
+            _ => false
+        };
+        if is_v6 && self.v6_servers.len() > 0 {
+            self.v6_servers.pick().map(|a| Message::Redirect6(Some(Redirect6(a))))
+        } else {
+            self.v4_servers.pick().map(|a| Message::Redirect(Some(Redirect(a))))
+        }
+    }
+
     pub fn run(mut self) {
         info!("Patch service running");
 
         // This service only responds to events; it does not run its own bookkeeping.
-        for msg in self.receiver.iter() {
+        while let Ok(msg) = self.receiver.recv() {
             match msg {
-                ServiceMsg::ClientConnected((_addr, id)) => {
+                ServiceMsg::ClientConnected((addr, id)) => {
                     info!("Client {} connected to patch service", id);
+                    self.clients.insert(id, addr);
                     let w = Message::Welcome(Some(Welcome { server_vector: 0, client_vector: 0 }));
                     self.sender.send(LoopMsg::Client(id, w.into())).unwrap();
                 },
                 ServiceMsg::ClientDisconnected(id) => {
-                    info!("Client {} disconnected from patch service.", id)
+                    info!("Client {} disconnected from patch service.", id);
+                    self.clients.remove(&id);
                 },
                 ServiceMsg::ClientSaid(id, NetMsg::Patch(m)) => {
                     match m {
@@ -74,14 +114,13 @@ impl PatchService {
                         },
                         Message::Login(Some(..)) => {
                             self.sender.send((id, Message::Motd(Some(Motd { message: self.motd.clone() }))).into()).unwrap();
-                            self.sender.send((id, Message::Redirect(Some(Redirect(self.v4_servers[self.next])))).into()).unwrap();
-
-                            if self.random_data {
-                                self.next = random();
-                            } else {
-                                self.next += 1;
+                            match self.redirect_for(id) {
+                                Some(r) => self.sender.send((id, r).into()).unwrap(),
+                                None => {
+                                    warn!("No data server reachable by client {}'s address family", id);
+                                    self.sender.send(LoopMsg::DropClient(id)).unwrap();
+                                }
                             }
-                            self.next %= self.v4_servers.len();
                         },
                         u => {
                             warn!("weird patch message sent by client: {:?}", u);
@@ -0,0 +1,158 @@
+//! Quests offered at the quest counter.
+//!
+//! Quests live in `{data_path}/quests` as pairs of PRS-compressed `.bin` and
+//! `.dat` files, or as a `.qst` packing both, listed by category in
+//! `quests.toml`:
+//!
+//! ```toml
+//! [[category]]
+//! name = "Retrieval"
+//! description = "Find what was lost."
+//! episode = 1
+//!
+//! [[category.quest]]
+//! file = "q058"
+//!
+//! [[category.quest]]
+//! file = "q059"
+//! name = "Hidden Quest"
+//! ```
+//!
+//! A quest's number, name and description default to the ones in its `.bin`.
+//! The files are sent to clients as they are.
+
+use std::fs::File;
+use std::io::{self, Read, Cursor};
+use std::path::Path;
+
+use toml::{Parser, Table, Value};
+
+use psodata::map::{MapEnemy, MapObject};
+use psodata::quest::{Quest as QuestFiles, unpack_qst};
+
+#[derive(Clone, Debug)]
+pub struct Quest {
+    pub number: u32,
+    pub name: String,
+    pub description: String,
+    /// 1, 2 or 4.
+    pub episode: u8,
+    /// The compressed files, as they're sent.
+    pub bin: Vec<u8>,
+    pub dat: Vec<u8>,
+    /// Enemies and objects by area, in the order the client counts them.
+    pub enemies: Vec<(u32, MapEnemy)>,
+    pub objects: Vec<(u32, MapObject)>
+}
+
+impl Quest {
+    /// The name the client saves a file under.
+    pub fn file_name(&self, ext: &str) -> String {
+        format!("quest{}.{}", self.number, ext)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QuestCategory {
+    pub name: String,
+    pub description: String,
+    pub episode: u8,
+    /// Indices into `Quests::quests`.
+    pub quests: Vec<usize>
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Quests {
+    pub categories: Vec<QuestCategory>,
+    pub quests: Vec<Quest>
+}
+
+fn invalid<T>(msg: String) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+fn get_str(t: &Table, key: &str, what: &str) -> io::Result<String> {
+    match t.get(key).and_then(|v| v.as_str()) {
+        Some(s) => Ok(s.to_string()),
+        None => invalid(format!("{} needs a {}", what, key))
+    }
+}
+
+fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut buf));
+    Ok(buf)
+}
+
+impl Quests {
+    /// Load every quest listed in `{path}/quests.toml`. A missing list means
+    /// there are no quests.
+    pub fn load_from_dir(path: &str) -> io::Result<Quests> {
+        let mut s = String::new();
+        match File::open(format!("{}/quests.toml", path)) {
+            Ok(mut f) => { try!(f.read_to_string(&mut s)); },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Quests::default()),
+            Err(e) => return Err(e)
+        }
+        let mut parser = Parser::new(&s);
+        let t = match parser.parse() {
+            Some(t) => t,
+            None => {
+                let errors: Vec<String> = parser.errors.into_iter().map(|e| format!("{}", e)).collect();
+                return invalid(format!("Couldn't parse quests.toml: {:?}", errors))
+            }
+        };
+
+        let mut ret = Quests::default();
+        let categories = t.get("category").and_then(|v| v.as_slice()).unwrap_or(&[]);
+        for c in categories.iter().filter_map(Value::as_table) {
+            let name = try!(get_str(c, "name", "Quest category"));
+            let episode = match c.get("episode").and_then(|v| v.as_integer()) {
+                Some(e @ 1) | Some(e @ 2) | Some(e @ 4) => e as u8,
+                _ => return invalid(format!("Quest category {} needs an episode of 1, 2 or 4", name))
+            };
+            let mut category = QuestCategory {
+                name: name,
+                description: c.get("description").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                episode: episode,
+                quests: Vec::new()
+            };
+            let quests = c.get("quest").and_then(|v| v.as_slice()).unwrap_or(&[]);
+            for q in quests.iter().filter_map(Value::as_table) {
+                let file = try!(get_str(q, "file", "Quest"));
+                let qst = format!("{}/{}.qst", path, file);
+                let (bin, dat) = if Path::new(&qst).exists() {
+                    try!(unpack_qst(&mut try!(File::open(&qst))))
+                } else {
+                    (try!(read_file(&format!("{}/{}.bin", path, file))),
+                     try!(read_file(&format!("{}/{}.dat", path, file))))
+                };
+                let files = match QuestFiles::load(&mut Cursor::new(&bin[..]), &mut Cursor::new(&dat[..])) {
+                    Ok(f) => f,
+                    Err(e) => return invalid(format!("Couldn't read quest {}: {}", file, e))
+                };
+                if files.header.episode_number() != episode {
+                    warn!("Quest {} is for episode {} but is listed under episode {}", file, files.header.episode_number(), episode);
+                }
+                let number = match q.get("number").and_then(|v| v.as_integer()) {
+                    Some(n) if n >= 0 && n <= 0xFFFF => n as u32,
+                    Some(_) => return invalid(format!("Quest {} has an invalid number", file)),
+                    None => files.header.number as u32
+                };
+                category.quests.push(ret.quests.len());
+                ret.quests.push(Quest {
+                    number: number,
+                    name: q.get("name").and_then(|v| v.as_str()).map(|s| s.to_string()).unwrap_or(files.header.name),
+                    description: q.get("description").and_then(|v| v.as_str()).map(|s| s.to_string()).unwrap_or(files.header.short_description),
+                    episode: episode,
+                    bin: bin,
+                    dat: dat,
+                    enemies: files.dat.enemies,
+                    objects: files.dat.objects
+                });
+            }
+            ret.categories.push(category);
+        }
+        Ok(ret)
+    }
+}
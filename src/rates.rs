@@ -0,0 +1,219 @@
+//! EXP and drop rate adjustments, as percentages of the normal amounts.
+//!
+//! A block takes its rates from its own config unless the shipgate has rates
+//! for the whole network, which it pushes to every block. Either may read its
+//! rates from a file that is polled for changes, so rates can be raised for an
+//! event and put back without a restart.
+
+use std::fs::{self, File};
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use toml::{Parser, Table};
+
+use psoserial::Serial;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rates {
+    /// EXP for defeating an enemy.
+    pub exp: u32,
+    /// EXP for helping to defeat an enemy someone else finished, out of what
+    /// they got.
+    pub assist: u32,
+    /// The chance of each rare item.
+    pub rare: u32,
+    /// The chance of an enemy dropping anything at all.
+    pub dar: u32
+}
+
+impl Default for Rates {
+    fn default() -> Rates {
+        Rates {
+            exp: 100,
+            assist: 80,
+            rare: 100,
+            dar: 100
+        }
+    }
+}
+
+impl Serial for Rates {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.exp.serialize(dst));
+        try!(self.assist.serialize(dst));
+        try!(self.rare.serialize(dst));
+        try!(self.dar.serialize(dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(Rates {
+            exp: try!(Serial::deserialize(src)),
+            assist: try!(Serial::deserialize(src)),
+            rare: try!(Serial::deserialize(src)),
+            dar: try!(Serial::deserialize(src))
+        })
+    }
+}
+
+impl Rates {
+    /// Scale an EXP award for the last hit, or for an assist.
+    pub fn scale_exp(&self, exp: u32, last_hit: bool) -> u32 {
+        let mut exp = exp as u64 * self.exp as u64 / 100;
+        if !last_hit {
+            exp = exp * self.assist as u64 / 100;
+        }
+        exp as u32
+    }
+
+    /// Read rates from the `exp`, `assist`, `rare` and `dar` keys of a table.
+    /// Rates left out stay as they are.
+    pub fn update_from_toml_table(&mut self, t: &Table) -> Result<(), String> {
+        for (k, v) in t.iter() {
+            let rate = match v.as_integer() {
+                Some(r) if r >= 0 && r <= 0xFFFFFFFF => r as u32,
+                _ => return Err(format!("Rate {} must be a whole percentage", k))
+            };
+            match &k[..] {
+                "exp" => self.exp = rate,
+                "assist" => self.assist = rate,
+                "rare" => self.rare = rate,
+                "dar" => self.dar = rate,
+                _ => return Err(format!("Unknown rate {}", k))
+            }
+        }
+        Ok(())
+    }
+
+    /// Read rates from a TOML file laid out like a `rates` table, starting
+    /// from the defaults.
+    pub fn load(path: &str) -> Result<Rates, String> {
+        let mut s = String::new();
+        if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+            return Err(format!("Couldn't read rates from {}: {}", path, e))
+        }
+        let mut parser = Parser::new(&s);
+        let t = match parser.parse() {
+            Some(t) => t,
+            None => {
+                let errors: Vec<String> = parser.errors.into_iter().map(|e| format!("{}", e)).collect();
+                return Err(format!("Couldn't parse rates in {}: {:?}", path, errors))
+            }
+        };
+        let mut rates = Rates::default();
+        try!(rates.update_from_toml_table(&t));
+        Ok(rates)
+    }
+}
+
+/// Where a service gets its rates: a `rates` table in its config, or a
+/// `rates_file` that is checked every `rates_interval` seconds (default 60).
+/// A service can't have both.
+#[derive(Clone, Debug)]
+pub struct RatesConf {
+    pub rates: Rates,
+    pub file: Option<String>,
+    pub interval: u64
+}
+
+impl RatesConf {
+    /// Parse the rates of a service. Yields None if none are configured.
+    pub fn from_toml_table(t: &Table) -> Result<Option<RatesConf>, String> {
+        let mut rates = Rates::default();
+        let table = t.get("rates");
+        let file = t.get("rates_file").and_then(|v| v.as_str()).map(|s| s.to_string());
+        if table.is_none() && file.is_none() {
+            return Ok(None)
+        }
+        if table.is_some() && file.is_some() {
+            return Err("service has both a rates table and a rates_file".to_string())
+        }
+        let interval = match t.get("rates_interval") {
+            Some(v) => match v.as_integer() {
+                Some(i) if i >= 1 => i as u64,
+                _ => return Err("service rates_interval is not a positive integer".to_string())
+            },
+            None => 60
+        };
+        if let Some(v) = table {
+            match v.as_table() {
+                Some(rt) => try!(rates.update_from_toml_table(rt)),
+                None => return Err("service rates field is not a table".to_string())
+            }
+        }
+        if let Some(ref f) = file {
+            rates = try!(Rates::load(f));
+        }
+        Ok(Some(RatesConf {
+            rates: rates,
+            file: file,
+            interval: interval
+        }))
+    }
+
+    /// If the rates come from a file, spawn a thread that polls it and calls
+    /// `changed` with the new rates whenever it's modified. Rates that don't
+    /// parse are logged and skipped.
+    pub fn spawn_watcher<F: Fn(Rates) + Send + 'static>(&self, changed: F) {
+        let path = match self.file {
+            Some(ref f) => f.clone(),
+            None => return
+        };
+        let interval = Duration::from_secs(self.interval);
+        let mut last = modified(&path);
+        thread::spawn(move|| {
+            loop {
+                thread::sleep(interval);
+                let now = modified(&path);
+                if now == last {
+                    continue
+                }
+                last = now;
+                match Rates::load(&path) {
+                    Ok(r) => {
+                        info!("Rates changed: {:?}", r);
+                        changed(r)
+                    },
+                    Err(e) => error!("Keeping the old rates: {}", e)
+                }
+            }
+        });
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The rates in effect on a block, shared by its clients. Rates from the
+/// shipgate take the place of the block's own once any arrive.
+#[derive(Clone, Debug, Default)]
+pub struct SharedRates {
+    inner: Arc<RwLock<(Rates, Option<Rates>)>>
+}
+
+impl SharedRates {
+    pub fn new(rates: Rates) -> SharedRates {
+        SharedRates {
+            inner: Arc::new(RwLock::new((rates, None)))
+        }
+    }
+
+    pub fn get(&self) -> Rates {
+        let r = self.inner.read().unwrap();
+        r.1.unwrap_or(r.0)
+    }
+
+    /// Replace the block's own rates.
+    pub fn set_local(&self, rates: Rates) {
+        self.inner.write().unwrap().0 = rates;
+    }
+
+    /// Replace the rates given by the shipgate.
+    pub fn set_network(&self, rates: Rates) {
+        self.inner.write().unwrap().1 = Some(rates);
+    }
+}
@@ -7,5 +7,7 @@ pub struct ClientState {
     pub sec_data: BbSecurityData,
     pub team_id: u32,
     pub bb_guildcard: u32,
-    pub ships: Option<Vec<(SocketAddrV4, String)>>
+    pub ships: Option<Vec<(SocketAddrV4, String)>>,
+    /// The (account id, token) of the session registered with the shipgate.
+    pub session: Option<(u32, u32)>
 }
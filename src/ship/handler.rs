@@ -11,6 +11,7 @@ use psomsg::bb::*;
 
 use ::config::BlockConf;
 use ::shipgate::client::callbacks::SgCbMgr;
+use ::shipgate::client::session::{SessionHandler, session_connect};
 use ::shipgate::msg::{BbLoginChallenge,
     //BbLoginChallengeAck,
     BbGetAccountInfo,
@@ -72,64 +73,69 @@ impl ShipHandler {
                 }
 
                 let sec_data = sec_data.clone();
+                let account_id = a.account_id;
+                let token = sec_data.session_token();
+                session_connect(&mut h, account_id, token, false, move|mut h| {
+                    let sec_data = sec_data.clone();
+
+                    let sgm: Sgm = BbGetAccountInfo { account_id: account_id }.into();
+                    h.sg_sender.request(h.client_id, sgm, move|h, m| {
+                        if let Sgm::BbGetAccountInfoAck(_, a) = m {
+
+                            {
+                                let mut b = h.clients.borrow_mut();
+                                let ref mut c = b.get_mut(&h.client_id).unwrap();
+                                c.sec_data = sec_data.clone();
+                                c.team_id = a.team_id;
+                                c.bb_guildcard = a.guildcard_num;
+                            }
+
+                            let r = Message::BbSecurity(0, BbSecurity {
+                                err_code: 0,
+                                tag: 0x00010000,
+                                guildcard: a.guildcard_num,
+                                team_id: 0xFFFFFFFF,
+                                security_data: sec_data.clone(),
+                                caps: 0x00000101
+                            });
+                            h.sender.send((h.client_id, r).into()).unwrap();
+
+                            let r = Message::Timestamp(0, Timestamp {
+                                year: 2016,
+                                month: 1,
+                                day: 1,
+                                hour: 0,
+                                minute: 30,
+                                second: 30,
+                                msec: 0
+                            });
+                            h.sender.send((h.client_id, r).into()).unwrap();
 
-                let sgm: Sgm = BbGetAccountInfo { account_id: a.account_id }.into();
-                h.sg_sender.request(h.client_id, sgm, move|h, m| {
-                    if let Sgm::BbGetAccountInfoAck(_, a) = m {
-
-                        {
-                            let mut b = h.clients.borrow_mut();
-                            let ref mut c = b.get_mut(&h.client_id).unwrap();
-                            c.sec_data = sec_data.clone();
-                            c.team_id = a.team_id;
-                            c.bb_guildcard = a.guildcard_num;
-                        }
-
-                        let r = Message::BbSecurity(0, BbSecurity {
-                            err_code: 0,
-                            tag: 0x00010000,
-                            guildcard: a.guildcard_num,
-                            team_id: 0xFFFFFFFF,
-                            security_data: sec_data.clone(),
-                            caps: 0x00000101
-                        });
-                        h.sender.send((h.client_id, r).into()).unwrap();
-
-                        let r = Message::Timestamp(0, Timestamp {
-                            year: 2016,
-                            month: 1,
-                            day: 1,
-                            hour: 0,
-                            minute: 30,
-                            second: 30,
-                            msec: 0
-                        });
-                        h.sender.send((h.client_id, r).into()).unwrap();
-
-                        // send blocklist
-                        info!("Sending blocklist to {}", h.client_id);
-                        let mut blist = Vec::new();
-                        blist.push(ShipListItem {
-                            menu_id: 0x00040000,
-                            item_id: 0,
-                            flags: 0x0000,
-                            name: h.ship_name.clone()
-                        });
-                        let mut i = 1;
-                        for b in h.blocks.iter() {
+                            // send blocklist
+                            info!("Sending blocklist to {}", h.client_id);
+                            let mut blist = Vec::new();
                             blist.push(ShipListItem {
                                 menu_id: 0x00040000,
-                                item_id: i,
+                                item_id: 0,
                                 flags: 0x0000,
-                                name: format!("{:02}:{}", i, b.name)
+                                name: h.ship_name.clone()
                             });
-                            i += 1;
+                            let mut i = 1;
+                            for b in h.blocks.iter() {
+                                blist.push(ShipListItem {
+                                    menu_id: 0x00040000,
+                                    item_id: i,
+                                    flags: 0x0000,
+                                    name: format!("{:02}:{}", i, b.name)
+                                });
+                                i += 1;
+                            }
+                            let r = Message::BlockList(blist.len() as u32 - 1, BlockList(blist));
+                            h.sender.send((h.client_id, r).into()).unwrap();
+                            return
                         }
-                        let r = Message::BlockList(blist.len() as u32 - 1, BlockList(blist));
-                        h.sender.send((h.client_id, r).into()).unwrap();
-                        return
-                    }
-                }).unwrap();
+                    }).unwrap();
+                });
             } else {
                 warn!("Unexpected response from shipgate: {:?}", m);
                 h.sender.send(LoopMsg::DropClient(h.client_id)).unwrap();
@@ -217,3 +223,33 @@ impl ShipHandler {
         }
     }
 }
+
+impl SessionHandler for ShipHandler {
+    fn sg_sender(&mut self) -> &mut SgCbMgr<ShipHandler> {
+        &mut self.sg_sender
+    }
+
+    fn client_id(&self) -> usize {
+        self.client_id
+    }
+
+    fn set_session(&mut self, session: (u32, u32)) -> bool {
+        match self.clients.borrow_mut().get_mut(&self.client_id) {
+            Some(c) => { c.session = Some(session); true },
+            None => false
+        }
+    }
+
+    fn refuse_session(&mut self, status: u32) {
+        let r = Message::BbSecurity(0, BbSecurity {
+            err_code: status,
+            tag: 0,
+            guildcard: 0,
+            team_id: 0,
+            security_data: Default::default(),
+            caps: 0
+        });
+        self.sender.send((self.client_id, r).into()).unwrap();
+        self.sender.send(LoopMsg::DropClient(self.client_id)).unwrap();
+    }
+}
@@ -24,7 +24,8 @@ use ::services::ServiceType;
 
 use ::shipgate::client::SgSender;
 use ::shipgate::client::callbacks::SgCbMgr;
-use ::shipgate::msg::RegisterShip;
+use ::shipgate::client::session::kick_session;
+use ::shipgate::msg::{RegisterShip, Message as Sgm, BbSessionDisconnect};
 use ::config::BlockConf;
 
 pub mod handler;
@@ -112,7 +113,10 @@ impl ShipService {
                 },
                 ServiceMsg::ClientDisconnected(id) => {
                     info!("Client {} disconnected from ship {}", id, self.name);
-                    {self.clients.borrow_mut().remove(&id);}
+                    let c = {self.clients.borrow_mut().remove(&id)};
+                    if let Some((account_id, token)) = c.and_then(|c| c.session) {
+                        self.sg_sender.send(BbSessionDisconnect { account_id: account_id, token: token }).unwrap();
+                    }
                 },
                 ServiceMsg::ClientSaid(id, NetMsg::Bb(m)) => {
                     let mut h = self.make_handler(id);
@@ -124,6 +128,12 @@ impl ShipService {
                         }
                     }
                 },
+                ServiceMsg::ShipGateMsg(Sgm::BbSessionKick(_, k)) => {
+                    let b = self.clients.borrow();
+                    kick_session(&self.sender, k, b.iter().map(|(&id, c)| (id, c.session)));
+                },
+                ServiceMsg::ShipGateMsg(Sgm::SetRates(..)) => (),
+                ServiceMsg::ShipGateMsg(Sgm::BbTeamChanged(..)) => (),
                 ServiceMsg::ShipGateMsg(m) => {
                     let req = m.get_response_key();
                     debug!("Shipgate Request {}: Response received", req);
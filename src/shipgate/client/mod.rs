@@ -14,11 +14,17 @@ use psoserial::Serial;
 use std::net::TcpStream;
 
 pub mod callbacks;
+pub mod session;
 
 pub struct ShipGateClient {
     receiver: Receiver<ClientMsg>,
     stream: TcpStream,
     responders: HashMap<u32, Sender<ServiceMsg>>,
+    /// Services told about messages the shipgate sends unprompted.
+    listeners: Vec<Sender<ServiceMsg>>,
+    /// The shipgate's latest rates, for services that start listening after
+    /// they were sent.
+    rates: Option<Message>,
     password: String
 }
 
@@ -26,6 +32,8 @@ enum ClientMsg {
     /// Send a message to the shipgate
     Send(Sender<ServiceMsg>, Message),
     SendForget(Message),
+    /// Register a service to receive unsolicited shipgate messages
+    Listen(Sender<ServiceMsg>),
     // Respond to the shipgate.
     Recv(Message)
 }
@@ -62,6 +70,9 @@ impl SgSender {
 
     /// Clone this sender with the given service sender handle.
     pub fn clone_with(&self, cb_sender: Sender<ServiceMsg>) -> SgSender {
+        if let Err(e) = self.tx.send(ClientMsg::Listen(cb_sender.clone())) {
+            error!("Couldn't register with the shipgate client: {}", e);
+        }
         SgSender {
             tx: self.tx.clone(),
             cb_sender: Some(cb_sender),
@@ -93,6 +104,8 @@ impl ShipGateClient {
                 receiver: rx,
                 stream: s_c,
                 responders: Default::default(),
+                listeners: Vec::new(),
+                rates: None,
                 password: pw
             };
             c.run()
@@ -133,9 +146,26 @@ impl ShipGateClient {
                 },
                 ClientMsg::SendForget(m) => {
                     m.serialize(&mut self.stream).unwrap();
-                }
+                },
+                ClientMsg::Listen(l) => {
+                    if let Some(ref m) = self.rates {
+                        let _ = l.send(ServiceMsg::ShipGateMsg(m.clone()));
+                    }
+                    self.listeners.push(l);
+                },
                 ClientMsg::Recv(m) => {
                     let rk = m.get_response_key();
+                    if rk == 0 {
+                        match m {
+                            Message::AuthAck(..) => continue,
+                            Message::SetRates(..) => self.rates = Some(m.clone()),
+                            _ => ()
+                        }
+                        // Not a response; every service may care about it.
+                        debug!("Shipgate sent unsolicited message: {:?}", m);
+                        self.listeners.retain(|l| l.send(ServiceMsg::ShipGateMsg(m.clone())).is_ok());
+                        continue
+                    }
                     self.responders.get(&rk).map(|r| {
                         debug!("Shipgate request had response callback: {:?}", m);
                         r.send(ServiceMsg::ShipGateMsg(m))
@@ -0,0 +1,62 @@
+//! Session reporting shared by the login, ship and block services.
+
+use mio::Sender;
+
+use psomsg::bb::*;
+
+use ::loop_handler::LoopMsg;
+use ::shipgate::msg::{BbSessionConnect, BbSessionDisconnect, BbSessionKick, Message as Sgm};
+
+use super::callbacks::SgCbMgr;
+
+/// A service handler whose connections are reported to the shipgate as part
+/// of a session.
+pub trait SessionHandler: Sized + 'static {
+    fn sg_sender(&mut self) -> &mut SgCbMgr<Self>;
+
+    fn client_id(&self) -> usize;
+
+    /// Record the session on this connection's client state. Returns false if
+    /// the client has already gone.
+    fn set_session(&mut self, session: (u32, u32)) -> bool;
+
+    /// Tell the client the shipgate turned its session away, and drop it.
+    fn refuse_session(&mut self, status: u32);
+}
+
+/// Report the handler's connection to the shipgate, and carry on with `next`
+/// if the account isn't already logged in elsewhere. `fresh` is set for a new
+/// login rather than a redirect of an existing one.
+pub fn session_connect<H, F>(h: &mut H, account_id: u32, token: u32, fresh: bool, mut next: F)
+where H: SessionHandler, F: FnMut(H) + 'static {
+    let sgm = BbSessionConnect { account_id: account_id, token: token, fresh: fresh as u8 };
+    let client_id = h.client_id();
+    h.sg_sender().request(client_id, sgm, move|mut h, m| {
+        if let Sgm::BbSessionConnectAck(_, a) = m {
+            if a.status != 0 {
+                h.refuse_session(a.status);
+                return
+            }
+            if !h.set_session((account_id, token)) {
+                // They left while we were waiting.
+                h.sg_sender().send(BbSessionDisconnect { account_id: account_id, token: token }).unwrap();
+                return
+            }
+            next(h)
+        }
+    }).unwrap();
+}
+
+/// Disconnect every client belonging to a session that was replaced by a
+/// newer login. `sessions` pairs each client id with its session, if any.
+pub fn kick_session<I>(sender: &Sender<LoopMsg>, k: BbSessionKick, sessions: I)
+where I: IntoIterator<Item=(usize, Option<(u32, u32)>)> {
+    for (id, session) in sessions {
+        if session == Some((k.account_id, k.token)) {
+            info!("Client {} was logged in again elsewhere; disconnecting", id);
+            let r = Message::LargeMsg(0, LargeMsg("This account has logged in from another location.".to_string()));
+            sender.send((id, r).into()).unwrap();
+            sender.send(LoopMsg::DropClient(id)).unwrap();
+        }
+    }
+}
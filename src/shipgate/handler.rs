@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use psodb_common::Backend;
+use psodb_common::Result as DbResult;
 use psodb_common::pool::Pool;
 use psodb_common::account::Account;
 use psodb_common::account::BbAccountInfo;
@@ -7,6 +9,7 @@ use psodata::chara::BbFullCharData;
 
 use ::shipgate::msg::*;
 use super::ClientCtx;
+use super::team;
 
 /// Substructure built to handle requests without borrowing the full service.
 pub struct MsgHandler<'a> {
@@ -288,6 +291,122 @@ impl<'a> MsgHandler<'a> {
         }
     }
 
+    pub fn handle_bb_get_common_bank(&mut self, m: BbGetCommonBank) -> Message {
+        let a = match self.pool.get_connection() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return BbGetCommonBankAck {
+                    status: 1,
+                    account_id: 0,
+                    bank: Default::default()
+                }.into()
+            }
+        };
+        let handle = match a.lock() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return BbGetCommonBankAck {
+                    status: 2,
+                    account_id: 0,
+                    bank: Default::default()
+                }.into()
+            }
+        };
+        match handle.fetch_bb_common_bank(m.account_id) {
+            Ok(bank) => BbGetCommonBankAck {
+                status: 0,
+                account_id: m.account_id,
+                bank: bank
+            }.into(),
+            Err(e) => {
+                error!("Database error getting common bank: {:?}", e);
+                BbGetCommonBankAck {
+                    status: 3,
+                    account_id: 0,
+                    bank: Default::default()
+                }.into()
+            }
+        }
+    }
+
+    pub fn handle_bb_put_character_and_common_bank(&mut self, m: BbPutCharacterAndCommonBank) {
+        let a = match self.pool.get_connection() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return
+            }
+        };
+        let handle = match a.lock() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return
+            }
+        };
+        let BbPutCharacterAndCommonBank { account_id, slot, full_char, common_bank } = m;
+        if let Err(e) = handle.put_bb_character_and_common_bank(account_id, slot, full_char, &common_bank) {
+            error!("Database error putting character slot {} and common bank for account {}: {}", slot, account_id, e);
+        }
+    }
+
+    /// Delete a character. If `recover_days` is nonzero, the character can be
+    /// restored for that many days, and older deleted characters are purged.
+    pub fn handle_bb_delete_character(&mut self, m: BbDeleteCharacter, recover_days: u32) {
+        let a = match self.pool.get_connection() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return
+            }
+        };
+        let handle = match a.lock() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return
+            }
+        };
+        let BbDeleteCharacter { account_id, slot } = m;
+        if let Err(e) = handle.delete_bb_character(account_id, slot, recover_days > 0) {
+            error!("Database error deleting character {} for account {}: {:?}", slot, account_id, e);
+            return
+        }
+        if recover_days > 0 {
+            match handle.purge_deleted_bb_characters(recover_days as u64 * 86400) {
+                Ok(0) => (),
+                Ok(n) => info!("Purged {} deleted characters past their recovery period", n),
+                Err(e) => error!("Database error purging deleted characters: {:?}", e)
+            }
+        }
+    }
+
+    pub fn handle_bb_reserve_name(&mut self, m: BbReserveName) -> Message {
+        let a = match self.pool.get_connection() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return BbReserveNameAck { status: 1, taken: 0 }.into()
+            }
+        };
+        let handle = match a.lock() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return BbReserveNameAck { status: 2, taken: 0 }.into()
+            }
+        };
+        match handle.reserve_bb_character_name(&m.name, m.account_id, m.slot) {
+            Ok(reserved) => BbReserveNameAck { status: 0, taken: !reserved as u8 }.into(),
+            Err(e) => {
+                error!("Database error reserving character name: {:?}", e);
+                BbReserveNameAck { status: 3, taken: 0 }.into()
+            }
+        }
+    }
+
     pub fn handle_bb_set_login_flags(&mut self, m: BbSetLoginFlags) {
         let a = match self.pool.get_connection() {
             Ok(h) => h,
@@ -351,4 +470,145 @@ impl<'a> MsgHandler<'a> {
             }
         }
     }
+
+    pub fn handle_bb_get_guildcard_file(&mut self, m: BbGetGuildCardFile) -> Message {
+        let a = match self.pool.get_connection() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return BbGetGuildCardFileAck {
+                    status: 1,
+                    account_id: 0,
+                    file: Default::default()
+                }.into()
+            }
+        };
+        let handle = match a.lock() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return BbGetGuildCardFileAck {
+                    status: 2,
+                    account_id: 0,
+                    file: Default::default()
+                }.into()
+            }
+        };
+        match handle.fetch_bb_guildcard_file(m.account_id) {
+            Ok(file) => BbGetGuildCardFileAck {
+                status: 0,
+                account_id: m.account_id,
+                file: file
+            }.into(),
+            Err(e) => {
+                error!("Database error getting guild card file: {:?}", e);
+                BbGetGuildCardFileAck {
+                    status: 3,
+                    account_id: 0,
+                    file: Default::default()
+                }.into()
+            }
+        }
+    }
+
+    pub fn handle_bb_add_guildcard(&mut self, m: BbAddGuildCard) {
+        let a = match self.pool.get_connection() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return
+            }
+        };
+        let handle = match a.lock() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return
+            }
+        };
+        let BbAddGuildCard { account_id, card } = m;
+        let mut file = match handle.fetch_bb_guildcard_file(account_id) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("Database error getting guild card file for account {}: {:?}", account_id, e);
+                return
+            }
+        };
+        let gc = card.guildcard;
+        if !file.add(card) {
+            warn!("Guild card file for account {} is full; not adding {}", account_id, gc);
+            return
+        }
+        match handle.put_bb_guildcard_file(account_id, &file) {
+            Ok(_) => (),
+            Err(e) => {
+                error!("Database error saving guild card file for account {}: {:?}", account_id, e);
+                return
+            }
+        }
+    }
+
+    /// Run a team operation on a database connection. `fail` builds the
+    /// response for a database error status.
+    fn team_op<T, F>(&mut self, op: F, fail: fn(u32) -> T) -> T
+    where F: FnOnce(&Backend) -> DbResult<T> {
+        let a = match self.pool.get_connection() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return fail(1)
+            }
+        };
+        let handle = match a.lock() {
+            Ok(h) => h,
+            Err(e) => {
+                error!("Database error locking connection handle: {:?}", e);
+                return fail(2)
+            }
+        };
+        match op(&**handle) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Database error updating team: {:?}", e);
+                fail(3)
+            }
+        }
+    }
+
+    pub fn handle_bb_team_create(&mut self, m: BbTeamCreate) -> (Message, Vec<BbTeamChanged>) {
+        let (a, changes) = self.team_op(|db| team::create(db, m), team_fail);
+        (a.into(), changes)
+    }
+
+    pub fn handle_bb_team_add_member(&mut self, m: BbTeamAddMember) -> (Message, Vec<BbTeamChanged>) {
+        let (a, changes) = self.team_op(|db| team::add_member(db, m), team_fail);
+        (a.into(), changes)
+    }
+
+    pub fn handle_bb_team_remove_member(&mut self, m: BbTeamRemoveMember) -> (Message, Vec<BbTeamChanged>) {
+        let (a, changes) = self.team_op(|db| team::remove_member(db, m), team_fail);
+        (a.into(), changes)
+    }
+
+    pub fn handle_bb_team_change_priv(&mut self, m: BbTeamChangePriv) -> (Message, Vec<BbTeamChanged>) {
+        let (a, changes) = self.team_op(|db| team::change_priv(db, m), team_fail);
+        (a.into(), changes)
+    }
+
+    pub fn handle_bb_team_disband(&mut self, m: BbTeamDisband) -> (Message, Vec<BbTeamChanged>) {
+        let (a, changes) = self.team_op(|db| team::disband(db, m), team_fail);
+        (a.into(), changes)
+    }
+
+    pub fn handle_bb_get_team_members(&mut self, m: BbGetTeamMembers) -> Message {
+        self.team_op(|db| team::members(db, m), team_members_fail).into()
+    }
+}
+
+fn team_fail(status: u32) -> (BbTeamAck, Vec<BbTeamChanged>) {
+    (BbTeamAck { status: status, team_id: 0, privilege: 0 }, Vec::new())
+}
+
+fn team_members_fail(status: u32) -> BbGetTeamMembersAck {
+    BbGetTeamMembersAck { status: status, members: Vec::new() }
 }
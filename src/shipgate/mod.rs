@@ -19,14 +19,18 @@ use psodb_common::pool::Pool;
 use ::services::message::NetMsg;
 use ::services::{ServiceType, Service, ServiceMsg};
 use ::loop_handler::LoopMsg;
+use ::rates::{Rates, RatesConf};
 
 use ::shipgate::msg::*;
 
 pub mod msg;
 pub mod client;
+pub mod session;
 mod handler;
+mod team;
 
 use self::handler::MsgHandler;
+use self::session::{SessionTable, DuplicateLogin, ConnectResult};
 
 pub struct ShipGateService {
     receiver: Receiver<ServiceMsg>,
@@ -34,7 +38,12 @@ pub struct ShipGateService {
     password: String,
     clients: HashMap<usize, ClientCtx>,
     pool: Arc<Pool>,
-    ships: BTreeMap<usize, (SocketAddrV4, String)>
+    ships: BTreeMap<usize, (SocketAddrV4, String)>,
+    sessions: SessionTable,
+    /// Days a deleted character can be restored for. 0 deletes immediately.
+    recover_days: u32,
+    /// Network-wide rates, which every block uses in place of its own.
+    rates: Option<Rates>
 }
 
 
@@ -46,9 +55,18 @@ pub struct ClientCtx {
 }
 
 impl ShipGateService {
-    pub fn spawn(bind: &SocketAddr, sender: Sender<LoopMsg>, password: &str, pool: Arc<Pool>) -> Service {
+    pub fn spawn(bind: &SocketAddr, sender: Sender<LoopMsg>, password: &str, pool: Arc<Pool>, duplicate_login: DuplicateLogin, recover_days: u32, rates: Option<RatesConf>) -> Service {
         let (tx, rx) = channel();
 
+        // Changes to the rates file go through the service's own queue, so
+        // they're sent out like any other shipgate message.
+        if let Some(ref rc) = rates {
+            let tx = tx.clone();
+            rc.spawn_watcher(move|r| {
+                let _ = tx.send(ServiceMsg::ShipGateMsg(Message::SetRates(0, SetRates(r))));
+            });
+        }
+
         let listener = TcpListener::bind(bind).expect("Couldn't create tcplistener");
 
         let pw = password.to_owned();
@@ -59,7 +77,10 @@ impl ShipGateService {
                 password: pw,
                 clients: Default::default(),
                 pool: pool,
-                ships: Default::default()
+                ships: Default::default(),
+                sessions: SessionTable::new(duplicate_login),
+                recover_days: recover_days,
+                rates: rates.map(|rc| rc.rates)
             };
             p.run()
         });
@@ -89,6 +110,7 @@ impl ShipGateService {
                 ServiceMsg::ClientDisconnected(id) => {
                     info!("Client {} disconnected from shipgate.", id);
                     self.clients.remove(&id);
+                    self.sessions.drop_sg_client(id);
                 },
                 ServiceMsg::ClientSaid(id, NetMsg::ShipGate(m)) => {
                     let mut c = match self.clients.get_mut(&id) {
@@ -96,6 +118,8 @@ impl ShipGateService {
                         None => unreachable!()
                     };
 
+                    // Messages for every shipgate client, once the response is sent.
+                    let mut notices: Vec<Message> = Vec::new();
                     if c.authenticated {
                         let mut handler = MsgHandler::new(self.pool.clone(), c);
                         let response: Option<(u32, Message)> = match m {
@@ -136,12 +160,85 @@ impl ShipGateService {
                                 handler.handle_bb_put_character(body);
                                 None
                             },
+                            Message::BbGetCommonBank(req, body) => {
+                                Some((req, handler.handle_bb_get_common_bank(body)))
+                            },
+                            Message::BbPutCharacterAndCommonBank(_, body) => {
+                                handler.handle_bb_put_character_and_common_bank(body);
+                                None
+                            },
+                            Message::BbDeleteCharacter(_, body) => {
+                                handler.handle_bb_delete_character(body, self.recover_days);
+                                None
+                            },
+                            Message::BbReserveName(req, body) => {
+                                Some((req, handler.handle_bb_reserve_name(body)))
+                            },
+                            Message::BbTeamCreate(req, body) => {
+                                let (response, changes) = handler.handle_bb_team_create(body);
+                                notices.extend(changes.into_iter().map(|c| c.into()));
+                                Some((req, response))
+                            },
+                            Message::BbTeamAddMember(req, body) => {
+                                let (response, changes) = handler.handle_bb_team_add_member(body);
+                                notices.extend(changes.into_iter().map(|c| c.into()));
+                                Some((req, response))
+                            },
+                            Message::BbTeamRemoveMember(req, body) => {
+                                let (response, changes) = handler.handle_bb_team_remove_member(body);
+                                notices.extend(changes.into_iter().map(|c| c.into()));
+                                Some((req, response))
+                            },
+                            Message::BbTeamChangePriv(req, body) => {
+                                let (response, changes) = handler.handle_bb_team_change_priv(body);
+                                notices.extend(changes.into_iter().map(|c| c.into()));
+                                Some((req, response))
+                            },
+                            Message::BbTeamDisband(req, body) => {
+                                let (response, changes) = handler.handle_bb_team_disband(body);
+                                notices.extend(changes.into_iter().map(|c| c.into()));
+                                Some((req, response))
+                            },
+                            Message::BbGetTeamMembers(req, body) => {
+                                Some((req, handler.handle_bb_get_team_members(body)))
+                            },
                             Message::BbSetLoginFlags(_, body) => {
                                 handler.handle_bb_set_login_flags(body);
                                 None
                             },
                             Message::BbGetLoginFlags(req, body) => {
                                 Some((req, handler.handle_bb_get_login_flags(body)))
+                            },
+                            Message::BbGetGuildCardFile(req, body) => {
+                                Some((req, handler.handle_bb_get_guildcard_file(body)))
+                            },
+                            Message::BbAddGuildCard(_, body) => {
+                                handler.handle_bb_add_guildcard(body);
+                                None
+                            },
+                            Message::BbSessionConnect(req, body) => {
+                                let BbSessionConnect { account_id, token, fresh } = body;
+                                let status = match self.sessions.connect(id, account_id, token, fresh != 0) {
+                                    ConnectResult::Accepted => 0,
+                                    ConnectResult::Refused => {
+                                        info!("Account {} is already logged in; refusing the new login", account_id);
+                                        5
+                                    },
+                                    ConnectResult::Replaced(old) => {
+                                        info!("Account {} is already logged in; kicking the old session", account_id);
+                                        notices.push(BbSessionKick { account_id: account_id, token: old }.into());
+                                        0
+                                    }
+                                };
+                                Some((req, BbSessionConnectAck { status: status, account_id: account_id }.into()))
+                            },
+                            Message::BbSessionDisconnect(_, body) => {
+                                self.sessions.disconnect(id, body.account_id, body.token);
+                                None
+                            },
+                            Message::BbChecksumRejected(_, body) => {
+                                warn!("Account {} was turned away for client checksum {:08x}", body.account_id, body.checksum);
+                                None
                             }
                             _ => unimplemented!()
                         };
@@ -155,6 +252,9 @@ impl ShipGateService {
                             if version == 0 && pw == self.password {
                                 c.authenticated = true;
                                 self.sender.send((id, Message::AuthAck(res, AuthAck)).into()).unwrap();
+                                if let Some(r) = self.rates {
+                                    self.sender.send((id, Message::SetRates(0, SetRates(r))).into()).unwrap();
+                                }
                                 info!("Shipgate client {} successfully authenticated", id);
                                 continue
                             } else {
@@ -169,6 +269,24 @@ impl ShipGateService {
                             continue
                         }
                     }
+
+                    // Replaced sessions and changed team members may be on
+                    // any ship, so every shipgate client is told about them.
+                    for n in notices {
+                        for (cid, c) in self.clients.iter() {
+                            if c.authenticated {
+                                self.sender.send((*cid, n.clone()).into()).unwrap();
+                            }
+                        }
+                    }
+                },
+                ServiceMsg::ShipGateMsg(Message::SetRates(_, SetRates(r))) => {
+                    self.rates = Some(r);
+                    for (cid, c) in self.clients.iter() {
+                        if c.authenticated {
+                            self.sender.send((*cid, Message::SetRates(0, SetRates(r))).into()).unwrap();
+                        }
+                    }
                 },
                 _ => unreachable!()
             }
@@ -7,7 +7,12 @@ use std::net::{SocketAddrV4, Ipv4Addr};
 use psoserial::Serial;
 use psoserial::util::*;
 
-use psodata::chara::BbFullCharData;
+use psodata::chara::{BbFullCharData, ItemBank};
+use psodata::guildcard::{BbGuildCardFile, BbGuildCard};
+
+use psodb_common::team::BbTeamMember;
+
+use ::rates::Rates;
 
 use byteorder::{BigEndian as BE, ReadBytesExt, WriteBytesExt};
 
@@ -156,7 +161,31 @@ impl_shipgate_message_enum! {
     15 => BbPutCharacter,
     16 => BbSetLoginFlags,
     17 => BbGetLoginFlags,
-    18 => BbGetLoginFlagsAck
+    18 => BbGetLoginFlagsAck,
+    19 => BbGetGuildCardFile,
+    20 => BbGetGuildCardFileAck,
+    21 => BbAddGuildCard,
+    22 => BbSessionConnect,
+    23 => BbSessionConnectAck,
+    24 => BbSessionDisconnect,
+    25 => BbSessionKick,
+    26 => BbChecksumRejected,
+    27 => BbDeleteCharacter,
+    28 => BbReserveName,
+    29 => BbReserveNameAck,
+    30 => BbTeamCreate,
+    31 => BbTeamAck,
+    32 => BbTeamAddMember,
+    33 => BbTeamRemoveMember,
+    34 => BbTeamChangePriv,
+    35 => BbTeamDisband,
+    36 => BbGetTeamMembers,
+    37 => BbGetTeamMembersAck,
+    38 => SetRates,
+    39 => BbGetCommonBank,
+    40 => BbGetCommonBankAck,
+    41 => BbPutCharacterAndCommonBank,
+    42 => BbTeamChanged
 }
 
 #[derive(Clone, Debug)]
@@ -471,3 +500,300 @@ derive_serial_default! {
         pub flags: u32
     }
 }
+
+derive_serial_default! {
+    BbGetCommonBank {
+        pub account_id: u32
+    }
+}
+
+derive_serial_default! {
+    BbGetCommonBankAck {
+        pub status: u32,
+        pub account_id: u32,
+        pub bank: ItemBank
+    }
+}
+
+derive_serial_default! {
+    // Saves both or neither
+    BbPutCharacterAndCommonBank {
+        pub account_id: u32,
+        pub slot: u8,
+        pub full_char: BbFullCharData,
+        pub common_bank: ItemBank
+    }
+}
+
+derive_serial_default! {
+    BbGetGuildCardFile {
+        pub account_id: u32
+    }
+}
+
+derive_serial_default! {
+    BbGetGuildCardFileAck {
+        pub status: u32,
+        pub account_id: u32,
+        pub file: BbGuildCardFile
+    }
+}
+
+derive_serial_default! {
+    BbAddGuildCard {
+        pub account_id: u32,
+        pub card: BbGuildCard
+    }
+}
+
+derive_serial_default! {
+    BbSessionConnect {
+        pub account_id: u32,
+        pub token: u32,
+        // 1 if this is a new login rather than a redirect of an existing one
+        pub fresh: u8
+    }
+}
+
+derive_serial_default! {
+    BbSessionConnectAck {
+        pub status: u32,
+        pub account_id: u32
+    }
+}
+
+derive_serial_default! {
+    BbSessionDisconnect {
+        pub account_id: u32,
+        pub token: u32
+    }
+}
+
+derive_serial_default! {
+    BbSessionKick {
+        pub account_id: u32,
+        pub token: u32
+    }
+}
+
+derive_serial_default! {
+    BbChecksumRejected {
+        pub account_id: u32,
+        pub checksum: u32
+    }
+}
+
+derive_serial_default! {
+    BbDeleteCharacter {
+        pub account_id: u32,
+        pub slot: u8
+    }
+}
+
+/// Reserve a character name for the character about to be made in the given
+/// slot, unless another character has it.
+#[derive(Clone, Debug, Default)]
+pub struct BbReserveName {
+    pub account_id: u32,
+    pub slot: u8,
+    pub name: String
+}
+impl Serial for BbReserveName {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.account_id.serialize(dst));
+        try!(self.slot.serialize(dst));
+        try!(write_utf16(&self.name, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbReserveName {
+            account_id: try!(Serial::deserialize(src)),
+            slot: try!(Serial::deserialize(src)),
+            name: try!(read_utf16(src))
+        })
+    }
+}
+
+derive_serial_default! {
+    BbReserveNameAck {
+        pub status: u32,
+        pub taken: u8
+    }
+}
+
+/// Create a team, with the account as its master.
+#[derive(Clone, Debug, Default)]
+pub struct BbTeamCreate {
+    pub account_id: u32,
+    pub name: String,
+    /// Name of the account's character, for the member list.
+    pub char_name: String
+}
+impl Serial for BbTeamCreate {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.account_id.serialize(dst));
+        try!(write_utf16(&self.name, dst));
+        try!(write_utf16(&self.char_name, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbTeamCreate {
+            account_id: try!(Serial::deserialize(src)),
+            name: try!(read_utf16(src)),
+            char_name: try!(read_utf16(src))
+        })
+    }
+}
+
+/// Team name is already used. Statuses 1 to 3 are database errors as usual.
+pub const TEAM_NAME_TAKEN: u32 = 4;
+/// The account's rank doesn't allow the action.
+pub const TEAM_NOT_ALLOWED: u32 = 5;
+/// The account (or the one being added) is already in a team.
+pub const TEAM_ALREADY_IN_TEAM: u32 = 6;
+/// The account (or the one being acted on) isn't in the team.
+pub const TEAM_NOT_IN_TEAM: u32 = 7;
+
+derive_serial_default! {
+    BbTeamAck {
+        pub status: u32,
+        pub team_id: u32,
+        // rank of the account acted on, after the action
+        pub privilege: u32
+    }
+}
+
+/// Add an account to the team of `account_id`.
+#[derive(Clone, Debug, Default)]
+pub struct BbTeamAddMember {
+    pub account_id: u32,
+    pub target_account_id: u32,
+    pub target_name: String
+}
+impl Serial for BbTeamAddMember {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.account_id.serialize(dst));
+        try!(self.target_account_id.serialize(dst));
+        try!(write_utf16(&self.target_name, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbTeamAddMember {
+            account_id: try!(Serial::deserialize(src)),
+            target_account_id: try!(Serial::deserialize(src)),
+            target_name: try!(read_utf16(src))
+        })
+    }
+}
+
+derive_serial_default! {
+    BbTeamRemoveMember {
+        pub account_id: u32,
+        pub target_guildcard: u32
+    }
+}
+
+derive_serial_default! {
+    BbTeamChangePriv {
+        pub account_id: u32,
+        pub target_guildcard: u32,
+        pub privilege: u32
+    }
+}
+
+derive_serial_default! {
+    BbTeamDisband {
+        pub account_id: u32
+    }
+}
+
+derive_serial_default! {
+    BbGetTeamMembers {
+        pub account_id: u32
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BbGetTeamMembersAck {
+    pub status: u32,
+    pub members: Vec<BbTeamMember>
+}
+impl Serial for BbGetTeamMembersAck {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.status.serialize(dst));
+        try!((self.members.len() as u32).serialize(dst));
+        for m in self.members.iter() {
+            try!(m.account_id.serialize(dst));
+            try!(m.team_id.serialize(dst));
+            try!(m.guildcard.serialize(dst));
+            try!(m.privilege.serialize(dst));
+            try!(write_utf16(&m.name, dst));
+        }
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        let status = try!(Serial::deserialize(src));
+        let len = try!(u32::deserialize(src));
+        let mut members = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            members.push(BbTeamMember {
+                account_id: try!(Serial::deserialize(src)),
+                team_id: try!(Serial::deserialize(src)),
+                guildcard: try!(Serial::deserialize(src)),
+                privilege: try!(Serial::deserialize(src)),
+                name: try!(read_utf16(src))
+            });
+        }
+        Ok(BbGetTeamMembersAck {
+            status: status,
+            members: members
+        })
+    }
+}
+
+/// Sent to every shipgate client when an account's team or rank changes, so
+/// its character is updated wherever it's logged in. A team ID of 0 means
+/// the account is no longer in a team.
+#[derive(Clone, Debug, Default)]
+pub struct BbTeamChanged {
+    pub account_id: u32,
+    pub team_id: u32,
+    pub privilege: u32,
+    pub team_name: String
+}
+impl Serial for BbTeamChanged {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        try!(self.account_id.serialize(dst));
+        try!(self.team_id.serialize(dst));
+        try!(self.privilege.serialize(dst));
+        try!(write_utf16(&self.team_name, dst));
+        Ok(())
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(BbTeamChanged {
+            account_id: try!(Serial::deserialize(src)),
+            team_id: try!(Serial::deserialize(src)),
+            privilege: try!(Serial::deserialize(src)),
+            team_name: try!(read_utf16(src))
+        })
+    }
+}
+
+/// The network-wide rates, sent by the shipgate to every client when it
+/// authenticates and again whenever the rates change.
+#[derive(Clone, Debug, Default)]
+pub struct SetRates(pub Rates);
+impl Serial for SetRates {
+    fn serialize(&self, dst: &mut Write) -> io::Result<()> {
+        self.0.serialize(dst)
+    }
+
+    fn deserialize(src: &mut Read) -> io::Result<Self> {
+        Ok(SetRates(try!(Serial::deserialize(src))))
+    }
+}
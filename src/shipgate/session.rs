@@ -0,0 +1,124 @@
+//! Tracks which Blue Burst accounts are logged in anywhere on the network.
+//!
+//! A session is identified by a random token, generated by the login service
+//! on the first login pass and carried by the client in its security data
+//! across every redirect after that. Each login, ship and block connection
+//! reports itself to the shipgate with that token; the session ends when the
+//! last of its connections closes.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// What to do when an account logs in while it already has a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicateLogin {
+    /// Turn the new login away.
+    Refuse,
+    /// Disconnect the existing session and let the new login through.
+    Kick
+}
+
+impl FromStr for DuplicateLogin {
+    type Err = String;
+    fn from_str(s: &str) -> Result<DuplicateLogin, Self::Err> {
+        match s {
+            "refuse" => Ok(DuplicateLogin::Refuse),
+            "kick" => Ok(DuplicateLogin::Kick),
+            _ => Err(format!("Unknown duplicate_login policy {}", s))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectResult {
+    Accepted,
+    Refused,
+    /// The session with the given token was replaced and must be kicked.
+    Replaced(u32)
+}
+
+#[derive(Debug)]
+struct Session {
+    token: u32,
+    /// One entry per open connection, naming the shipgate client it is on.
+    conns: Vec<usize>
+}
+
+#[derive(Debug)]
+pub struct SessionTable {
+    policy: DuplicateLogin,
+    sessions: HashMap<u32, Session>
+}
+
+impl SessionTable {
+    pub fn new(policy: DuplicateLogin) -> SessionTable {
+        SessionTable {
+            policy: policy,
+            sessions: HashMap::new()
+        }
+    }
+
+    /// Record a client connection for `account_id`, reported by shipgate
+    /// client `sg_client`. `fresh` is set for a new login, as opposed to a
+    /// redirect within an existing session.
+    pub fn connect(&mut self, sg_client: usize, account_id: u32, token: u32, fresh: bool) -> ConnectResult {
+        let policy = self.policy;
+        match self.sessions.get_mut(&account_id) {
+            Some(s) => {
+                if s.token == token {
+                    s.conns.push(sg_client);
+                    return ConnectResult::Accepted
+                }
+                // A redirect carrying an old token belongs to a session that
+                // was already replaced.
+                if !fresh || policy == DuplicateLogin::Refuse {
+                    return ConnectResult::Refused
+                }
+                let old = s.token;
+                s.token = token;
+                s.conns = vec![sg_client];
+                return ConnectResult::Replaced(old)
+            },
+            None => ()
+        }
+        self.sessions.insert(account_id, Session {
+            token: token,
+            conns: vec![sg_client]
+        });
+        ConnectResult::Accepted
+    }
+
+    /// Record a client connection closing. Disconnects for a session that was
+    /// replaced are ignored.
+    pub fn disconnect(&mut self, sg_client: usize, account_id: u32, token: u32) {
+        let empty = match self.sessions.get_mut(&account_id) {
+            Some(s) => {
+                if s.token != token {
+                    return
+                }
+                if let Some(i) = s.conns.iter().position(|&c| c == sg_client) {
+                    s.conns.remove(i);
+                }
+                s.conns.is_empty()
+            },
+            None => return
+        };
+        if empty {
+            self.sessions.remove(&account_id);
+        }
+    }
+
+    /// Forget every connection reported by a shipgate client that went away.
+    pub fn drop_sg_client(&mut self, sg_client: usize) {
+        let mut empty = Vec::new();
+        for (&account_id, s) in self.sessions.iter_mut() {
+            s.conns.retain(|&c| c != sg_client);
+            if s.conns.is_empty() {
+                empty.push(account_id);
+            }
+        }
+        for account_id in empty {
+            self.sessions.remove(&account_id);
+        }
+    }
+}
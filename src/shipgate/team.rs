@@ -0,0 +1,362 @@
+//! Team membership rules. Masters may do anything to their team; leaders may
+//! add members and remove anyone ranked below them; anyone but the master may
+//! leave. The master has to hand the team over or disband it to leave.
+//!
+//! Each successful action also yields a `BbTeamChanged` for every account
+//! whose team or rank it changed, for the shipgate to pass on to every ship.
+
+use psodb_common::Backend;
+use psodb_common::Result;
+use psodb_common::team::*;
+
+use ::shipgate::msg::*;
+
+/// The response to a team action, and the accounts it changed.
+pub type TeamResult = Result<(BbTeamAck, Vec<BbTeamChanged>)>;
+
+fn ack(status: u32, team_id: u32, privilege: u32) -> BbTeamAck {
+    BbTeamAck {
+        status: status,
+        team_id: team_id,
+        privilege: privilege
+    }
+}
+
+/// Turn an action down without changing anything.
+fn refuse(status: u32, team_id: u32, privilege: u32) -> TeamResult {
+    Ok((ack(status, team_id, privilege), Vec::new()))
+}
+
+fn changed(account_id: u32, team_id: u32, privilege: u32, team_name: &str) -> BbTeamChanged {
+    BbTeamChanged {
+        account_id: account_id,
+        team_id: team_id,
+        privilege: privilege,
+        team_name: team_name.to_string()
+    }
+}
+
+fn team_name(db: &Backend, team_id: u32) -> Result<String> {
+    Ok(try!(db.fetch_bb_team(team_id)).map(|t| t.name).unwrap_or_default())
+}
+
+pub fn create(db: &Backend, m: BbTeamCreate) -> TeamResult {
+    if try!(db.fetch_bb_team_member(m.account_id)).is_some() {
+        return refuse(TEAM_ALREADY_IN_TEAM, 0, 0)
+    }
+    let team_id = match try!(db.create_bb_team(&m.name)) {
+        Some(id) => id,
+        None => return refuse(TEAM_NAME_TAKEN, 0, 0)
+    };
+    try!(db.put_bb_team_member(&BbTeamMember {
+        account_id: m.account_id,
+        team_id: team_id,
+        guildcard: 0,
+        privilege: PRIV_MASTER,
+        name: m.char_name
+    }));
+    info!("Account {} created team {} ({})", m.account_id, team_id, m.name);
+    Ok((ack(0, team_id, PRIV_MASTER), vec![changed(m.account_id, team_id, PRIV_MASTER, &m.name)]))
+}
+
+pub fn add_member(db: &Backend, m: BbTeamAddMember) -> TeamResult {
+    let me = match try!(db.fetch_bb_team_member(m.account_id)) {
+        Some(me) => me,
+        None => return refuse(TEAM_NOT_IN_TEAM, 0, 0)
+    };
+    if me.privilege < PRIV_LEADER {
+        return refuse(TEAM_NOT_ALLOWED, me.team_id, 0)
+    }
+    if try!(db.fetch_bb_team_member(m.target_account_id)).is_some() {
+        return refuse(TEAM_ALREADY_IN_TEAM, me.team_id, 0)
+    }
+    try!(db.put_bb_team_member(&BbTeamMember {
+        account_id: m.target_account_id,
+        team_id: me.team_id,
+        guildcard: 0,
+        privilege: PRIV_MEMBER,
+        name: m.target_name
+    }));
+    let name = try!(team_name(db, me.team_id));
+    Ok((ack(0, me.team_id, PRIV_MEMBER), vec![changed(m.target_account_id, me.team_id, PRIV_MEMBER, &name)]))
+}
+
+pub fn remove_member(db: &Backend, m: BbTeamRemoveMember) -> TeamResult {
+    let me = match try!(db.fetch_bb_team_member(m.account_id)) {
+        Some(me) => me,
+        None => return refuse(TEAM_NOT_IN_TEAM, 0, 0)
+    };
+    let members = try!(db.fetch_bb_team_members(me.team_id));
+    let target = match members.into_iter().find(|t| t.guildcard == m.target_guildcard) {
+        Some(t) => t,
+        None => return refuse(TEAM_NOT_IN_TEAM, me.team_id, 0)
+    };
+    if target.account_id == me.account_id {
+        if me.privilege == PRIV_MASTER {
+            return refuse(TEAM_NOT_ALLOWED, me.team_id, me.privilege)
+        }
+    } else if me.privilege < PRIV_LEADER || target.privilege >= me.privilege {
+        return refuse(TEAM_NOT_ALLOWED, me.team_id, target.privilege)
+    }
+    try!(db.remove_bb_team_member(target.account_id));
+    Ok((ack(0, me.team_id, 0), vec![changed(target.account_id, 0, 0, "")]))
+}
+
+pub fn change_priv(db: &Backend, m: BbTeamChangePriv) -> TeamResult {
+    let mut me = match try!(db.fetch_bb_team_member(m.account_id)) {
+        Some(me) => me,
+        None => return refuse(TEAM_NOT_IN_TEAM, 0, 0)
+    };
+    if me.privilege != PRIV_MASTER {
+        return refuse(TEAM_NOT_ALLOWED, me.team_id, 0)
+    }
+    match m.privilege {
+        PRIV_MEMBER | PRIV_LEADER | PRIV_MASTER => (),
+        _ => return refuse(TEAM_NOT_ALLOWED, me.team_id, 0)
+    }
+    let members = try!(db.fetch_bb_team_members(me.team_id));
+    let mut target = match members.into_iter().find(|t| t.guildcard == m.target_guildcard) {
+        Some(t) => t,
+        None => return refuse(TEAM_NOT_IN_TEAM, me.team_id, 0)
+    };
+    if target.account_id == me.account_id {
+        return refuse(TEAM_NOT_ALLOWED, me.team_id, me.privilege)
+    }
+    target.privilege = m.privilege;
+    try!(db.put_bb_team_member(&target));
+    let name = try!(team_name(db, me.team_id));
+    let mut changes = vec![changed(target.account_id, me.team_id, target.privilege, &name)];
+    // Handing over the team steps the old master down to leader.
+    if m.privilege == PRIV_MASTER {
+        me.privilege = PRIV_LEADER;
+        try!(db.put_bb_team_member(&me));
+        changes.push(changed(me.account_id, me.team_id, me.privilege, &name));
+        info!("Account {} handed team {} over to account {}", me.account_id, me.team_id, target.account_id);
+    }
+    Ok((ack(0, me.team_id, target.privilege), changes))
+}
+
+pub fn disband(db: &Backend, m: BbTeamDisband) -> TeamResult {
+    let me = match try!(db.fetch_bb_team_member(m.account_id)) {
+        Some(me) => me,
+        None => return refuse(TEAM_NOT_IN_TEAM, 0, 0)
+    };
+    if me.privilege != PRIV_MASTER {
+        return refuse(TEAM_NOT_ALLOWED, me.team_id, me.privilege)
+    }
+    let members = try!(db.fetch_bb_team_members(me.team_id));
+    try!(db.delete_bb_team(me.team_id));
+    info!("Account {} disbanded team {}", m.account_id, me.team_id);
+    Ok((ack(0, me.team_id, 0), members.iter().map(|t| changed(t.account_id, 0, 0, "")).collect()))
+}
+
+pub fn members(db: &Backend, m: BbGetTeamMembers) -> Result<BbGetTeamMembersAck> {
+    let members = match try!(db.fetch_bb_team_member(m.account_id)) {
+        Some(me) => try!(db.fetch_bb_team_members(me.team_id)),
+        None => Vec::new()
+    };
+    Ok(BbGetTeamMembersAck {
+        status: 0,
+        members: members
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use psodb_common::account::{Account, BbAccountInfo};
+    use psodb_sqlite::Sqlite;
+
+    use super::*;
+
+    /// Make an account with the given guild card number, yielding its ID.
+    fn account(db: &Sqlite, username: &str, guildcard: u32) -> u32 {
+        let mut a = Account::new(username, "testpassword", "pourthesalt");
+        db.put_account(&mut a).unwrap();
+        let mut info = BbAccountInfo::new();
+        info.account_id = a.id.unwrap();
+        info.guildcard_num = guildcard;
+        db.put_bb_account_info(&info).unwrap();
+        a.id.unwrap()
+    }
+
+    fn create_team(db: &Sqlite, account_id: u32, name: &str) -> BbTeamAck {
+        create(db, BbTeamCreate {
+            account_id: account_id,
+            name: name.to_string(),
+            char_name: "Master".to_string()
+        }).unwrap().0
+    }
+
+    fn add(db: &Sqlite, account_id: u32, target: u32) -> BbTeamAck {
+        add_member(db, BbTeamAddMember {
+            account_id: account_id,
+            target_account_id: target,
+            target_name: "Member".to_string()
+        }).unwrap().0
+    }
+
+    fn remove(db: &Sqlite, account_id: u32, guildcard: u32) -> BbTeamAck {
+        remove_member(db, BbTeamRemoveMember {
+            account_id: account_id,
+            target_guildcard: guildcard
+        }).unwrap().0
+    }
+
+    fn promote(db: &Sqlite, account_id: u32, guildcard: u32, privilege: u32) -> BbTeamAck {
+        change_priv(db, BbTeamChangePriv {
+            account_id: account_id,
+            target_guildcard: guildcard,
+            privilege: privilege
+        }).unwrap().0
+    }
+
+    fn rank(db: &Sqlite, account_id: u32) -> Option<u32> {
+        db.fetch_bb_team_member(account_id).unwrap().map(|m| m.privilege)
+    }
+
+    /// A team with a master, a leader and two members, with guild cards
+    /// 1 through 4.
+    fn team(db: &Sqlite) -> (u32, u32, u32, u32) {
+        let master = account(db, "master", 1);
+        let leader = account(db, "leader", 2);
+        let member = account(db, "member", 3);
+        let other = account(db, "other", 4);
+        assert_eq!(create_team(db, master, "Hunters").status, 0);
+        for &a in [leader, member, other].iter() {
+            assert_eq!(add(db, master, a).status, 0);
+        }
+        assert_eq!(promote(db, master, 2, PRIV_LEADER).status, 0);
+        (master, leader, member, other)
+    }
+
+    #[test]
+    fn test_create() {
+        let db = Sqlite::new(":memory:", true).unwrap();
+        let a = account(&db, "a", 1);
+        let b = account(&db, "b", 2);
+
+        let ack = create_team(&db, a, "Hunters");
+        assert_eq!(ack.status, 0);
+        assert_eq!(ack.privilege, PRIV_MASTER);
+        assert_eq!(rank(&db, a), Some(PRIV_MASTER));
+
+        assert_eq!(create_team(&db, a, "Rangers").status, TEAM_ALREADY_IN_TEAM);
+        assert_eq!(create_team(&db, b, "Hunters").status, TEAM_NAME_TAKEN);
+        assert_eq!(rank(&db, b), None);
+    }
+
+    #[test]
+    fn test_add_member() {
+        let db = Sqlite::new(":memory:", true).unwrap();
+        let (master, leader, member, other) = team(&db);
+        let outsider = account(&db, "outsider", 5);
+        let newcomer = account(&db, "newcomer", 6);
+
+        assert_eq!(add(&db, outsider, newcomer).status, TEAM_NOT_IN_TEAM);
+        assert_eq!(add(&db, member, newcomer).status, TEAM_NOT_ALLOWED);
+        assert_eq!(add(&db, master, other).status, TEAM_ALREADY_IN_TEAM);
+
+        let ack = add(&db, leader, newcomer);
+        assert_eq!(ack.status, 0);
+        assert_eq!(rank(&db, newcomer), Some(PRIV_MEMBER));
+        assert_eq!(db.fetch_bb_team_member(newcomer).unwrap().unwrap().team_id, ack.team_id);
+    }
+
+    #[test]
+    fn test_remove_member() {
+        let db = Sqlite::new(":memory:", true).unwrap();
+        let (master, leader, member, other) = team(&db);
+
+        // Members can't remove anyone but themselves.
+        assert_eq!(remove(&db, member, 4).status, TEAM_NOT_ALLOWED);
+        assert_eq!(remove(&db, member, 3).status, 0);
+        assert_eq!(rank(&db, member), None);
+
+        // Leaders can only remove those ranked below them.
+        assert_eq!(remove(&db, leader, 1).status, TEAM_NOT_ALLOWED);
+        assert_eq!(remove(&db, leader, 9).status, TEAM_NOT_IN_TEAM);
+        assert_eq!(remove(&db, leader, 4).status, 0);
+        assert_eq!(rank(&db, other), None);
+
+        // The master can't leave, but can remove anyone else.
+        assert_eq!(remove(&db, master, 1).status, TEAM_NOT_ALLOWED);
+        assert_eq!(remove(&db, master, 2).status, 0);
+        assert_eq!(rank(&db, leader), None);
+        assert_eq!(rank(&db, master), Some(PRIV_MASTER));
+    }
+
+    #[test]
+    fn test_change_priv() {
+        let db = Sqlite::new(":memory:", true).unwrap();
+        let (master, leader, member, _) = team(&db);
+
+        // Only the master hands out ranks, and only known ones.
+        assert_eq!(promote(&db, leader, 3, PRIV_LEADER).status, TEAM_NOT_ALLOWED);
+        assert_eq!(promote(&db, master, 3, 0x10).status, TEAM_NOT_ALLOWED);
+        assert_eq!(promote(&db, master, 1, PRIV_LEADER).status, TEAM_NOT_ALLOWED);
+        assert_eq!(rank(&db, member), Some(PRIV_MEMBER));
+
+        assert_eq!(promote(&db, master, 2, PRIV_MEMBER).status, 0);
+        assert_eq!(rank(&db, leader), Some(PRIV_MEMBER));
+
+        // Handing over the team steps the master down to leader.
+        let ack = promote(&db, master, 3, PRIV_MASTER);
+        assert_eq!(ack.status, 0);
+        assert_eq!(ack.privilege, PRIV_MASTER);
+        assert_eq!(rank(&db, member), Some(PRIV_MASTER));
+        assert_eq!(rank(&db, master), Some(PRIV_LEADER));
+    }
+
+    #[test]
+    fn test_disband() {
+        let db = Sqlite::new(":memory:", true).unwrap();
+        let (master, leader, member, other) = team(&db);
+
+        assert_eq!(disband(&db, BbTeamDisband { account_id: leader }).unwrap().0.status, TEAM_NOT_ALLOWED);
+        assert_eq!(rank(&db, leader), Some(PRIV_LEADER));
+
+        let (ack, changes) = disband(&db, BbTeamDisband { account_id: master }).unwrap();
+        assert_eq!(ack.status, 0);
+        assert!(db.fetch_bb_team(ack.team_id).unwrap().is_none());
+        assert_eq!(rank(&db, master), None);
+        assert_eq!(rank(&db, member), None);
+        assert_eq!(disband(&db, BbTeamDisband { account_id: master }).unwrap().0.status, TEAM_NOT_IN_TEAM);
+
+        // Every member is told they've left.
+        let mut left: Vec<u32> = changes.iter().map(|c| c.account_id).collect();
+        left.sort();
+        let mut all = vec![master, leader, member, other];
+        all.sort();
+        assert_eq!(left, all);
+        assert!(changes.iter().all(|c| c.team_id == 0 && c.privilege == 0));
+    }
+
+    #[test]
+    fn test_changes() {
+        let db = Sqlite::new(":memory:", true).unwrap();
+        let (master, leader, member, other) = team(&db);
+
+        // Refused actions change nothing.
+        let (_, changes) = remove_member(&db, BbTeamRemoveMember { account_id: member, target_guildcard: 1 }).unwrap();
+        assert!(changes.is_empty());
+
+        let (_, changes) = remove_member(&db, BbTeamRemoveMember { account_id: leader, target_guildcard: 4 }).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].account_id, changes[0].team_id), (other, 0));
+
+        let (ack, changes) = change_priv(&db, BbTeamChangePriv { account_id: master, target_guildcard: 3, privilege: PRIV_MASTER }).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].account_id, changes[0].team_id, changes[0].privilege), (member, ack.team_id, PRIV_MASTER));
+        assert_eq!((changes[1].account_id, changes[1].privilege), (master, PRIV_LEADER));
+        assert!(changes.iter().all(|c| c.team_name == "Hunters"));
+
+        let (ack, changes) = add_member(&db, BbTeamAddMember {
+            account_id: leader,
+            target_account_id: other,
+            target_name: "Member".to_string()
+        }).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].account_id, changes[0].team_id, changes[0].privilege), (other, ack.team_id, PRIV_MEMBER));
+        assert_eq!(changes[0].team_name, "Hunters");
+    }
+}